@@ -1,5 +1,34 @@
-use calamp_rs::Message;
+use calamp_rs::{Message, MessageRef};
 use criterion::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts every allocation so the zero-copy parse can be checked.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn parse_ref(data: &[u8]) -> u32 {
+    let msg = MessageRef::parse(data).unwrap();
+    let report = msg.event_report();
+    report
+        .accumulators()
+        .fold(report.update_time().secs(), u32::wrapping_add)
+}
 
 fn bench(c: &mut Criterion) {
     let data: [u8; 117] = [
@@ -14,7 +43,20 @@ fn bench(c: &mut Criterion) {
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40,
         0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
     ];
-    c.bench_function("Message::parse", |b| b.iter(|| Message::parse(&data)));
+    c.bench_function("Message::parse", |b| {
+        b.iter(|| Message::parse(&data).unwrap())
+    });
+
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+    black_box(parse_ref(black_box(&data)));
+    assert_eq!(
+        ALLOCATIONS.load(Ordering::SeqCst),
+        before,
+        "MessageRef::parse must not allocate"
+    );
+    c.bench_function("MessageRef::parse", |b| {
+        b.iter(|| parse_ref(black_box(&data)))
+    });
 }
 
 criterion_group!(benches, bench);
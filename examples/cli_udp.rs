@@ -1,5 +1,7 @@
+use calamp_rs::message_header::MessageHeader;
+use calamp_rs::messages::ack_nak::AckNak;
+use calamp_rs::options_header::OptionsHeader;
 use std::net::UdpSocket;
-use std::str;
 
 fn main() -> std::io::Result<()> {
     let data: [u8; 117] = [
@@ -19,11 +21,14 @@ fn main() -> std::io::Result<()> {
     socket.send(&data)?;
 
     let mut buffer = [0u8; 1500];
-    socket.recv_from(&mut buffer)?;
+    let (amt, _) = socket.recv_from(&mut buffer)?;
 
+    let (i, _) = OptionsHeader::parse(&buffer[..amt]).expect("options header");
+    let (i, header) = MessageHeader::parse(i).expect("message header");
+    let (_, ack) = AckNak::parse(i).expect("ack/nak");
     println!(
-        "recv: {}",
-        str::from_utf8(&buffer).expect("Could not write buffer as string")
+        "recv: {} for seq {}, ack {}",
+        header.message_type, header.sequence_number, ack.ack
     );
 
     Ok(())
@@ -14,7 +14,7 @@ fn main() {
         0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
     ];
 
-    let msg = Message::parse(&data);
+    let msg = Message::parse(&data).unwrap();
 
     println!("Message Type: {}", msg.message_header.message_type);
 }
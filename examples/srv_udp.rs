@@ -1,17 +1,27 @@
-use calamp_rs::Message;
-use std::net::UdpSocket;
+use calamp_rs::server::blocking::UdpServer;
+use calamp_rs::server::Handler;
+use calamp_rs::{Error, Message};
+use std::net::SocketAddr;
 
-fn main() -> std::io::Result<()> {
-    let socket = UdpSocket::bind("127.0.0.1:34254")?;
+struct Printer;
 
-    loop {
-        let mut buf = [0u8; 1500];
-        let (amt, src) = socket.recv_from(&mut buf)?;
-        println!("Receive: {} bytes from: {:?}", amt, &src);
-        let msg = Message::parse(&buf);
+impl Handler for Printer {
+    fn on_message(&self, src: SocketAddr, msg: Message) {
+        println!(
+            "{}: {} seq {}",
+            src,
+            msg.message_header.message_type,
+            msg.message_header.sequence_number
+        );
+    }
 
-        let msg_type: String = format!("{}", msg.message_header.message_type);
-        let buf = msg_type.as_bytes();
-        socket.send_to(buf, &src)?;
+    fn on_error(&self, src: SocketAddr, err: Error) {
+        println!("{}: invalid message: {}", src, err);
     }
 }
+
+fn main() -> std::io::Result<()> {
+    let server = UdpServer::bind("127.0.0.1:34254")?;
+    println!("Listening on {}", server.local_addr()?);
+    server.run(Printer)
+}
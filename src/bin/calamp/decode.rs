@@ -0,0 +1,248 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! `calamp decode`.
+//!
+//! Hex and base64 input hold one message per line, blank lines and lines
+//! starting with `#` are skipped. Raw input is cut into messages by their
+//! length, so a file may hold several messages back to back.
+//!
+//! With `--dissect` each message is printed as an annotated hex dump, in
+//! colour on a terminal unless `NO_COLOR` is set.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use calamp_rs::dissect;
+use calamp_rs::framer::frame_length;
+use calamp_rs::Message;
+use clap::ValueEnum;
+
+use crate::output::{self, Format, Printed};
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Files to decode, standard input when none is given or for `-`.
+    files: Vec<PathBuf>,
+
+    /// Encoding of the input.
+    #[arg(short, long, value_enum, default_value_t = Input::Hex)]
+    input: Input,
+
+    /// Output format.
+    #[arg(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Print each message as an annotated hex dump, field by field, even
+    /// when it does not parse.
+    #[arg(short, long)]
+    dissect: bool,
+}
+
+/// How messages are printed.
+#[derive(Debug, Clone, Copy)]
+enum Print {
+    Decoded(Format),
+    Dissected { color: bool },
+}
+
+/// Encoding of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Input {
+    /// Hex digits, spaces and a `0x` prefix are allowed.
+    Hex,
+
+    /// Standard base64.
+    Base64,
+
+    /// The bytes as sent on the wire.
+    Raw,
+}
+
+/// Where a message was read from, for error reports.
+#[derive(Debug)]
+enum Location<'a> {
+    Line(&'a str, usize),
+    Offset(&'a str, usize),
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Line(name, line) => write!(f, "{}:{}", name, line),
+            Location::Offset(name, offset) => {
+                write!(f, "{}@{:#x}", name, offset)
+            }
+        }
+    }
+}
+
+pub fn run(args: Args) -> crate::Result {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut failed = 0;
+    let print = if args.dissect {
+        Print::Dissected {
+            color: stdout.is_terminal() && env::var_os("NO_COLOR").is_none(),
+        }
+    } else {
+        Print::Decoded(args.format)
+    };
+
+    let stdin = [PathBuf::from("-")];
+    let files = if args.files.is_empty() {
+        &stdin[..]
+    } else {
+        &args.files[..]
+    };
+    for path in files {
+        let name = path.display().to_string();
+        let data = if name == "-" {
+            let mut data = Vec::new();
+            io::stdin().read_to_end(&mut data)?;
+            data
+        } else {
+            fs::read(path).map_err(|e| format!("{}: {}", name, e))?
+        };
+
+        failed += match args.input {
+            Input::Raw => decode_raw(&mut out, &name, &data, print)?,
+            input => decode_lines(&mut out, &name, &data, input, print)?,
+        };
+    }
+
+    out.flush()?;
+    Ok(if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+/// Decode one message per line, returning the number of failures.
+fn decode_lines<W: Write>(
+    out: &mut W, name: &str, data: &[u8], input: Input, print: Print,
+) -> io::Result<usize> {
+    let text = String::from_utf8_lossy(data);
+    let mut failed = 0;
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let location = Location::Line(name, n + 1);
+
+        let bytes = match input {
+            Input::Base64 => STANDARD.decode(line).map_err(|e| e.to_string()),
+            _ => parse_hex(line),
+        };
+        let ok = match bytes {
+            Ok(bytes) => decode_one(out, &location, &bytes, print)?,
+            Err(e) => {
+                eprintln!("{}: invalid {:?} input: {}", location, input, e);
+                false
+            }
+        };
+        failed += usize::from(!ok);
+    }
+    Ok(failed)
+}
+
+/// Decode the messages following each other in `data`, returning the
+/// number of failures. Decoding stops at the first message whose length
+/// cannot be known.
+fn decode_raw<W: Write>(
+    out: &mut W, name: &str, data: &[u8], print: Print,
+) -> io::Result<usize> {
+    let mut offset = 0;
+    let mut failed = 0;
+
+    while offset < data.len() {
+        let location = Location::Offset(name, offset);
+        let len = match frame_length(&data[offset..]) {
+            Ok(len) => len,
+            // Let the parser report the error.
+            Err(_) => data.len() - offset,
+        };
+        let ok =
+            decode_one(out, &location, &data[offset..offset + len], print)?;
+        failed += usize::from(!ok);
+        offset += len;
+    }
+    Ok(failed)
+}
+
+/// Decode and print one message, reporting the error on stderr.
+fn decode_one<W: Write>(
+    out: &mut W, location: &Location<'_>, bytes: &[u8], print: Print,
+) -> io::Result<bool> {
+    let res = Message::parse(bytes);
+    match (&res, print) {
+        (Ok(msg), Print::Decoded(format)) => {
+            output::write(out, &Printed::new(msg, format), format)?
+        }
+        (_, Print::Dissected { color }) => {
+            let spans = dissect::dissect(bytes);
+            writeln!(out, "{}", dissect::render(&spans, color))?;
+        }
+        (Err(_), _) => {}
+    }
+    match res {
+        Ok(_) => Ok(true),
+        Err(e) => {
+            eprintln!("{}: {} ({} bytes)", location, e, bytes.len());
+            Ok(false)
+        }
+    }
+}
+
+/// Bytes of the hex digits in `s`, ignoring whitespace and a `0x` prefix.
+fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    let digits: Vec<char> =
+        s.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    if digits.len() % 2 != 0 {
+        return Err(format!("odd number of digits: {}", digits.len()));
+    }
+
+    digits
+        .chunks(2)
+        .enumerate()
+        .map(|(i, pair)| {
+            let byte: String = pair.iter().collect();
+            u8::from_str_radix(&byte, 16)
+                .map_err(|_| format!("bad digits {:?} at byte {}", byte, i))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_hex;
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex("0x83 05ff"), Ok(vec![0x83, 0x05, 0xff]));
+        assert_eq!(parse_hex("8305FF"), Ok(vec![0x83, 0x05, 0xff]));
+        assert!(parse_hex("830").is_err());
+        assert!(parse_hex("83zz").is_err());
+    }
+}
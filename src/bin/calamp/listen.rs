@@ -0,0 +1,212 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! `calamp listen`.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use calamp_rs::capture::PcapWriter;
+use calamp_rs::message_header::{MessageHeader, MessageType};
+use calamp_rs::options_header::OptionsHeader;
+use calamp_rs::server::{ack_reply, MAX_DATAGRAM};
+use calamp_rs::Message;
+use serde::Serialize;
+
+use crate::output::{self, Format, Printed};
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Address to listen on.
+    #[arg(short, long, default_value = "0.0.0.0:20500")]
+    bind: SocketAddr,
+
+    /// Output format.
+    #[arg(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Only show messages from this Mobile ID, as hex or decoded by its
+    /// type. Can be repeated.
+    #[arg(short, long = "mobile-id", value_name = "ID")]
+    mobile_ids: Vec<String>,
+
+    /// Only show messages of this type, by name (`event-report`) or
+    /// number. Can be repeated.
+    #[arg(
+        short = 't',
+        long = "type",
+        value_name = "TYPE",
+        value_parser = parse_message_type
+    )]
+    message_types: Vec<MessageType>,
+
+    /// Answer Acknowledged Requests with an ACK.
+    #[arg(long)]
+    ack: bool,
+
+    /// Record the datagrams received, and the ACKs sent, to a pcap file.
+    #[arg(long, value_name = "FILE")]
+    save: Option<PathBuf>,
+
+    /// Exit after this many datagrams.
+    #[arg(short = 'n', long)]
+    count: Option<u64>,
+}
+
+/// A message and where it came from.
+#[derive(Debug, Serialize)]
+struct Received<'a> {
+    /// Seconds since the Unix epoch.
+    time: f64,
+    src: SocketAddr,
+    message: Printed<'a>,
+}
+
+pub fn run(args: Args) -> crate::Result {
+    let socket = UdpSocket::bind(args.bind)?;
+    let local = socket.local_addr()?;
+    eprintln!("listening on {}", local);
+
+    let mut pcap = match &args.save {
+        Some(path) => {
+            let file = File::create(path)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            Some(PcapWriter::new(BufWriter::new(file))?)
+        }
+        None => None,
+    };
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut buf = [0u8; MAX_DATAGRAM];
+    let mut received = 0;
+
+    while args.count.map_or(true, |count| received < count) {
+        let (len, src) = socket.recv_from(&mut buf)?;
+        let time = SystemTime::now();
+        let datagram = &buf[..len];
+        received += 1;
+
+        if let Some(pcap) = &mut pcap {
+            pcap.write_udp(time, src, local, datagram)?;
+        }
+        if args.ack {
+            if let Some(ack) = ack_reply(datagram) {
+                // A lost ACK only makes the LMU retransmit, so a failed send
+                // does not stop the listener, only what was sent is saved.
+                if socket.send_to(&ack, src).is_ok() {
+                    if let Some(pcap) = &mut pcap {
+                        pcap.write_udp(SystemTime::now(), local, src, &ack)?;
+                    }
+                }
+            }
+        }
+        // Flushed for every datagram, the capture is complete whenever the
+        // tool is interrupted.
+        if let Some(pcap) = &mut pcap {
+            pcap.flush()?;
+        }
+
+        if !args.matches(datagram) {
+            continue;
+        }
+        match Message::parse(datagram) {
+            Ok(message) => {
+                let time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+                let received = Received {
+                    time: time.as_secs_f64(),
+                    src,
+                    message: Printed::new(&message, args.format),
+                };
+                output::write(&mut out, &received, args.format)?;
+                out.flush()?;
+            }
+            Err(e) => eprintln!("{}: {} ({} bytes)", src, e, len),
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+impl Args {
+    /// Whether `datagram` passes the filters, only its headers are read.
+    fn matches(&self, datagram: &[u8]) -> bool {
+        if self.mobile_ids.is_empty() && self.message_types.is_empty() {
+            return true;
+        }
+        let (i, options_header) = match OptionsHeader::parse(datagram) {
+            Ok(parsed) => parsed,
+            Err(_) => return false,
+        };
+        let message_header = match MessageHeader::parse(i) {
+            Ok((_, message_header)) => message_header,
+            Err(_) => return false,
+        };
+
+        let type_matches = self.message_types.is_empty()
+            || self.message_types.contains(&message_header.message_type);
+        let id_matches = self.mobile_ids.is_empty()
+            || options_header.is_some_and(|options_header| {
+                self.mobile_ids
+                    .iter()
+                    .any(|id| mobile_id_is(&options_header, id))
+            });
+        type_matches && id_matches
+    }
+}
+
+/// Whether the Mobile ID of `options_header` is `id`, in hex or decoded.
+fn mobile_id_is(options_header: &OptionsHeader, id: &str) -> bool {
+    let hex = options_header.mobile_id.as_ref().map(|m| m.to_hex());
+    hex.is_some_and(|hex| hex.eq_ignore_ascii_case(id))
+        || options_header.mobile_id_string().as_deref() == Some(id)
+}
+
+/// A message type by number or name, case and separators ignored.
+fn parse_message_type(s: &str) -> Result<MessageType, String> {
+    if let Ok(value) = s.parse::<u8>() {
+        return MessageType::try_from(value).map_err(|e| e.to_string());
+    }
+
+    let wanted: String = s
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    (0..=u8::MAX)
+        .map_while(|value| MessageType::try_from(value).ok())
+        .find(|message_type| message_type.name().replace('_', "") == wanted)
+        .ok_or_else(|| format!("unknown message type: {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_message_type;
+    use calamp_rs::message_header::MessageType;
+
+    #[test]
+    fn test_parse_message_type() {
+        assert_eq!(parse_message_type("2"), Ok(MessageType::EventReport));
+        assert_eq!(
+            parse_message_type("event-report"),
+            Ok(MessageType::EventReport)
+        );
+        assert_eq!(parse_message_type("AckNak"), Ok(MessageType::AckNak));
+        assert!(parse_message_type("15").is_err());
+        assert!(parse_message_type("report").is_err());
+    }
+}
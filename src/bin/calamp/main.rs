@@ -0,0 +1,86 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! `calamp`, a command line tool to look at LMDirect messages.
+
+use std::error::Error;
+use std::io;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+
+mod decode;
+mod listen;
+mod output;
+mod pcap;
+mod replay;
+mod simulate;
+mod wireshark;
+
+#[derive(Debug, Parser)]
+#[command(name = "calamp", version, about = "Inspect CalAmp LMDirect messages")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Decode messages given as hex, base64 or raw bytes.
+    Decode(decode::Args),
+
+    /// Print the messages received on a UDP port.
+    Listen(listen::Args),
+
+    /// Summarise or dump the LMDirect traffic of a pcap or pcapng capture.
+    Pcap(pcap::Args),
+
+    /// Send the datagrams of a capture to a server, keeping their timing.
+    Replay(replay::Args),
+
+    /// Run virtual LMUs reporting to a server.
+    Simulate(simulate::Args),
+
+    /// Generate a Lua dissector decoding LMDirect in Wireshark.
+    Wireshark(wireshark::Args),
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let res = match cli.command {
+        Command::Decode(args) => decode::run(args),
+        Command::Listen(args) => listen::run(args),
+        Command::Pcap(args) => pcap::run(args),
+        Command::Replay(args) => replay::run(args),
+        Command::Simulate(args) => simulate::run(args),
+        Command::Wireshark(args) => wireshark::run(args),
+    };
+    match res {
+        Ok(code) => code,
+        // The reader went away, as with `calamp decode … | head`.
+        Err(e) if is_broken_pipe(&*e) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("calamp: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn is_broken_pipe(e: &(dyn Error + 'static)) -> bool {
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe)
+}
+
+/// Result of a subcommand, the exit code when it ran to the end.
+type Result<T = ExitCode> = std::result::Result<T, Box<dyn Error>>;
@@ -0,0 +1,92 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Printing decoded messages.
+
+use std::fmt::{self, Debug};
+use std::io::{self, Write};
+
+use calamp_rs::json::MessageJson;
+use calamp_rs::Message;
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// How messages are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Indented Rust debug output.
+    Text,
+
+    /// Indented JSON, in the stable schema of `calamp_rs::json`.
+    Json,
+
+    /// One JSON object per line, in the stable schema of `calamp_rs::json`.
+    Ndjson,
+
+    /// One YAML document per message.
+    Yaml,
+}
+
+/// Write `msg` to `out` in `format`.
+pub fn write<W, T>(out: &mut W, msg: &T, format: Format) -> io::Result<()>
+where
+    W: Write,
+    T: Serialize + Debug,
+{
+    match format {
+        Format::Text => writeln!(out, "{:#?}", msg),
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, msg)?;
+            writeln!(out)
+        }
+        Format::Ndjson => {
+            serde_json::to_writer(&mut *out, msg)?;
+            writeln!(out)
+        }
+        Format::Yaml => {
+            let doc = serde_yaml::to_string(msg)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            write!(out, "---\n{}", doc)
+        }
+    }
+}
+
+/// A message as printed: in the stable JSON schema for JSON and NDJSON, as
+/// the Rust types otherwise.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Printed<'a> {
+    Stable(Box<MessageJson<'a>>),
+    Types(&'a Message),
+}
+
+impl<'a> Printed<'a> {
+    pub fn new(msg: &'a Message, format: Format) -> Self {
+        match format {
+            Format::Json | Format::Ndjson => {
+                Printed::Stable(Box::new(MessageJson::new(msg)))
+            }
+            _ => Printed::Types(msg),
+        }
+    }
+}
+
+impl Debug for Printed<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Printed::Stable(json) => json.fmt(f),
+            Printed::Types(msg) => msg.fmt(f),
+        }
+    }
+}
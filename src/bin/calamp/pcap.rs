@@ -0,0 +1,223 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! `calamp pcap`.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use calamp_rs::capture::{Capture, Datagram};
+use calamp_rs::Timestamp;
+use serde::Serialize;
+
+use crate::output::{self, Format, Printed};
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// A pcap or pcapng capture.
+    file: PathBuf,
+
+    /// UDP port of the LMDirect traffic, 0 for every port.
+    #[arg(short, long, default_value_t = 20500)]
+    port: u16,
+
+    /// Print every message instead of a summary.
+    #[arg(short, long)]
+    dump: bool,
+
+    /// Output format of the dump.
+    #[arg(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+}
+
+/// A message and where it travelled.
+#[derive(Debug, Serialize)]
+struct Captured<'a> {
+    /// Seconds since the Unix epoch.
+    time: f64,
+    src: SocketAddr,
+    dst: SocketAddr,
+    message: Printed<'a>,
+}
+
+#[derive(Debug)]
+struct Device {
+    messages: usize,
+    first: SystemTime,
+    last: SystemTime,
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    datagrams: usize,
+    errors: Vec<String>,
+    devices: BTreeMap<String, Device>,
+    message_types: BTreeMap<Cow<'static, str>, usize>,
+    span: Option<(SystemTime, SystemTime)>,
+}
+
+impl Summary {
+    fn add(&mut self, datagram: &Datagram) {
+        self.datagrams += 1;
+        let time = datagram.time;
+        self.span = Some(match self.span {
+            Some((first, last)) => (first.min(time), last.max(time)),
+            None => (time, time),
+        });
+
+        let msg = match &datagram.message {
+            Ok(msg) => msg,
+            Err(e) => {
+                self.errors.push(format!(
+                    "{} {} -> {}: {}",
+                    iso(time),
+                    datagram.src,
+                    datagram.dst,
+                    e
+                ));
+                return;
+            }
+        };
+        let message_type = msg.message_header.message_type.name();
+        *self.message_types.entry(message_type).or_default() += 1;
+
+        let mobile_id = msg
+            .options_header
+            .as_ref()
+            .and_then(|options_header| options_header.mobile_id_string())
+            .unwrap_or_else(|| "-".into());
+        let device = self.devices.entry(mobile_id).or_insert(Device {
+            messages: 0,
+            first: time,
+            last: time,
+        });
+        device.messages += 1;
+        device.first = device.first.min(time);
+        device.last = device.last.max(time);
+    }
+
+    fn write<W: Write>(&self, out: &mut W, name: &str) -> io::Result<()> {
+        let messages: usize = self.message_types.values().sum();
+        writeln!(
+            out,
+            "{}: {} datagrams, {} messages, {} errors",
+            name,
+            self.datagrams,
+            messages,
+            self.errors.len()
+        )?;
+        if let Some((first, last)) = self.span {
+            writeln!(out, "from {} to {}", iso(first), iso(last))?;
+        }
+
+        if !self.devices.is_empty() {
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{:<20} {:>8}  {:<20}  {:<20}",
+                "mobile id", "messages", "first", "last"
+            )?;
+            for (mobile_id, device) in &self.devices {
+                writeln!(
+                    out,
+                    "{:<20} {:>8}  {:<20}  {:<20}",
+                    mobile_id,
+                    device.messages,
+                    iso(device.first),
+                    iso(device.last)
+                )?;
+            }
+        }
+        if !self.message_types.is_empty() {
+            writeln!(out)?;
+            writeln!(out, "{:<36} {:>8}", "message type", "messages")?;
+            for (message_type, count) in &self.message_types {
+                writeln!(out, "{:<36} {:>8}", message_type, count)?;
+            }
+        }
+        if !self.errors.is_empty() {
+            writeln!(out)?;
+            writeln!(out, "errors")?;
+            for error in &self.errors {
+                writeln!(out, "{}", error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn run(args: Args) -> crate::Result {
+    let name = args.file.display().to_string();
+    let mut capture =
+        Capture::open(&args.file).map_err(|e| format!("{}: {}", name, e))?;
+    if args.port != 0 {
+        capture = capture.port(args.port);
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut summary = Summary::default();
+    for datagram in capture {
+        let datagram = datagram.map_err(|e| format!("{}: {}", name, e))?;
+        if !args.dump {
+            summary.add(&datagram);
+            continue;
+        }
+
+        match &datagram.message {
+            Ok(message) => {
+                let captured = Captured {
+                    time: secs(datagram.time),
+                    src: datagram.src,
+                    dst: datagram.dst,
+                    message: Printed::new(message, args.format),
+                };
+                output::write(&mut out, &captured, args.format)?;
+            }
+            Err(e) => eprintln!(
+                "{} {} -> {}: {} ({} bytes)",
+                iso(datagram.time),
+                datagram.src,
+                datagram.dst,
+                e,
+                datagram.payload.len()
+            ),
+        }
+    }
+
+    if !args.dump {
+        summary.write(&mut out, &name)?;
+    }
+    out.flush()?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn secs(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+fn iso(time: SystemTime) -> Timestamp {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Timestamp::new(secs as u32)
+}
@@ -0,0 +1,131 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! `calamp replay`.
+
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use calamp_rs::capture::Capture;
+use calamp_rs::replay::{Replay, Stats};
+
+use crate::simulate::parse_duration;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// A pcap or pcapng capture.
+    file: PathBuf,
+
+    /// Server to send the datagrams to.
+    #[arg(short, long)]
+    to: SocketAddr,
+
+    /// Server port of the capture, only the datagrams sent to it are
+    /// replayed. 0 replays every datagram.
+    #[arg(short, long, default_value_t = 20500)]
+    port: u16,
+
+    /// Speed up, such as `10x` or `0.5x`, `max` to send as fast as
+    /// possible.
+    #[arg(short, long, default_value = "1x", value_parser = parse_speed)]
+    speed: f64,
+
+    /// Give the LMUs ESNs counting up from this one.
+    #[arg(long, value_name = "FIRST")]
+    rewrite_ids: Option<u64>,
+
+    /// Number the requests of every LMU again from 1.
+    #[arg(long)]
+    renumber: bool,
+
+    /// Time to wait for the last acks.
+    #[arg(short, long, default_value = "2s", value_parser = parse_duration)]
+    wait: Duration,
+}
+
+/// Exits with a failure when acks are missing.
+pub fn run(args: Args) -> crate::Result {
+    let capture = Capture::open(&args.file)
+        .map_err(|e| format!("{}: {}", args.file.display(), e))?;
+    let mut replay = Replay::new(args.to)
+        .speed(args.speed)
+        .renumber(args.renumber)
+        .wait(args.wait);
+    if args.port != 0 {
+        replay = replay.to_port(args.port);
+    }
+    if let Some(first) = args.rewrite_ids {
+        replay = replay.rewrite_mobile_ids(first);
+    }
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let stats = runtime
+        .block_on(replay.run(capture))
+        .map_err(|e| format!("{}: {}", args.file.display(), e))?;
+
+    print_stats(&mut io::stdout().lock(), &stats)?;
+    Ok(if stats.missing == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn print_stats<W: Write>(out: &mut W, stats: &Stats) -> io::Result<()> {
+    writeln!(
+        out,
+        "{} datagrams from {} LMUs",
+        stats.datagrams, stats.sources
+    )?;
+    writeln!(
+        out,
+        "{} acks expected: {} acked, {} nakd, {} missing",
+        stats.expected, stats.acked, stats.nakd, stats.missing
+    )?;
+    if stats.unexpected > 0 {
+        writeln!(out, "{} unexpected acks", stats.unexpected)?;
+    }
+    Ok(())
+}
+
+/// A speed up such as `10x`, `10` or `max`.
+fn parse_speed(s: &str) -> Result<f64, String> {
+    if s == "max" {
+        return Ok(f64::INFINITY);
+    }
+    match s.strip_suffix('x').unwrap_or(s).parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(format!("bad speed: {}", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_speed;
+
+    #[test]
+    fn test_parse_speed() {
+        assert_eq!(parse_speed("10x"), Ok(10.0));
+        assert_eq!(parse_speed("0.5x"), Ok(0.5));
+        assert_eq!(parse_speed("2"), Ok(2.0));
+        assert_eq!(parse_speed("max"), Ok(f64::INFINITY));
+        assert!(parse_speed("0x").is_err());
+        assert!(parse_speed("fast").is_err());
+    }
+}
@@ -0,0 +1,214 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! `calamp simulate`.
+
+use std::future;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use calamp_rs::simulator::{
+    ReportTemplate, Route, RouteSource, Simulator, Stats,
+};
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Server to report to.
+    #[arg(short, long, default_value = "127.0.0.1:20500")]
+    to: SocketAddr,
+
+    /// Number of LMUs.
+    #[arg(short = 'n', long, default_value_t = 1)]
+    devices: usize,
+
+    /// Time between two reports of an LMU, such as `30s` or `500ms`.
+    #[arg(short, long, default_value = "60s", value_parser = parse_duration)]
+    interval: Duration,
+
+    /// ESN of the first LMU, the next ones count up from it.
+    #[arg(long, default_value_t = 4_000_000_000)]
+    first_id: u64,
+
+    /// Latitude reported, in degrees.
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    lat: f64,
+
+    /// Longitude reported, in degrees.
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    lon: f64,
+
+    /// Speed reported, in km/h. Along a route without times, the speed it
+    /// is driven at, 50 km/h by default.
+    #[arg(long)]
+    speed: Option<f64>,
+
+    /// Heading reported, in degrees from true North.
+    #[arg(long, default_value_t = 0)]
+    heading: u16,
+
+    /// Report the ignition on.
+    #[arg(long)]
+    ignition: bool,
+
+    /// Accumulator values reported, comma separated.
+    #[arg(long, value_delimiter = ',', value_name = "VALUES")]
+    accumulators: Vec<u32>,
+
+    /// Drive the route of a GPX or GeoJSON file instead of reporting a
+    /// fixed position.
+    #[arg(long, value_name = "FILE")]
+    route: Option<PathBuf>,
+
+    /// Time parked with the ignition off at each end of the route.
+    #[arg(long, default_value = "60s", value_parser = parse_duration)]
+    dwell: Duration,
+
+    /// Drive the route again after parking at its end.
+    #[arg(long)]
+    repeat: bool,
+
+    /// Seed of the GPS variations along the route, runs with the same seed
+    /// report the same readings.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Time to wait for an ack before retransmitting.
+    #[arg(long, default_value = "5s", value_parser = parse_duration)]
+    retry: Duration,
+
+    /// Transmissions of a report before giving up on it.
+    #[arg(long, default_value_t = 3)]
+    attempts: u32,
+
+    /// Stop after this long, runs until interrupted otherwise.
+    #[arg(short, long, value_parser = parse_duration)]
+    duration: Option<Duration>,
+
+    /// Take a new source port after this long without sending, as behind
+    /// a NAT.
+    #[arg(long, value_parser = parse_duration)]
+    nat_timeout: Option<Duration>,
+}
+
+pub fn run(args: Args) -> crate::Result {
+    let template = ReportTemplate::new(args.lat, args.lon)
+        .speed(args.speed.unwrap_or(0.0))
+        .heading(args.heading)
+        .ignition(args.ignition)
+        .accumulators(args.accumulators.clone());
+    let mut simulator = Simulator::new(args.to)
+        .devices(args.devices)
+        .first_mobile_id(args.first_id)
+        .report_interval(args.interval)
+        .retry_interval(args.retry)
+        .max_attempts(args.attempts);
+    simulator = match &args.route {
+        Some(path) => {
+            let route = Route::open(path)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            let source = RouteSource::new(route)
+                .template(template)
+                .speed(args.speed.unwrap_or(50.0))
+                .dwell(args.dwell)
+                .repeat(args.repeat)
+                .seed(args.seed);
+            simulator.route(source)
+        }
+        None => simulator.template(template),
+    };
+    if let Some(timeout) = args.nat_timeout {
+        simulator = simulator.nat_timeout(timeout);
+    }
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    eprintln!("simulating {} LMUs reporting to {}", args.devices, args.to);
+    let stats = runtime.block_on(async {
+        let duration = args.duration;
+        simulator
+            .run_until(async move {
+                let elapsed = async {
+                    match duration {
+                        Some(duration) => tokio::time::sleep(duration).await,
+                        None => future::pending().await,
+                    }
+                };
+                tokio::select! {
+                    _ = elapsed => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            })
+            .await
+    })?;
+
+    print_stats(&mut io::stdout().lock(), &stats)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn print_stats<W: Write>(out: &mut W, stats: &Stats) -> io::Result<()> {
+    let rows = [
+        ("reports", stats.reports),
+        ("retransmissions", stats.retransmissions),
+        ("acked", stats.acked),
+        ("nakd", stats.nakd),
+        ("unacked", stats.unacked),
+        ("unit requests", stats.unit_requests),
+        ("locate reports", stats.locate_reports),
+        ("rebinds", stats.rebinds),
+    ];
+    for (name, count) in rows {
+        writeln!(out, "{:<16} {}", name, count)?;
+    }
+    Ok(())
+}
+
+/// A duration in `ms`, `s`, `m` or `h`, seconds without a unit.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("bad duration: {}", s))?;
+    let secs = match unit {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => return Err(format!("unknown unit in duration: {}", s)),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("{}: {}", s, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_duration;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("2"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("1.5m"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert!(parse_duration("5d").is_err());
+        assert!(parse_duration("-1s").is_err());
+        assert!(parse_duration("s").is_err());
+    }
+}
@@ -0,0 +1,43 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! `calamp wireshark`.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use calamp_rs::wireshark::lua_dissector;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// UDP ports to decode as LMDirect, may be repeated.
+    #[arg(short, long = "port", default_values_t = [20500])]
+    ports: Vec<u16>,
+
+    /// Write the dissector to a file instead of standard output.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+pub fn run(args: Args) -> crate::Result {
+    let lua = lua_dissector(&args.ports);
+    match &args.output {
+        Some(path) => fs::write(path, lua)
+            .map_err(|e| format!("{}: {}", path.display(), e))?,
+        None => io::stdout().write_all(lua.as_bytes())?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
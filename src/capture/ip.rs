@@ -0,0 +1,476 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! IP and UDP headers around captured datagrams.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, SystemTime};
+
+const UDP: u8 = 17;
+const TTL: u8 = 64;
+const UDP_HEADER_LEN: usize = 8;
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+
+/// IPv6 extension headers skipped on the way to UDP.
+const HOP_BY_HOP: u8 = 0;
+const ROUTING: u8 = 43;
+const FRAGMENT: u8 = 44;
+const DESTINATION: u8 = 60;
+
+/// Fragments of a datagram not complete after this long are dropped.
+const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Datagrams being reassembled at most, the oldest is dropped beyond.
+const MAX_PENDING: usize = 256;
+
+/// An IP packet carrying `payload` from `src` to `dst` over UDP.
+///
+/// When one address is IPv6, as on a dual stack socket, the other one is
+/// mapped to IPv6 as well.
+pub(crate) fn udp_packet(
+    src: SocketAddr, dst: SocketAddr, payload: &[u8],
+) -> Vec<u8> {
+    let udp_len = UDP_HEADER_LEN + payload.len();
+    let mut packet = Vec::with_capacity(40 + udp_len);
+
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(s), IpAddr::V4(d)) => {
+            let total_len = (IPV4_HEADER_LEN + udp_len) as u16;
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&total_len.to_be_bytes());
+            // Identification, then the don't fragment flag.
+            packet.extend_from_slice(&[0, 0, 0x40, 0, TTL, UDP, 0, 0]);
+            packet.extend_from_slice(&s.octets());
+            packet.extend_from_slice(&d.octets());
+            let sum = checksum(0, &packet);
+            packet[10..12].copy_from_slice(&sum.to_be_bytes());
+        }
+        (s, d) => {
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(udp_len as u16).to_be_bytes());
+            packet.extend_from_slice(&[UDP, TTL]);
+            packet.extend_from_slice(&to_ipv6(s).octets());
+            packet.extend_from_slice(&to_ipv6(d).octets());
+        }
+    }
+
+    let udp = packet.len();
+    packet.extend_from_slice(&src.port().to_be_bytes());
+    packet.extend_from_slice(&dst.port().to_be_bytes());
+    packet.extend_from_slice(&(udp_len as u16).to_be_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(payload);
+
+    // The pseudo header: addresses, protocol and UDP length.
+    let addrs = if packet[0] >> 4 == 4 {
+        &packet[12..20]
+    } else {
+        &packet[8..40]
+    };
+    let pseudo = checksum_add(u32::from(UDP) + udp_len as u32, addrs);
+    let sum = match checksum(pseudo, &packet[udp..]) {
+        // Zero means no checksum, all ones is the same in one's complement.
+        0 => 0xffff,
+        sum => sum,
+    };
+    packet[udp + 6..udp + 8].copy_from_slice(&sum.to_be_bytes());
+    packet
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// Add the 16 bit words of `data` to `sum`, without folding.
+fn checksum_add(mut sum: u32, data: &[u8]) -> u32 {
+    for word in data.chunks(2) {
+        let hi = u32::from(word[0]) << 8;
+        sum += hi | word.get(1).copied().map_or(0, u32::from);
+    }
+    sum
+}
+
+/// Internet checksum of `data`, starting from the partial `sum`.
+pub(crate) fn checksum(sum: u32, data: &[u8]) -> u16 {
+    let mut sum = checksum_add(sum, data);
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// A UDP datagram taken out of an IP packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Udp {
+    pub(crate) src: SocketAddr,
+    pub(crate) dst: SocketAddr,
+    pub(crate) payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct FragmentKey {
+    src: IpAddr,
+    dst: IpAddr,
+    id: u32,
+    protocol: u8,
+}
+
+#[derive(Debug)]
+struct Fragments {
+    first_seen: SystemTime,
+    parts: Vec<(usize, Vec<u8>)>,
+    /// Known once the last fragment arrived.
+    len: Option<usize>,
+}
+
+impl Fragments {
+    /// The whole payload, once every byte of it arrived.
+    fn assemble(&mut self) -> Option<Vec<u8>> {
+        let len = self.len?;
+        self.parts.sort_by_key(|(offset, _)| *offset);
+
+        let mut whole = Vec::with_capacity(len);
+        for (offset, data) in &self.parts {
+            if *offset > whole.len() {
+                return None;
+            }
+            // Overlapping fragments: the bytes already there win.
+            if let Some(rest) = data.get(whole.len() - offset..) {
+                whole.extend_from_slice(rest);
+            }
+        }
+        if whole.len() < len {
+            return None;
+        }
+        whole.truncate(len);
+        Some(whole)
+    }
+}
+
+/// Takes UDP datagrams out of IP packets, putting fragmented datagrams
+/// back together.
+#[derive(Debug, Default)]
+pub(crate) struct Reassembler {
+    pending: HashMap<FragmentKey, Fragments>,
+}
+
+impl Reassembler {
+    /// The UDP datagram in `packet`, captured at `time`. `None` for other
+    /// protocols, truncated packets and fragments of datagrams not yet
+    /// complete.
+    pub(crate) fn udp(
+        &mut self, time: SystemTime, packet: &[u8],
+    ) -> Option<Udp> {
+        match packet.first()? >> 4 {
+            4 => self.ipv4(time, packet),
+            6 => self.ipv6(time, packet),
+            _ => None,
+        }
+    }
+
+    fn ipv4(&mut self, time: SystemTime, packet: &[u8]) -> Option<Udp> {
+        let header_len = usize::from(packet[0] & 0x0f) * 4;
+        let total_len = usize::from(be16(packet.get(2..4)?));
+        if header_len < IPV4_HEADER_LEN || total_len < header_len {
+            return None;
+        }
+        // Link layers pad short packets, the padding is not payload.
+        let packet = packet.get(..total_len)?;
+        let header = &packet[..header_len];
+        let protocol = header[9];
+        if protocol != UDP {
+            return None;
+        }
+        let src = IpAddr::from(Ipv4Addr::new(
+            header[12], header[13], header[14], header[15],
+        ));
+        let dst = IpAddr::from(Ipv4Addr::new(
+            header[16], header[17], header[18], header[19],
+        ));
+
+        let flags = be16(&header[6..8]);
+        let more = flags & 0x2000 != 0;
+        let offset = usize::from(flags & 0x1fff) * 8;
+        let payload = &packet[header_len..];
+        if !more && offset == 0 {
+            return udp(src, dst, payload);
+        }
+
+        let key = FragmentKey {
+            src,
+            dst,
+            id: u32::from(be16(&header[4..6])),
+            protocol,
+        };
+        let whole = self.fragment(key, time, offset, more, payload)?;
+        udp(src, dst, &whole)
+    }
+
+    fn ipv6(&mut self, time: SystemTime, packet: &[u8]) -> Option<Udp> {
+        let header = packet.get(..IPV6_HEADER_LEN)?;
+        let payload_len = usize::from(be16(&header[4..6]));
+        let payload =
+            packet.get(IPV6_HEADER_LEN..IPV6_HEADER_LEN + payload_len)?;
+        let src = IpAddr::from(ipv6(&header[8..24]));
+        let dst = IpAddr::from(ipv6(&header[24..40]));
+
+        self.ipv6_payload(time, src, dst, header[6], Cow::Borrowed(payload))
+    }
+
+    /// Walk the extension headers from `next` down to UDP.
+    fn ipv6_payload(
+        &mut self, time: SystemTime, src: IpAddr, dst: IpAddr, mut next: u8,
+        mut data: Cow<'_, [u8]>,
+    ) -> Option<Udp> {
+        loop {
+            match next {
+                UDP => return udp(src, dst, &data),
+                HOP_BY_HOP | ROUTING | DESTINATION => {
+                    let len = (usize::from(*data.get(1)?) + 1) * 8;
+                    next = data[0];
+                    data = Cow::Owned(data.get(len..)?.to_vec());
+                }
+                FRAGMENT => {
+                    let header = data.get(..8)?;
+                    let flags = be16(&header[2..4]);
+                    let key = FragmentKey {
+                        src,
+                        dst,
+                        id: be32(&header[4..8]),
+                        protocol: header[0],
+                    };
+                    next = header[0];
+                    let offset = usize::from(flags & 0xfff8);
+                    let more = flags & 1 != 0;
+                    let whole =
+                        self.fragment(key, time, offset, more, &data[8..])?;
+                    data = Cow::Owned(whole);
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// Store a fragment, returning the whole payload when it was the last
+    /// one missing.
+    fn fragment(
+        &mut self, key: FragmentKey, time: SystemTime, offset: usize,
+        more: bool, data: &[u8],
+    ) -> Option<Vec<u8>> {
+        self.expire(time);
+        let fragments = self.pending.entry(key.clone()).or_insert(Fragments {
+            first_seen: time,
+            parts: Vec::new(),
+            len: None,
+        });
+        fragments.parts.push((offset, data.to_vec()));
+        if !more {
+            fragments.len = Some(offset + data.len());
+        }
+
+        let whole = fragments.assemble()?;
+        self.pending.remove(&key);
+        Some(whole)
+    }
+
+    fn expire(&mut self, now: SystemTime) {
+        self.pending.retain(|_, fragments| {
+            now.duration_since(fragments.first_seen)
+                .map_or(true, |age| age < FRAGMENT_TIMEOUT)
+        });
+        if self.pending.len() >= MAX_PENDING {
+            let oldest = self
+                .pending
+                .iter()
+                .min_by_key(|(_, fragments)| fragments.first_seen)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.pending.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// The datagram at the start of the UDP segment `data`.
+fn udp(src: IpAddr, dst: IpAddr, data: &[u8]) -> Option<Udp> {
+    let header = data.get(..UDP_HEADER_LEN)?;
+    let len = usize::from(be16(&header[4..6]));
+    if len < UDP_HEADER_LEN {
+        return None;
+    }
+
+    Some(Udp {
+        src: SocketAddr::new(src, be16(&header[0..2])),
+        dst: SocketAddr::new(dst, be16(&header[2..4])),
+        payload: data.get(UDP_HEADER_LEN..len)?.to_vec(),
+    })
+}
+
+fn be16(b: &[u8]) -> u16 {
+    u16::from_be_bytes([b[0], b[1]])
+}
+
+fn be32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn ipv6(b: &[u8]) -> Ipv6Addr {
+    let mut octets = [0u8; 16];
+    octets.copy_from_slice(b);
+    Ipv6Addr::from(octets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checksum, udp_packet, Reassembler, Udp};
+    use crate::fixtures::addrs;
+    use std::net::SocketAddr;
+    use std::time::{Duration, SystemTime};
+
+    /// The IPv4 `packet` cut in fragments of `sizes` bytes of payload.
+    fn fragments_v4(packet: &[u8], sizes: &[usize]) -> Vec<Vec<u8>> {
+        let (header, mut payload) = packet.split_at(20);
+        let mut offset = 0;
+        let mut fragments = Vec::new();
+        for (n, size) in sizes.iter().enumerate() {
+            let (part, rest) = payload.split_at(*size);
+            payload = rest;
+            let mut fragment = header.to_vec();
+            let len = (20 + size) as u16;
+            fragment[2..4].copy_from_slice(&len.to_be_bytes());
+            let mut flags = (offset / 8) as u16;
+            if n + 1 < sizes.len() {
+                flags |= 0x2000;
+            }
+            fragment[4..6].copy_from_slice(&[0x12, 0x34]);
+            fragment[6..8].copy_from_slice(&flags.to_be_bytes());
+            fragment.extend_from_slice(part);
+            fragments.push(fragment);
+            offset += size;
+        }
+        fragments
+    }
+
+    #[test]
+    fn test_udp_packet_v4() {
+        let (src, dst) = addrs();
+        let packet = udp_packet(src, dst, &[0x83, 0x00]);
+
+        assert_eq!(packet.len(), 30);
+        assert_eq!(&packet[..4], [0x45, 0x00, 0x00, 30]);
+        assert_eq!(packet[9], 17);
+        assert_eq!(&packet[12..16], [192, 0, 2, 1]);
+        assert_eq!(&packet[20..26], [0x0d, 0xac, 0x50, 0x14, 0x00, 10]);
+        // A header with its checksum sums to zero.
+        assert_eq!(checksum(0, &packet[..20]), 0);
+        let pseudo = [&packet[12..20], &[0, 17, 0, 10]].concat();
+        assert_eq!(checksum(0, &[&pseudo[..], &packet[20..]].concat()), 0);
+    }
+
+    #[test]
+    fn test_udp_packet_dual_stack() {
+        let src: SocketAddr = "192.0.2.1:3500".parse().unwrap();
+        let dst: SocketAddr = "[::]:20500".parse().unwrap();
+        let packet = udp_packet(src, dst, &[0x83]);
+
+        assert_eq!(packet.len(), 49);
+        assert_eq!(packet[0] >> 4, 6);
+        assert_eq!(&packet[4..7], [0, 9, 17]);
+        assert_eq!(&packet[16..22], [0, 0, 0xff, 0xff, 192, 0]);
+        let pseudo = [&packet[8..40], &[0, 17, 0, 9]].concat();
+        assert_eq!(checksum(0, &[&pseudo[..], &packet[40..]].concat()), 0);
+    }
+
+    #[test]
+    fn test_udp_from_packet() {
+        let (src, dst) = addrs();
+        let mut packet = udp_packet(src, dst, &[0x83, 0x00]);
+        // Ethernet padding after the packet.
+        packet.extend_from_slice(&[0; 6]);
+
+        let mut reassembler = Reassembler::default();
+        let udp = reassembler.udp(SystemTime::now(), &packet);
+        assert_eq!(
+            udp,
+            Some(Udp {
+                src,
+                dst,
+                payload: vec![0x83, 0x00]
+            })
+        );
+        assert_eq!(reassembler.udp(SystemTime::now(), &packet[..25]), None);
+
+        let v6: SocketAddr = "[2001:db8::1]:3500".parse().unwrap();
+        let packet = udp_packet(v6, dst, &[0x83]);
+        let udp = reassembler.udp(SystemTime::now(), &packet).unwrap();
+        assert_eq!(udp.src, v6);
+        assert_eq!(udp.dst.port(), 20500);
+        assert_eq!(udp.payload, [0x83]);
+    }
+
+    #[test]
+    fn test_reassemble_v4() {
+        let (src, dst) = addrs();
+        let payload: Vec<u8> = (0..100).collect();
+        let packet = udp_packet(src, dst, &payload);
+        let now = SystemTime::now();
+
+        let mut reassembler = Reassembler::default();
+        let fragments = fragments_v4(&packet, &[48, 40, 20]);
+        assert_eq!(reassembler.udp(now, &fragments[2]), None);
+        assert_eq!(reassembler.udp(now, &fragments[0]), None);
+        let udp = reassembler.udp(now, &fragments[1]).unwrap();
+        assert_eq!(udp.payload, payload);
+        assert!(reassembler.pending.is_empty());
+
+        // A fragment whose datagram never completes is dropped.
+        assert_eq!(reassembler.udp(now, &fragments[0]), None);
+        let later = now + Duration::from_secs(31);
+        assert_eq!(reassembler.udp(later, &fragments[1]), None);
+        assert_eq!(reassembler.pending.len(), 1);
+    }
+
+    #[test]
+    fn test_reassemble_v6() {
+        let src: SocketAddr = "[2001:db8::1]:3500".parse().unwrap();
+        let dst: SocketAddr = "[2001:db8::2]:20500".parse().unwrap();
+        let payload: Vec<u8> = (0..40).collect();
+        let packet = udp_packet(src, dst, &payload);
+        let (header, segment) = packet.split_at(40);
+
+        let mut reassembler = Reassembler::default();
+        let now = SystemTime::now();
+        let mut udp = None;
+        for (offset, part) in [(0, &segment[..24]), (24, &segment[24..])] {
+            let more = u16::from(offset == 0);
+            let flags = (offset as u16) | more;
+            let mut fragment = header.to_vec();
+            fragment[4..6]
+                .copy_from_slice(&(8 + part.len() as u16).to_be_bytes());
+            fragment[6] = 44;
+            fragment.extend_from_slice(&[17, 0]);
+            fragment.extend_from_slice(&flags.to_be_bytes());
+            fragment.extend_from_slice(&[0, 0, 0, 7]);
+            fragment.extend_from_slice(part);
+            udp = reassembler.udp(now, &fragment);
+        }
+        assert_eq!(udp.unwrap().payload, payload);
+    }
+}
@@ -0,0 +1,117 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Packet captures.
+//!
+//! [`PcapWriter`] records LMDirect traffic in the pcap format, to be opened
+//! with Wireshark or tcpdump. Datagrams are stored as IP packets
+//! (`LINKTYPE_RAW`) or Ethernet frames, with IP and UDP headers built from
+//! the socket addresses. [`RotatingPcapWriter`] spreads a long recording,
+//! such as the audit trail of a server, over files of a bounded size or
+//! age.
+//!
+//! [`Capture`] reads pcap and pcapng files, as written by tcpdump or
+//! Wireshark, and yields the UDP datagrams they hold with the message
+//! parsed. Fragmented IP datagrams are put back together.
+//!
+//! ```no_run
+//! use calamp_rs::capture::Capture;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! for datagram in Capture::open("customer.pcapng")?.port(20500) {
+//!     let datagram = datagram?;
+//!     match datagram.message {
+//!         Ok(msg) => println!("{}: {:?}", datagram.src, msg.message_header),
+//!         Err(e) => println!("{}: {}", datagram.src, e),
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+mod ip;
+mod reader;
+mod rotate;
+mod writer;
+
+pub use reader::{Capture, Datagram, Packet, PacketReader};
+pub use rotate::RotatingPcapWriter;
+pub use writer::PcapWriter;
+
+/// Magic number of a pcap file with microsecond timestamps.
+pub const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+
+/// Magic number of a pcap file with nanosecond timestamps.
+pub const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+
+/// Largest packet kept in a capture.
+pub const SNAPLEN: u32 = 65535;
+
+/// Link layer of the packets in a capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkType {
+    /// BSD loopback, a 4 byte address family before the IP packet.
+    Null,
+
+    /// Ethernet II, possibly with VLAN tags.
+    Ethernet,
+
+    /// IPv4 or IPv6 packets, without link layer header.
+    Raw,
+
+    /// Linux cooked capture, as recorded on the `any` interface.
+    LinuxSll,
+
+    /// IPv4 packets only.
+    Ipv4,
+
+    /// IPv6 packets only.
+    Ipv6,
+
+    /// Linux cooked capture, version 2.
+    LinuxSll2,
+
+    /// Link type not known to this crate.
+    Unknown(u32),
+}
+
+impl From<u32> for LinkType {
+    fn from(value: u32) -> LinkType {
+        match value {
+            0 => LinkType::Null,
+            1 => LinkType::Ethernet,
+            101 => LinkType::Raw,
+            113 => LinkType::LinuxSll,
+            228 => LinkType::Ipv4,
+            229 => LinkType::Ipv6,
+            276 => LinkType::LinuxSll2,
+            _ => LinkType::Unknown(value),
+        }
+    }
+}
+
+impl From<LinkType> for u32 {
+    fn from(link_type: LinkType) -> u32 {
+        match link_type {
+            LinkType::Null => 0,
+            LinkType::Ethernet => 1,
+            LinkType::Raw => 101,
+            LinkType::LinuxSll => 113,
+            LinkType::Ipv4 => 228,
+            LinkType::Ipv6 => 229,
+            LinkType::LinuxSll2 => 276,
+            LinkType::Unknown(value) => value,
+        }
+    }
+}
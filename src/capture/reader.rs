@@ -0,0 +1,593 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::ip::Reassembler;
+use super::{LinkType, PCAP_MAGIC, PCAP_MAGIC_NANOS};
+use crate::error::Error;
+use crate::Message;
+
+/// Block types of pcapng.
+const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const OBSOLETE_PACKET: u32 = 2;
+const SIMPLE_PACKET: u32 = 3;
+const ENHANCED_PACKET: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+/// Interface option giving the timestamp resolution.
+const IF_TSRESOL: u16 = 9;
+
+/// Records larger than this are taken for a corrupt file.
+const MAX_RECORD: usize = 1 << 24;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+
+/// A packet read from a capture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    /// When the packet was captured, the Unix epoch when the capture does
+    /// not tell.
+    pub time: SystemTime,
+    pub link_type: LinkType,
+
+    /// The frame, as much of it as was captured.
+    pub data: Vec<u8>,
+}
+
+impl Packet {
+    /// The IP packet in the frame, `None` for other protocols.
+    pub fn ip(&self) -> Option<&[u8]> {
+        let data = &self.data;
+        match self.link_type {
+            LinkType::Null => data.get(4..),
+            LinkType::Raw | LinkType::Ipv4 | LinkType::Ipv6 => Some(data),
+            LinkType::Ethernet => {
+                let mut offset = 12;
+                loop {
+                    let ethertype = be16(data.get(offset..offset + 2)?);
+                    offset += 2;
+                    match ethertype {
+                        ETHERTYPE_VLAN | ETHERTYPE_QINQ => offset += 2,
+                        ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => {
+                            return data.get(offset..)
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+            LinkType::LinuxSll => match be16(data.get(14..16)?) {
+                ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => data.get(16..),
+                _ => None,
+            },
+            LinkType::LinuxSll2 => match be16(data.get(..2)?) {
+                ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => data.get(20..),
+                _ => None,
+            },
+            LinkType::Unknown(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Endian {
+    big: bool,
+}
+
+impl Endian {
+    fn u16(self, b: &[u8]) -> u16 {
+        let b = [b[0], b[1]];
+        if self.big {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        }
+    }
+
+    fn u32(self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        if self.big {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Interface {
+    link_type: LinkType,
+    units_per_sec: u64,
+}
+
+#[derive(Debug)]
+enum Format {
+    Pcap {
+        endian: Endian,
+        nanos: bool,
+        link_type: LinkType,
+    },
+    PcapNg {
+        endian: Endian,
+        interfaces: Vec<Interface>,
+    },
+}
+
+/// Reads the packets of a pcap or pcapng capture, the format is found
+/// from the file header.
+#[derive(Debug)]
+pub struct PacketReader<R> {
+    input: R,
+    format: Format,
+}
+
+impl<R: Read> PacketReader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+
+        let format = if u32::from_le_bytes(magic) == SECTION_HEADER {
+            let mut len = [0u8; 4];
+            input.read_exact(&mut len)?;
+            Format::PcapNg {
+                endian: section_header(&mut input, len)?,
+                interfaces: Vec::new(),
+            }
+        } else {
+            let (endian, nanos) = match magic {
+                m if u32::from_le_bytes(m) == PCAP_MAGIC => (false, false),
+                m if u32::from_be_bytes(m) == PCAP_MAGIC => (true, false),
+                m if u32::from_le_bytes(m) == PCAP_MAGIC_NANOS => (false, true),
+                m if u32::from_be_bytes(m) == PCAP_MAGIC_NANOS => (true, true),
+                _ => return Err(invalid("not a pcap or pcapng file")),
+            };
+            let endian = Endian { big: endian };
+            let mut header = [0u8; 20];
+            input.read_exact(&mut header)?;
+            Format::Pcap {
+                endian,
+                nanos,
+                // The upper bits carry FCS information.
+                link_type: LinkType::from(endian.u32(&header[16..20]) & 0xffff),
+            }
+        };
+        Ok(PacketReader { input, format })
+    }
+
+    /// The next packet, `None` at the end of the capture.
+    pub fn next_packet(&mut self) -> io::Result<Option<Packet>> {
+        match self.format {
+            Format::Pcap { .. } => self.next_pcap(),
+            Format::PcapNg { .. } => self.next_pcapng(),
+        }
+    }
+
+    fn next_pcap(&mut self) -> io::Result<Option<Packet>> {
+        let (endian, nanos, link_type) = match self.format {
+            Format::Pcap {
+                endian,
+                nanos,
+                link_type,
+            } => (endian, nanos, link_type),
+            Format::PcapNg { .. } => unreachable!(),
+        };
+
+        let mut header = [0u8; 16];
+        if !read_or_eof(&mut self.input, &mut header)? {
+            return Ok(None);
+        }
+        let secs = u64::from(endian.u32(&header[0..4]));
+        let frac = endian.u32(&header[4..8]);
+        let captured = endian.u32(&header[8..12]) as usize;
+        if captured > MAX_RECORD {
+            return Err(invalid("pcap record too large"));
+        }
+        let mut data = vec![0u8; captured];
+        self.input.read_exact(&mut data)?;
+
+        let frac = if nanos {
+            Duration::from_nanos(u64::from(frac))
+        } else {
+            Duration::from_micros(u64::from(frac))
+        };
+        Ok(Some(Packet {
+            time: UNIX_EPOCH + Duration::from_secs(secs) + frac,
+            link_type,
+            data,
+        }))
+    }
+
+    fn next_pcapng(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            let mut head = [0u8; 8];
+            if !read_or_eof(&mut self.input, &mut head)? {
+                return Ok(None);
+            }
+            if u32::from_le_bytes([head[0], head[1], head[2], head[3]])
+                == SECTION_HEADER
+            {
+                let endian = section_header(
+                    &mut self.input,
+                    [head[4], head[5], head[6], head[7]],
+                )?;
+                self.format = Format::PcapNg {
+                    endian,
+                    interfaces: Vec::new(),
+                };
+                continue;
+            }
+
+            let (endian, interfaces) = match &mut self.format {
+                Format::PcapNg { endian, interfaces } => (*endian, interfaces),
+                Format::Pcap { .. } => unreachable!(),
+            };
+            let block_type = endian.u32(&head[0..4]);
+            let len = endian.u32(&head[4..8]) as usize;
+            if len < 12 || len % 4 != 0 || len > MAX_RECORD {
+                return Err(invalid("bad pcapng block length"));
+            }
+            // The body and the trailing copy of the length.
+            let mut body = vec![0u8; len - 8];
+            self.input.read_exact(&mut body)?;
+            body.truncate(len - 12);
+
+            match block_type {
+                INTERFACE_DESCRIPTION => {
+                    interfaces.push(interface(endian, &body)?);
+                }
+                ENHANCED_PACKET | OBSOLETE_PACKET => {
+                    let header = body.get(..20).ok_or_else(short_block)?;
+                    // The obsolete block has a 16 bit interface id.
+                    let index = match block_type {
+                        ENHANCED_PACKET => endian.u32(&header[0..4]) as usize,
+                        _ => usize::from(endian.u16(&header[0..2])),
+                    };
+                    let interface = interfaces
+                        .get(index)
+                        .ok_or_else(|| invalid("unknown pcapng interface"))?;
+                    let ts = u64::from(endian.u32(&header[4..8])) << 32
+                        | u64::from(endian.u32(&header[8..12]));
+                    let captured = endian.u32(&header[12..16]) as usize;
+                    let data = body
+                        .get(20..20 + captured)
+                        .ok_or_else(short_block)?
+                        .to_vec();
+                    return Ok(Some(Packet {
+                        time: timestamp(ts, interface.units_per_sec)?,
+                        link_type: interface.link_type,
+                        data,
+                    }));
+                }
+                SIMPLE_PACKET => {
+                    let interface = interfaces
+                        .first()
+                        .ok_or_else(|| invalid("unknown pcapng interface"))?;
+                    let original =
+                        endian.u32(body.get(..4).ok_or_else(short_block)?);
+                    let data = &body[4..];
+                    let data = &data[..data.len().min(original as usize)];
+                    return Ok(Some(Packet {
+                        time: UNIX_EPOCH,
+                        link_type: interface.link_type,
+                        data: data.to_vec(),
+                    }));
+                }
+                // Statistics, name resolution and custom blocks.
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// Read the rest of a section header block, whose length bytes are `len`,
+/// and return the byte order of the section.
+fn section_header<R: Read>(input: &mut R, len: [u8; 4]) -> io::Result<Endian> {
+    let mut magic = [0u8; 4];
+    input.read_exact(&mut magic)?;
+    let endian = match magic {
+        m if u32::from_le_bytes(m) == BYTE_ORDER_MAGIC => Endian { big: false },
+        m if u32::from_be_bytes(m) == BYTE_ORDER_MAGIC => Endian { big: true },
+        _ => return Err(invalid("bad pcapng byte order magic")),
+    };
+
+    let len = endian.u32(&len) as usize;
+    if len < 28 || len % 4 != 0 || len > MAX_RECORD {
+        return Err(invalid("bad pcapng block length"));
+    }
+    let mut rest = vec![0u8; len - 12];
+    input.read_exact(&mut rest)?;
+    Ok(endian)
+}
+
+/// Parse the body of an interface description block.
+fn interface(endian: Endian, body: &[u8]) -> io::Result<Interface> {
+    let header = body.get(..8).ok_or_else(short_block)?;
+    let mut interface = Interface {
+        link_type: LinkType::from(u32::from(endian.u16(&header[0..2]))),
+        units_per_sec: 1_000_000,
+    };
+
+    let mut options = &body[8..];
+    while options.len() >= 4 {
+        let code = endian.u16(&options[0..2]);
+        let len = usize::from(endian.u16(&options[2..4]));
+        let value = options.get(4..4 + len).ok_or_else(short_block)?;
+        if code == 0 {
+            break;
+        }
+        if code == IF_TSRESOL && len == 1 {
+            let exp = u32::from(value[0] & 0x7f);
+            let units = if value[0] & 0x80 == 0 {
+                10u64.checked_pow(exp)
+            } else {
+                1u64.checked_shl(exp)
+            };
+            interface.units_per_sec =
+                units.ok_or_else(|| invalid("bad pcapng time resolution"))?;
+        }
+        let padded = (len + 3) & !3;
+        options = options.get(4 + padded..).unwrap_or(&[]);
+    }
+    Ok(interface)
+}
+
+fn timestamp(ts: u64, units_per_sec: u64) -> io::Result<SystemTime> {
+    let secs = ts / units_per_sec;
+    let nanos = u128::from(ts % units_per_sec) * 1_000_000_000
+        / u128::from(units_per_sec);
+    UNIX_EPOCH
+        .checked_add(Duration::new(secs, nanos as u32))
+        .ok_or_else(|| invalid("pcapng timestamp out of range"))
+}
+
+/// Fill `buf`, returning false when the input was already at its end.
+fn read_or_eof<R: Read>(input: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut read = 0;
+    while read < buf.len() {
+        match input.read(&mut buf[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn short_block() -> io::Error {
+    invalid("pcapng block too short")
+}
+
+fn be16(b: &[u8]) -> u16 {
+    u16::from_be_bytes([b[0], b[1]])
+}
+
+/// A UDP datagram read from a capture.
+#[derive(Debug)]
+pub struct Datagram {
+    pub time: SystemTime,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+
+    /// The UDP payload.
+    pub payload: Vec<u8>,
+
+    /// The payload parsed.
+    pub message: Result<Message, Error>,
+}
+
+/// The UDP datagrams of a capture, in the order they were captured.
+#[derive(Debug)]
+pub struct Capture<R> {
+    packets: PacketReader<R>,
+    reassembler: Reassembler,
+    port: Option<u16>,
+}
+
+impl Capture<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Capture::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> Capture<R> {
+    pub fn new(input: R) -> io::Result<Self> {
+        Ok(Capture {
+            packets: PacketReader::new(input)?,
+            reassembler: Reassembler::default(),
+            port: None,
+        })
+    }
+
+    /// Only yield the datagrams sent from or to `port`.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    fn next_datagram(&mut self) -> io::Result<Option<Datagram>> {
+        while let Some(packet) = self.packets.next_packet()? {
+            let ip = match packet.ip() {
+                Some(ip) => ip,
+                None => continue,
+            };
+            let udp = match self.reassembler.udp(packet.time, ip) {
+                Some(udp) => udp,
+                None => continue,
+            };
+            if let Some(port) = self.port {
+                if udp.src.port() != port && udp.dst.port() != port {
+                    continue;
+                }
+            }
+
+            return Ok(Some(Datagram {
+                time: packet.time,
+                src: udp.src,
+                dst: udp.dst,
+                message: Message::parse(&udp.payload),
+                payload: udp.payload,
+            }));
+        }
+        Ok(None)
+    }
+}
+
+impl<R: Read> Iterator for Capture<R> {
+    type Item = io::Result<Datagram>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_datagram().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Capture, PacketReader};
+    use crate::capture::ip::udp_packet;
+    use crate::capture::{LinkType, PcapWriter};
+    use crate::fixtures::{addrs, DATA};
+    use crate::message_header::MessageType;
+    use std::io;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// A little endian pcapng block.
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let padded = (body.len() + 3) & !3;
+        let len = (12 + padded) as u32;
+        let mut block = Vec::new();
+        block.extend_from_slice(&block_type.to_le_bytes());
+        block.extend_from_slice(&len.to_le_bytes());
+        block.extend_from_slice(body);
+        block.resize(8 + padded, 0);
+        block.extend_from_slice(&len.to_le_bytes());
+        block
+    }
+
+    #[test]
+    fn test_read_pcap() {
+        let (src, dst) = addrs();
+        let time = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let mut pcap = PcapWriter::new(Vec::new()).unwrap();
+        pcap.write_udp(time, src, "192.0.2.53:53".parse().unwrap(), &[0])
+            .unwrap();
+        pcap.write_udp(time, src, dst, &DATA).unwrap();
+        let file = pcap.into_inner();
+
+        let datagrams: Vec<_> = Capture::new(&file[..])
+            .unwrap()
+            .port(20500)
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(datagrams.len(), 1);
+        let datagram = &datagrams[0];
+        assert_eq!(datagram.time, time);
+        assert_eq!((datagram.src, datagram.dst), (src, dst));
+        assert_eq!(datagram.payload, DATA);
+        let msg = datagram.message.as_ref().unwrap();
+        assert_eq!(msg.message_header.message_type, MessageType::EventReport);
+
+        // Every datagram without a port filter, the last record cut short.
+        let mut capture = Capture::new(&file[..file.len() - 1]).unwrap();
+        assert!(capture.next().unwrap().unwrap().message.is_err());
+        let err = capture.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_read_pcapng() {
+        let (src, dst) = addrs();
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&0x1a2b_3c4du32.to_le_bytes());
+        shb.extend_from_slice(&[1, 0, 0, 0]);
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        // Ethernet, timestamps in nanoseconds.
+        let mut idb = vec![1, 0, 0, 0, 0, 0, 4, 0];
+        idb.extend_from_slice(&[9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0]);
+
+        // An Ethernet frame with a VLAN tag.
+        let mut frame = vec![0xff; 12];
+        frame.extend_from_slice(&[0x81, 0x00, 0x00, 0x07, 0x08, 0x00]);
+        frame.extend_from_slice(&udp_packet(src, dst, &DATA));
+        let ts: u64 = 1_600_000_000_123_456_789;
+        let mut epb = vec![0; 4];
+        epb.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(ts as u32).to_le_bytes());
+        epb.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&frame);
+
+        let file = [
+            block(0x0a0d_0d0a, &shb),
+            block(1, &idb),
+            block(5, &[0; 12]),
+            block(6, &epb),
+        ]
+        .concat();
+
+        let mut packets = PacketReader::new(&file[..]).unwrap();
+        let packet = packets.next_packet().unwrap().unwrap();
+        assert_eq!(packet.link_type, LinkType::Ethernet);
+        assert_eq!(packet.time, UNIX_EPOCH + Duration::from_nanos(ts));
+        assert_eq!(packet.ip().unwrap()[0], 0x45);
+        assert!(packets.next_packet().unwrap().is_none());
+
+        let mut capture = Capture::new(&file[..]).unwrap();
+        let datagram = capture.next().unwrap().unwrap();
+        assert_eq!(datagram.src, src);
+        assert!(datagram.message.is_ok());
+        assert!(capture.next().is_none());
+    }
+
+    #[test]
+    fn test_read_pcapng_timestamp_out_of_range() {
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&0x1a2b_3c4du32.to_le_bytes());
+        shb.extend_from_slice(&[1, 0, 0, 0]);
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        // Ethernet, timestamps in seconds.
+        let mut idb = vec![1, 0, 0, 0, 0, 0, 4, 0];
+        idb.extend_from_slice(&[9, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let mut epb = vec![0; 4];
+        epb.extend_from_slice(&[0xff; 8]);
+        epb.extend_from_slice(&[0; 8]);
+
+        let file =
+            [block(0x0a0d_0d0a, &shb), block(1, &idb), block(6, &epb)].concat();
+
+        let mut packets = PacketReader::new(&file[..]).unwrap();
+        let err = packets.next_packet().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "pcapng timestamp out of range");
+    }
+
+    #[test]
+    fn test_read_not_a_capture() {
+        let err = PacketReader::new(&b"GET / HTTP/1.1\r\n"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
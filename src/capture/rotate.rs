@@ -0,0 +1,277 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{LinkType, PcapWriter};
+use crate::Timestamp;
+
+#[derive(Debug)]
+struct Current {
+    pcap: PcapWriter<BufWriter<File>>,
+    path: PathBuf,
+    opened: SystemTime,
+}
+
+/// Records datagrams to a series of pcap files, starting a new file when
+/// the current one reaches a size or an age.
+///
+/// Files are named after the time of their first packet, such as
+/// `audit-20210103T033028Z.pcap`, and hold Ethernet frames by default so
+/// any tool opens them. Packets are buffered, call [`flush`] to have them
+/// on disk.
+///
+/// ```no_run
+/// use calamp_rs::capture::RotatingPcapWriter;
+/// use std::time::{Duration, SystemTime};
+///
+/// # fn main() -> std::io::Result<()> {
+/// let mut audit = RotatingPcapWriter::new("/var/log/lmdirect", "audit")
+///     .max_size(100 << 20)
+///     .max_age(Duration::from_secs(3600));
+/// let src = "192.0.2.1:3500".parse().unwrap();
+/// let dst = "192.0.2.2:20500".parse().unwrap();
+/// audit.write_udp(SystemTime::now(), src, dst, &[0x83, 0x05])?;
+/// audit.flush()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`flush`]: RotatingPcapWriter::flush
+#[derive(Debug)]
+pub struct RotatingPcapWriter {
+    dir: PathBuf,
+    prefix: String,
+    link_type: LinkType,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    current: Option<Current>,
+}
+
+impl RotatingPcapWriter {
+    /// Write files named `prefix-<time>.pcap` in `dir`, which must exist.
+    /// Without limits, everything goes to one file.
+    pub fn new<P: AsRef<Path>>(dir: P, prefix: &str) -> Self {
+        RotatingPcapWriter {
+            dir: dir.as_ref().to_path_buf(),
+            prefix: prefix.to_string(),
+            link_type: LinkType::Ethernet,
+            max_size: None,
+            max_age: None,
+            current: None,
+        }
+    }
+
+    /// Link type of the next files, [`LinkType::Ethernet`] by default.
+    pub fn link_type(mut self, link_type: LinkType) -> Self {
+        self.link_type = link_type;
+        self
+    }
+
+    /// Start a new file rather than grow one past `bytes`. A file always
+    /// takes at least one packet.
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Start a new file for the packets captured `age` after the first one
+    /// of the current file.
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// The file being written, if any.
+    pub fn current_path(&self) -> Option<&Path> {
+        self.current.as_ref().map(|current| current.path.as_path())
+    }
+
+    /// Record `payload`, sent from `src` to `dst` at `time`.
+    pub fn write_udp(
+        &mut self, time: SystemTime, src: SocketAddr, dst: SocketAddr,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        // Record header, Ethernet, IPv6 and UDP headers at most.
+        let record_len = 16 + 14 + 40 + 8 + payload.len() as u64;
+        self.rotate(time, record_len)?;
+
+        let current = match &mut self.current {
+            Some(current) => current,
+            None => self.current.insert(self.open(time)?),
+        };
+        current.pcap.write_udp(time, src, dst, payload)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(current) => current.pcap.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Close the current file when the next record does not belong in it.
+    fn rotate(&mut self, time: SystemTime, record_len: u64) -> io::Result<()> {
+        let current = match &self.current {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+
+        let written = current.pcap.bytes_written();
+        let too_big = self.max_size.is_some_and(|max| {
+            // The file header alone is there when the file is empty.
+            written > 24 && written + record_len > max
+        });
+        let too_old = self.max_age.is_some_and(|max| {
+            time.duration_since(current.opened)
+                .is_ok_and(|age| age >= max)
+        });
+        if too_big || too_old {
+            if let Some(mut current) = self.current.take() {
+                current.pcap.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn open(&self, time: SystemTime) -> io::Result<Current> {
+        let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let stamp: String = Timestamp::new(secs.as_secs() as u32)
+            .to_string()
+            .chars()
+            .filter(|c| *c != '-' && *c != ':')
+            .collect();
+
+        // Files are never overwritten, a suffix tells apart the files
+        // started within the same second.
+        let mut n = 0;
+        loop {
+            let name = match n {
+                0 => format!("{}-{}.pcap", self.prefix, stamp),
+                n => format!("{}-{}-{}.pcap", self.prefix, stamp, n),
+            };
+            let path = self.dir.join(name);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    let pcap = PcapWriter::with_link_type(
+                        BufWriter::new(file),
+                        self.link_type,
+                    )?;
+                    return Ok(Current {
+                        pcap,
+                        path,
+                        opened: time,
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RotatingPcapWriter;
+    use crate::capture::Capture;
+    use crate::fixtures::addrs;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "calamp-rotate-{}-{}",
+            name,
+            process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn files(dir: &PathBuf) -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_rotate_by_size() {
+        let dir = temp_dir("size");
+        let (src, dst) = addrs();
+        let time = UNIX_EPOCH + Duration::from_secs(1_609_644_628);
+        // Room for two 100 byte datagrams per file.
+        let mut audit = RotatingPcapWriter::new(&dir, "audit").max_size(400);
+        for n in 0..5u8 {
+            audit.write_udp(time, src, dst, &[n; 100]).unwrap();
+        }
+        audit.flush().unwrap();
+        drop(audit);
+
+        assert_eq!(
+            files(&dir),
+            [
+                "audit-20210103T033028Z-1.pcap",
+                "audit-20210103T033028Z-2.pcap",
+                "audit-20210103T033028Z.pcap",
+            ]
+        );
+        let payloads: Vec<Vec<u8>> = files(&dir)
+            .iter()
+            .map(|name| {
+                let datagrams = Capture::open(dir.join(name)).unwrap();
+                datagrams.map(|d| d.unwrap().payload[0]).collect()
+            })
+            .collect();
+        assert_eq!(payloads, [vec![2, 3], vec![4], vec![0, 1]]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotate_by_age() {
+        let dir = temp_dir("age");
+        let (src, dst) = addrs();
+        let start = UNIX_EPOCH + Duration::from_secs(1_609_644_628);
+        let mut audit = RotatingPcapWriter::new(&dir, "audit")
+            .max_age(Duration::from_secs(60));
+        for secs in [0, 30, 59, 60, 200] {
+            let time = start + Duration::from_secs(secs);
+            audit.write_udp(time, src, dst, &[0x83]).unwrap();
+            assert!(audit.current_path().is_some());
+        }
+        drop(audit);
+
+        assert_eq!(
+            files(&dir),
+            [
+                "audit-20210103T033028Z.pcap",
+                "audit-20210103T033128Z.pcap",
+                "audit-20210103T033348Z.pcap",
+            ]
+        );
+        let first = Capture::open(dir.join(&files(&dir)[0])).unwrap();
+        assert_eq!(first.count(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::ip::udp_packet;
+use super::{LinkType, PCAP_MAGIC, SNAPLEN};
+
+/// Writes datagrams to a pcap file.
+///
+/// ```no_run
+/// use calamp_rs::capture::PcapWriter;
+/// use std::fs::File;
+/// use std::time::SystemTime;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let mut pcap = PcapWriter::new(File::create("capture.pcap")?)?;
+/// let src = "192.0.2.1:3500".parse().unwrap();
+/// let dst = "192.0.2.2:20500".parse().unwrap();
+/// pcap.write_udp(SystemTime::now(), src, dst, &[0x83, 0x05])?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct PcapWriter<W: Write> {
+    out: W,
+    link_type: LinkType,
+    written: u64,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Start a capture of IP packets by writing the file header to `out`.
+    pub fn new(out: W) -> io::Result<Self> {
+        PcapWriter::with_link_type(out, LinkType::Raw)
+    }
+
+    /// Start a capture of `link_type` frames, [`LinkType::Raw`] or
+    /// [`LinkType::Ethernet`]. Ethernet frames carry zero MAC addresses,
+    /// for tools that expect a link layer.
+    pub fn with_link_type(mut out: W, link_type: LinkType) -> io::Result<Self> {
+        if !matches!(link_type, LinkType::Raw | LinkType::Ethernet) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only raw IP and Ethernet captures can be written",
+            ));
+        }
+
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        // Time zone and timestamp accuracy, always zero.
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&u32::from(link_type).to_le_bytes());
+        out.write_all(&header)?;
+        Ok(PcapWriter {
+            out,
+            link_type,
+            written: header.len() as u64,
+        })
+    }
+
+    pub fn link_type(&self) -> LinkType {
+        self.link_type
+    }
+
+    /// Bytes written so far, file header included.
+    pub fn bytes_written(&self) -> u64 {
+        self.written
+    }
+
+    /// Record `payload`, sent from `src` to `dst` at `time`.
+    pub fn write_udp(
+        &mut self, time: SystemTime, src: SocketAddr, dst: SocketAddr,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let packet = udp_packet(src, dst, payload);
+        match self.link_type {
+            LinkType::Ethernet => {
+                self.write_packet(time, &ethernet_frame(&packet))
+            }
+            _ => self.write_packet(time, &packet),
+        }
+    }
+
+    /// Record a complete frame of the capture link type, captured at
+    /// `time`.
+    pub fn write_packet(
+        &mut self, time: SystemTime, packet: &[u8],
+    ) -> io::Result<()> {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let len = packet.len() as u32;
+        let kept = &packet[..packet.len().min(SNAPLEN as usize)];
+
+        let mut record = Vec::with_capacity(16 + kept.len());
+        record.extend_from_slice(&(since_epoch.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&since_epoch.subsec_micros().to_le_bytes());
+        record.extend_from_slice(&(kept.len() as u32).to_le_bytes());
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(kept);
+        self.out.write_all(&record)?;
+        self.written += record.len() as u64;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    /// Give back the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// `packet` in an Ethernet frame.
+fn ethernet_frame(packet: &[u8]) -> Vec<u8> {
+    let ethertype: u16 = match packet.first().map(|b| b >> 4) {
+        Some(6) => 0x86dd,
+        _ => 0x0800,
+    };
+    let mut frame = Vec::with_capacity(14 + packet.len());
+    frame.extend_from_slice(&[0; 12]);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(packet);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PcapWriter;
+    use crate::capture::{Capture, LinkType, PacketReader};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_pcap_writer() {
+        let mut pcap = PcapWriter::new(Vec::new()).unwrap();
+        let time = UNIX_EPOCH + Duration::from_micros(1_600_000_000_000_042);
+        pcap.write_udp(
+            time,
+            "192.0.2.1:3500".parse().unwrap(),
+            "192.0.2.2:20500".parse().unwrap(),
+            &[0x83, 0x00],
+        )
+        .unwrap();
+        let out = pcap.into_inner();
+
+        assert_eq!(
+            &out[..24],
+            [
+                0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0xff, 0xff, 0, 0, 101, 0, 0, 0,
+            ]
+        );
+        assert_eq!(
+            &out[24..40],
+            [0x00, 0x10, 0x5e, 0x5f, 42, 0, 0, 0, 30, 0, 0, 0, 30, 0, 0, 0]
+        );
+        assert_eq!(out.len(), 24 + 16 + 30);
+        assert_eq!(&out[out.len() - 2..], [0x83, 0x00]);
+    }
+
+    #[test]
+    fn test_pcap_writer_ethernet() {
+        let mut pcap =
+            PcapWriter::with_link_type(Vec::new(), LinkType::Ethernet).unwrap();
+        let time = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let src = "[2001:db8::1]:3500".parse().unwrap();
+        let dst = "[2001:db8::2]:20500".parse().unwrap();
+        pcap.write_udp(time, src, dst, &[0x83]).unwrap();
+        assert_eq!(pcap.bytes_written(), 24 + 16 + 14 + 40 + 8 + 1);
+        let out = pcap.into_inner();
+
+        let packet = PacketReader::new(&out[..])
+            .unwrap()
+            .next_packet()
+            .unwrap()
+            .unwrap();
+        assert_eq!(packet.link_type, LinkType::Ethernet);
+        assert_eq!(&packet.data[12..14], [0x86, 0xdd]);
+
+        let datagram = Capture::new(&out[..]).unwrap().next().unwrap().unwrap();
+        assert_eq!((datagram.src, datagram.dst), (src, dst));
+        assert_eq!(datagram.payload, [0x83]);
+
+        assert!(PcapWriter::with_link_type(Vec::new(), LinkType::Null).is_err());
+    }
+}
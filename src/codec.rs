@@ -0,0 +1,176 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! tokio-util codec, enabled with the `tokio` feature.
+//!
+//! [`LmDirectCodec`] turns a `Framed<TcpStream, _>` or a `UdpFramed<_>` into
+//! a `Stream` of parsed messages and a `Sink` for outbound ones. Framing is
+//! the one of [`Framer`](crate::framer::Framer): bytes before an options
+//! header are skipped. A frame that does not parse is skipped too, rather
+//! than returned as an error that would end the stream, and counted in
+//! [`LmDirectCodec::skipped`].
+
+use std::io;
+
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::error::Error;
+use crate::framer;
+use crate::Message;
+
+/// LMDirect [`Decoder`] and [`Encoder`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LmDirectCodec {
+    skipped: u64,
+}
+
+impl LmDirectCodec {
+    pub fn new() -> Self {
+        LmDirectCodec::default()
+    }
+
+    /// Number of frames skipped because they did not parse, or were cut
+    /// short by the end of the input.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+impl Decoder for LmDirectCodec {
+    type Item = Message;
+    type Error = io::Error;
+
+    fn decode(
+        &mut self, src: &mut BytesMut,
+    ) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            src.advance(framer::sync(src));
+            if src.is_empty() {
+                return Ok(None);
+            }
+
+            match framer::frame_length(src) {
+                Ok(len) => {
+                    let frame = src.split_to(len);
+                    match Message::parse(&frame) {
+                        Ok(msg) => return Ok(Some(msg)),
+                        Err(_) => self.skipped += 1,
+                    }
+                }
+                Err(Error::Incomplete { needed }) => {
+                    src.reserve(needed);
+                    return Ok(None);
+                }
+                Err(_) => {
+                    // Skip the marker and look for another one.
+                    src.advance(1);
+                    self.skipped += 1;
+                }
+            }
+        }
+    }
+
+    /// A message cut short by the end of the stream, or of the datagram,
+    /// is dropped and counted as skipped.
+    fn decode_eof(
+        &mut self, src: &mut BytesMut,
+    ) -> Result<Option<Self::Item>, Self::Error> {
+        let msg = self.decode(src)?;
+        if msg.is_none() && !src.is_empty() {
+            src.clear();
+            self.skipped += 1;
+        }
+        Ok(msg)
+    }
+}
+
+impl Encoder<Message> for LmDirectCodec {
+    type Error = io::Error;
+
+    fn encode(
+        &mut self, item: Message, dst: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        dst.extend_from_slice(&item.to_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LmDirectCodec;
+    use crate::fixtures::DATA;
+    use crate::Message;
+    use bytes::BytesMut;
+    use futures_util::StreamExt;
+    use tokio_util::codec::{Decoder, Encoder, FramedRead};
+
+    #[test]
+    fn test_decode_stream() {
+        let mut codec = LmDirectCodec::new();
+        let mut src = BytesMut::new();
+        src.extend_from_slice(&[0x00, 0x42]);
+        src.extend_from_slice(&DATA);
+        src.extend_from_slice(&DATA[..30]);
+
+        let msg = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(msg.message_header.sequence_number.data(), 14982);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        assert_eq!(src.len(), 30);
+
+        src.extend_from_slice(&DATA[30..]);
+        assert!(codec.decode(&mut src).unwrap().is_some());
+        assert!(src.is_empty());
+        assert_eq!(codec.skipped(), 0);
+    }
+
+    #[test]
+    fn test_decode_eof_truncated() {
+        let mut codec = LmDirectCodec::new();
+        let mut src = BytesMut::from(&DATA[..100]);
+
+        assert!(codec.decode_eof(&mut src).unwrap().is_none());
+        assert!(src.is_empty());
+        assert_eq!(codec.skipped(), 1);
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let mut codec = LmDirectCodec::new();
+        let msg = Message::parse(&DATA).unwrap();
+
+        let mut dst = BytesMut::new();
+        codec.encode(msg, &mut dst).unwrap();
+        assert_eq!(&dst[..], &DATA[..]);
+        assert!(codec.decode(&mut dst).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_framed_read_carries_on_after_bad_frame() {
+        // A Unit Request can not be framed, its marker is skipped and the
+        // stream goes on with the following messages.
+        let mut input = vec![0x83, 0x01, 0x01, 0x01, 0x01, 0x00, 0x07, 0x00];
+        input.extend_from_slice(&DATA);
+        input.extend_from_slice(&DATA);
+        input.extend_from_slice(&DATA[..20]);
+        let mut framed = FramedRead::new(&input[..], LmDirectCodec::new());
+
+        for _ in 0..2 {
+            let msg = framed.next().await.unwrap().unwrap();
+            assert_eq!(msg.message_header.sequence_number.data(), 14982);
+        }
+        assert!(framed.next().await.is_none());
+        assert_eq!(framed.decoder().skipped(), 2);
+    }
+}
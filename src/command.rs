@@ -0,0 +1,778 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Outbound commands.
+//!
+//! An LMU behind a carrier NAT can only be reached for a short time after
+//! it sent something. [`CommandQueue`] holds the messages for each Mobile
+//! ID and hands them out, with the address to send them to, when the next
+//! message of that LMU arrives. The body of a command is given already
+//! encoded, the queue adds the options and message headers.
+//!
+//! Commands are sent as Acknowledged Requests, each LMU gets its own run of
+//! sequence numbers so the Ack/Nak it answers with is matched back to the
+//! command. A command not answered is sent again, waiting twice as long
+//! after every attempt, until it runs out of attempts. The outcome can be
+//! awaited with a [`Completion`].
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::message_header::{
+    MessageHeader, MessageType, SequenceNumber, ServiceType,
+};
+use crate::messages::ack_nak::AckNakMessage;
+use crate::options_header::{MobileID, OptionsHeader};
+
+/// Final states kept for [`CommandQueue::state`] and
+/// [`CommandQueue::drain_finished`], the oldest ones are dropped past this.
+const MAX_FINISHED: usize = 1024;
+
+/// Identifies a command pushed to a [`CommandQueue`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CommandId(u64);
+
+impl fmt::Display for CommandId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Where a command is in its delivery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandState {
+    /// Waiting for the LMU to show up.
+    Queued,
+
+    /// Sent `attempts` times, no answer yet.
+    Sent { attempts: u32 },
+
+    /// The LMU acknowledged it.
+    Acked,
+
+    /// The LMU refused it with this NAK reason.
+    Nakd(u8),
+
+    /// Not answered before its timeout or after its last attempt.
+    Expired,
+}
+
+impl CommandState {
+    /// Whether the command left the queue.
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            CommandState::Acked | CommandState::Nakd(_) | CommandState::Expired
+        )
+    }
+}
+
+/// A message to deliver to an LMU.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub message_type: MessageType,
+
+    /// The encoded message body.
+    pub body: Vec<u8>,
+
+    /// Times the command is sent before giving up.
+    pub max_attempts: u32,
+
+    /// Wait for an answer after the first attempt, doubled after each
+    /// following one.
+    pub retry_interval: Duration,
+
+    /// Time after being queued the command expires if not answered.
+    pub timeout: Duration,
+}
+
+impl Command {
+    /// A command sent up to 3 times, 5 seconds apart at first, and expiring
+    /// after one hour.
+    pub fn new(message_type: MessageType, body: &[u8]) -> Self {
+        Command {
+            message_type,
+            body: body.to_vec(),
+            max_attempts: 3,
+            retry_interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(3600),
+        }
+    }
+
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// A message to send now.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outbound {
+    pub id: CommandId,
+
+    /// Current address of the LMU.
+    pub addr: SocketAddr,
+
+    /// The complete message.
+    pub bytes: Vec<u8>,
+}
+
+/// The final state once reached, and the tasks waiting for it: clones of
+/// a [`Completion`] may be awaited by several tasks.
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<(Option<CommandState>, Vec<Waker>)>,
+    done: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, (Option<CommandState>, Vec<Waker>)> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn complete(&self, state: CommandState) {
+        let mut guard = self.lock();
+        guard.0 = Some(state);
+        for waker in guard.1.drain(..) {
+            waker.wake();
+        }
+        self.done.notify_all();
+    }
+}
+
+/// Final state of a command, as a future or by blocking on
+/// [`Completion::wait`]. It does not depend on any async runtime.
+#[derive(Debug, Clone)]
+pub struct Completion(Arc<Shared>);
+
+impl Completion {
+    /// Block the thread until the command reaches its final state.
+    pub fn wait(self) -> CommandState {
+        let mut guard = self.0.lock();
+        loop {
+            if let Some(state) = guard.0 {
+                return state;
+            }
+            guard = self.0.done.wait(guard).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// The final state, if reached.
+    pub fn try_get(&self) -> Option<CommandState> {
+        self.0.lock().0
+    }
+}
+
+impl Future for Completion {
+    type Output = CommandState;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<CommandState> {
+        let mut guard = self.0.lock();
+        match guard.0 {
+            Some(state) => Poll::Ready(state),
+            None => {
+                let waker = cx.waker();
+                if !guard.1.iter().any(|w| w.will_wake(waker)) {
+                    guard.1.push(waker.clone());
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    id: CommandId,
+    command: Command,
+    sequence: SequenceNumber,
+    queued_at: Instant,
+    attempts: u32,
+    next_attempt: Option<Instant>,
+    completion: Option<Arc<Shared>>,
+}
+
+impl Entry {
+    fn state(&self) -> CommandState {
+        match self.attempts {
+            0 => CommandState::Queued,
+            attempts => CommandState::Sent { attempts },
+        }
+    }
+
+    fn is_due(&self, now: Instant) -> bool {
+        self.attempts < self.command.max_attempts
+            && self.next_attempt.map_or(true, |at| at <= now)
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        let timed_out =
+            now.duration_since(self.queued_at) >= self.command.timeout;
+        let exhausted = self.attempts >= self.command.max_attempts
+            && self.next_attempt.map_or(true, |at| at <= now);
+        timed_out || exhausted
+    }
+
+    /// Record an attempt at `now` and schedule the next one.
+    fn attempt(&mut self, now: Instant) {
+        let backoff = 1u32.checked_shl(self.attempts).unwrap_or(u32::MAX);
+        self.attempts += 1;
+        self.next_attempt =
+            Some(now + self.command.retry_interval.saturating_mul(backoff));
+    }
+}
+
+#[derive(Debug)]
+struct Device {
+    addr: Option<SocketAddr>,
+    last_sequence: SequenceNumber,
+    entries: VecDeque<Entry>,
+}
+
+impl Default for Device {
+    fn default() -> Self {
+        Device {
+            addr: None,
+            last_sequence: SequenceNumber::new(0),
+            entries: VecDeque::new(),
+        }
+    }
+}
+
+/// Commands waiting for their LMU, keyed by Mobile ID.
+#[derive(Debug, Default)]
+pub struct CommandQueue {
+    next_id: u64,
+    devices: HashMap<MobileID, Device>,
+    finished: BTreeMap<CommandId, CommandState>,
+}
+
+impl CommandQueue {
+    pub fn new() -> Self {
+        CommandQueue::default()
+    }
+
+    /// Queue `command` for `mobile_id`.
+    pub fn push(&mut self, mobile_id: MobileID, command: Command) -> CommandId {
+        self.push_at(mobile_id, command, Instant::now())
+    }
+
+    pub fn push_at(
+        &mut self, mobile_id: MobileID, command: Command, now: Instant,
+    ) -> CommandId {
+        let id = CommandId(self.next_id);
+        self.next_id += 1;
+
+        let device = self.devices.entry(mobile_id).or_default();
+        // Zero tells the LMU not to check for repeats, it is skipped.
+        device.last_sequence = device.last_sequence.next();
+        device.entries.push_back(Entry {
+            id,
+            command,
+            sequence: device.last_sequence,
+            queued_at: now,
+            attempts: 0,
+            next_attempt: None,
+            completion: None,
+        });
+        id
+    }
+
+    /// A future resolving to the final state of `id`, `None` when the
+    /// command is unknown or its final state was drained.
+    pub fn completion(&mut self, id: CommandId) -> Option<Completion> {
+        let shared = Arc::new(Shared::default());
+        if let Some(state) = self.finished.get(&id) {
+            shared.complete(*state);
+            return Some(Completion(shared));
+        }
+
+        let entry = self
+            .devices
+            .values_mut()
+            .flat_map(|device| device.entries.iter_mut())
+            .find(|entry| entry.id == id)?;
+        let shared = entry.completion.get_or_insert(shared);
+        Some(Completion(Arc::clone(shared)))
+    }
+
+    /// `mobile_id` sent a message from `addr`: the commands to send it
+    /// now, in the order they were queued. Commands waiting for a retry are
+    /// sent again only when it is due.
+    pub fn on_contact(
+        &mut self, mobile_id: &MobileID, addr: SocketAddr,
+    ) -> Vec<Outbound> {
+        self.on_contact_at(mobile_id, addr, Instant::now())
+    }
+
+    pub fn on_contact_at(
+        &mut self, mobile_id: &MobileID, addr: SocketAddr, now: Instant,
+    ) -> Vec<Outbound> {
+        match self.devices.get_mut(mobile_id) {
+            Some(device) => device.addr = Some(addr),
+            None => return Vec::new(),
+        }
+        self.expire_device(mobile_id, now);
+        self.due(mobile_id, now)
+    }
+
+    /// Retransmissions due at `now`, to LMUs whose address is known.
+    pub fn poll(&mut self, now: Instant) -> Vec<Outbound> {
+        self.expire(now);
+        let mobile_ids: Vec<MobileID> = self.devices.keys().cloned().collect();
+        mobile_ids
+            .iter()
+            .flat_map(|mobile_id| self.due(mobile_id, now))
+            .collect()
+    }
+
+    /// Match an Ack/Nak from `msg` with the command it answers, by Mobile
+    /// ID, sequence number and message type. Returns the command, now
+    /// acked or nak'd.
+    pub fn on_ack_nak(&mut self, msg: &AckNakMessage) -> Option<CommandId> {
+        let mobile_id = msg.options_header.as_ref()?.mobile_id.as_ref()?;
+        let device = self.devices.get(mobile_id)?;
+        let sequence = msg.message_header.sequence_number;
+        let id = device
+            .entries
+            .iter()
+            .find(|entry| {
+                entry.sequence == sequence
+                    && entry.command.message_type == msg.ack_nak.message_type
+            })?
+            .id;
+
+        let state = match msg.ack_nak.ack {
+            0 => CommandState::Acked,
+            reason => CommandState::Nakd(reason),
+        };
+        self.finish(id, state);
+        Some(id)
+    }
+
+    /// The LMU acknowledged `id`. Returns false when it was not pending.
+    pub fn ack(&mut self, id: CommandId) -> bool {
+        self.finish(id, CommandState::Acked)
+    }
+
+    /// The LMU refused `id` with `reason`. Returns false when it was not
+    /// pending.
+    pub fn nak(&mut self, id: CommandId, reason: u8) -> bool {
+        self.finish(id, CommandState::Nakd(reason))
+    }
+
+    /// Expire the commands past their timeout or out of attempts,
+    /// returning them.
+    pub fn expire(&mut self, now: Instant) -> Vec<CommandId> {
+        let mobile_ids: Vec<MobileID> = self.devices.keys().cloned().collect();
+        mobile_ids
+            .iter()
+            .flat_map(|mobile_id| self.expire_device(mobile_id, now))
+            .collect()
+    }
+
+    /// State of `id`, until its final state is drained or it is one of the
+    /// oldest past the last 1024 finished commands.
+    pub fn state(&self, id: CommandId) -> Option<CommandState> {
+        if let Some(state) = self.finished.get(&id) {
+            return Some(*state);
+        }
+        self.devices
+            .values()
+            .flat_map(|device| device.entries.iter())
+            .find(|entry| entry.id == id)
+            .map(Entry::state)
+    }
+
+    /// Sequence number `id` is sent with, while it is pending.
+    pub fn sequence_number(&self, id: CommandId) -> Option<SequenceNumber> {
+        self.devices
+            .values()
+            .flat_map(|device| device.entries.iter())
+            .find(|entry| entry.id == id)
+            .map(|entry| entry.sequence)
+    }
+
+    /// Number of commands waiting for `mobile_id`.
+    pub fn pending(&self, mobile_id: &MobileID) -> usize {
+        self.devices
+            .get(mobile_id)
+            .map_or(0, |device| device.entries.len())
+    }
+
+    /// Take the commands that reached a final state, at most the last
+    /// 1024 of them.
+    pub fn drain_finished(&mut self) -> Vec<(CommandId, CommandState)> {
+        std::mem::take(&mut self.finished).into_iter().collect()
+    }
+
+    fn due(&mut self, mobile_id: &MobileID, now: Instant) -> Vec<Outbound> {
+        let device = match self.devices.get_mut(mobile_id) {
+            Some(device) => device,
+            None => return Vec::new(),
+        };
+        let addr = match device.addr {
+            Some(addr) => addr,
+            None => return Vec::new(),
+        };
+
+        device
+            .entries
+            .iter_mut()
+            .filter(|entry| entry.is_due(now))
+            .map(|entry| {
+                entry.attempt(now);
+                Outbound {
+                    id: entry.id,
+                    addr,
+                    bytes: encode(mobile_id, entry),
+                }
+            })
+            .collect()
+    }
+
+    fn finish(&mut self, id: CommandId, state: CommandState) -> bool {
+        for device in self.devices.values_mut() {
+            let index = match device.entries.iter().position(|e| e.id == id) {
+                Some(index) => index,
+                None => continue,
+            };
+            if let Some(entry) = device.entries.remove(index) {
+                self.record(entry, state);
+            }
+            return true;
+        }
+        false
+    }
+
+    fn expire_device(
+        &mut self, mobile_id: &MobileID, now: Instant,
+    ) -> Vec<CommandId> {
+        let device = match self.devices.get_mut(mobile_id) {
+            Some(device) => device,
+            None => return Vec::new(),
+        };
+
+        let (expired, alive) = device
+            .entries
+            .drain(..)
+            .partition(|entry| entry.is_expired(now));
+        device.entries = alive;
+
+        let expired: Vec<Entry> = expired.into_iter().collect();
+        expired
+            .into_iter()
+            .map(|entry| {
+                let id = entry.id;
+                self.record(entry, CommandState::Expired);
+                id
+            })
+            .collect()
+    }
+
+    fn record(&mut self, entry: Entry, state: CommandState) {
+        if let Some(completion) = entry.completion {
+            completion.complete(state);
+        }
+        self.finished.insert(entry.id, state);
+        if self.finished.len() > MAX_FINISHED {
+            self.finished.pop_first();
+        }
+    }
+}
+
+/// Nobody is left to answer the pending commands, they expire.
+impl Drop for CommandQueue {
+    fn drop(&mut self) {
+        for device in self.devices.values_mut() {
+            for entry in device.entries.drain(..) {
+                if let Some(completion) = entry.completion {
+                    completion.complete(CommandState::Expired);
+                }
+            }
+        }
+    }
+}
+
+/// The command as an Acknowledged Request addressed to `mobile_id`.
+fn encode(mobile_id: &MobileID, entry: &Entry) -> Vec<u8> {
+    let mut buf = Vec::new();
+    OptionsHeader {
+        mobile_id: Some(mobile_id.clone()),
+        mobile_id_type: None,
+        authentication_world: None,
+        routing: None,
+        forwarding: None,
+        response_redirection: None,
+        options_extension: None,
+    }
+    .encode(&mut buf);
+    MessageHeader {
+        service_type: ServiceType::Acknowledged,
+        message_type: entry.command.message_type,
+        sequence_number: entry.sequence,
+    }
+    .encode(&mut buf);
+    buf.extend_from_slice(&entry.command.body);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, CommandQueue, CommandState, MAX_FINISHED};
+    use crate::message_header::{
+        MessageHeader, MessageType, SequenceNumber, ServiceType,
+    };
+    use crate::messages::ack_nak::{AckNak, AckNakMessage};
+    use crate::options_header::{MobileID, OptionsHeader};
+    use std::future::Future;
+    use std::net::SocketAddr;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Wake, Waker};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn mobile_id() -> MobileID {
+        MobileID::new(&[0x46, 0x34, 0x66, 0x32, 0x35])
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([192, 0, 2, 1], port))
+    }
+
+    /// The Ack/Nak the LMU sends back for `request`.
+    fn reply(request: &[u8], ack_nak: AckNak) -> AckNakMessage {
+        let (i, options_header) = OptionsHeader::parse(request).unwrap();
+        let (_, message_header) = MessageHeader::parse(i).unwrap();
+        let mut buf = Vec::new();
+        ack_nak.encode_reply(
+            options_header.as_ref(),
+            &message_header,
+            &mut buf,
+        );
+        AckNakMessage::parse(&buf).unwrap()
+    }
+
+    #[test]
+    fn test_command_delivered_on_contact() {
+        let mut queue = CommandQueue::new();
+        let now = Instant::now();
+        let id = queue.push_at(
+            mobile_id(),
+            Command::new(MessageType::UnitRequest, &[0x01, 0x02]),
+            now,
+        );
+        assert_eq!(queue.state(id), Some(CommandState::Queued));
+        assert!(queue
+            .on_contact_at(&MobileID::new(&[0x01]), addr(1), now)
+            .is_empty());
+
+        let out = queue.on_contact_at(&mobile_id(), addr(2000), now);
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].id, id);
+        assert_eq!(out[0].addr, addr(2000));
+        let (i, options_header) = OptionsHeader::parse(&out[0].bytes).unwrap();
+        assert_eq!(options_header.unwrap().mobile_id, Some(mobile_id()));
+        let (i, header) = MessageHeader::parse(i).unwrap();
+        assert_eq!(header.service_type, ServiceType::Acknowledged);
+        assert_eq!(header.message_type, MessageType::UnitRequest);
+        assert_eq!(header.sequence_number, SequenceNumber::new(1));
+        assert_eq!(i, [0x01, 0x02]);
+        assert_eq!(queue.state(id), Some(CommandState::Sent { attempts: 1 }));
+
+        assert!(queue.ack(id));
+        assert!(!queue.ack(id));
+        assert_eq!(queue.state(id), Some(CommandState::Acked));
+        assert_eq!(queue.pending(&mobile_id()), 0);
+        assert_eq!(queue.drain_finished(), [(id, CommandState::Acked)]);
+        assert_eq!(queue.state(id), None);
+    }
+
+    #[test]
+    fn test_command_matched_by_sequence_and_type() {
+        let mut queue = CommandQueue::new();
+        let now = Instant::now();
+        let first = queue.push_at(
+            mobile_id(),
+            Command::new(MessageType::UnitRequest, &[]),
+            now,
+        );
+        let second = queue.push_at(
+            mobile_id(),
+            Command::new(MessageType::UserData, &[]),
+            now,
+        );
+        assert_eq!(queue.sequence_number(second), Some(SequenceNumber::new(2)));
+
+        let out = queue.on_contact_at(&mobile_id(), addr(1), now);
+        assert_eq!(out.len(), 2);
+
+        // Right sequence number, wrong type.
+        let wrong = reply(&out[1].bytes, AckNak::ack(MessageType::UnitRequest));
+        assert_eq!(queue.on_ack_nak(&wrong), None);
+
+        let nak = reply(&out[1].bytes, AckNak::nak(MessageType::UserData, 3));
+        assert_eq!(queue.on_ack_nak(&nak), Some(second));
+        let ack = reply(&out[0].bytes, AckNak::ack(MessageType::UnitRequest));
+        assert_eq!(queue.on_ack_nak(&ack), Some(first));
+        assert_eq!(queue.on_ack_nak(&ack), None);
+
+        assert_eq!(
+            queue.drain_finished(),
+            [
+                (first, CommandState::Acked),
+                (second, CommandState::Nakd(3))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_command_retransmitted_with_backoff() {
+        let mut queue = CommandQueue::new();
+        let now = Instant::now();
+        let secs = |n| now + Duration::from_secs(n);
+        let id = queue.push_at(
+            mobile_id(),
+            Command::new(MessageType::UnitRequest, &[])
+                .max_attempts(3)
+                .retry_interval(Duration::from_secs(10)),
+            now,
+        );
+        let completion = queue.completion(id).unwrap();
+
+        // Nothing is sent before the address of the LMU is known.
+        assert!(queue.poll(now).is_empty());
+        assert_eq!(queue.on_contact_at(&mobile_id(), addr(1), now).len(), 1);
+        assert!(queue.poll(secs(9)).is_empty());
+        assert_eq!(queue.poll(secs(10)).len(), 1);
+        // The second wait is twice as long.
+        assert!(queue.poll(secs(29)).is_empty());
+        assert_eq!(
+            queue.on_contact_at(&mobile_id(), addr(2), secs(30))[0].addr,
+            addr(2)
+        );
+        assert_eq!(queue.state(id), Some(CommandState::Sent { attempts: 3 }));
+        assert!(completion.try_get().is_none());
+
+        // Out of attempts, it expires once the last wait is over.
+        assert!(queue.poll(secs(69)).is_empty());
+        assert_eq!(queue.state(id), Some(CommandState::Sent { attempts: 3 }));
+        assert!(queue.poll(secs(70)).is_empty());
+        assert_eq!(queue.state(id), Some(CommandState::Expired));
+        assert_eq!(completion.wait(), CommandState::Expired);
+    }
+
+    #[test]
+    fn test_command_timeout() {
+        let mut queue = CommandQueue::new();
+        let now = Instant::now();
+        let id = queue.push_at(
+            mobile_id(),
+            Command::new(MessageType::UserData, &[0xff])
+                .timeout(Duration::from_secs(60)),
+            now,
+        );
+
+        let later = now + Duration::from_secs(61);
+        assert!(queue.on_contact_at(&mobile_id(), addr(1), later).is_empty());
+        assert_eq!(queue.state(id), Some(CommandState::Expired));
+        assert!(queue.state(id).unwrap().is_final());
+    }
+
+    #[test]
+    fn test_completion_from_another_thread() {
+        let mut queue = CommandQueue::new();
+        let id = queue.push(mobile_id(), Command::new(MessageType::Null, &[]));
+        let completion = queue.completion(id).unwrap();
+
+        let waiter = thread::spawn(move || completion.wait());
+        queue.nak(id, 1);
+        assert_eq!(waiter.join().unwrap(), CommandState::Nakd(1));
+        assert_eq!(
+            queue.completion(id).unwrap().try_get(),
+            Some(CommandState::Nakd(1))
+        );
+    }
+
+    #[test]
+    fn test_finished_states_are_capped() {
+        let mut queue = CommandQueue::new();
+        let ids: Vec<_> = (0..MAX_FINISHED + 10)
+            .map(|_| {
+                queue.push(mobile_id(), Command::new(MessageType::Null, &[]))
+            })
+            .collect();
+        for id in &ids {
+            assert!(queue.ack(*id));
+        }
+
+        assert_eq!(queue.state(ids[0]), None);
+        assert_eq!(queue.state(ids[10]), Some(CommandState::Acked));
+        let finished = queue.drain_finished();
+        assert_eq!(finished.len(), MAX_FINISHED);
+        assert_eq!(finished[0].0, ids[10]);
+    }
+
+    /// Counts its wake ups.
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_completion_clones_all_woken() {
+        let mut queue = CommandQueue::new();
+        let id = queue.push(mobile_id(), Command::new(MessageType::Null, &[]));
+        let mut first = queue.completion(id).unwrap();
+        let mut second = first.clone();
+
+        let wakers: Vec<_> = (0..2)
+            .map(|_| Arc::new(CountingWaker(AtomicUsize::new(0))))
+            .collect();
+        for (completion, counter) in
+            vec![&mut first, &mut second].into_iter().zip(&wakers)
+        {
+            let waker = Waker::from(Arc::clone(counter));
+            let mut cx = Context::from_waker(&waker);
+            assert!(Pin::new(completion).poll(&mut cx).is_pending());
+        }
+
+        queue.ack(id);
+        for counter in &wakers {
+            assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        }
+        assert_eq!(first.try_get(), Some(CommandState::Acked));
+    }
+}
@@ -0,0 +1,260 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Duplicate detection.
+//!
+//! An LMU that does not get the ACK of an Acknowledged Request sends the
+//! same message again, with the same Sequence Number. [`Dedup`] remembers
+//! the `(MobileID, MessageType, SequenceNumber)` seen during a time window
+//! and flags the copies. The numbers kept for one LMU and message type
+//! never reach half of the sequence circle, so a number reused after the
+//! counter rolls over is not mistaken for an old one.
+//!
+//! Messages without a Mobile ID, or with a zero Sequence Number, cannot be
+//! told apart and are always new.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::message_header::{MessageType, SequenceNumber};
+use crate::options_header::MobileID;
+use crate::Message;
+
+/// Numbers further behind the newest one than this are forgotten, the
+/// counter gets back to them only after a rollover.
+const MAX_BEHIND: u16 = u16::MAX / 4;
+
+/// Result of [`Dedup::check`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// First time the message is seen.
+    New,
+
+    /// A copy of a message seen within the window.
+    Duplicate,
+}
+
+#[derive(Debug)]
+struct Stream {
+    newest: SequenceNumber,
+    seen: HashMap<SequenceNumber, Instant>,
+}
+
+/// Remembers the messages seen during a time window.
+///
+/// Every window, the next check also [purges](Dedup::purge) the LMUs that
+/// went silent, so a long running server does not keep every Mobile ID it
+/// has ever seen.
+#[derive(Debug)]
+pub struct Dedup {
+    window: Duration,
+    streams: HashMap<(MobileID, MessageType), Stream>,
+    purged_at: Option<Instant>,
+}
+
+impl Dedup {
+    /// Flag copies arriving up to `window` after the first message.
+    pub fn new(window: Duration) -> Self {
+        Dedup {
+            window,
+            streams: HashMap::new(),
+            purged_at: None,
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Check `msg`, received now.
+    pub fn check(&mut self, msg: &Message) -> Verdict {
+        self.check_at(msg, Instant::now())
+    }
+
+    /// Check `msg`, received at `now`.
+    pub fn check_at(&mut self, msg: &Message, now: Instant) -> Verdict {
+        let mobile_id = match msg
+            .options_header
+            .as_ref()
+            .and_then(|options_header| options_header.mobile_id.as_ref())
+        {
+            Some(mobile_id) => mobile_id,
+            None => return Verdict::New,
+        };
+        let header = &msg.message_header;
+        self.check_key(
+            mobile_id,
+            header.message_type,
+            header.sequence_number,
+            now,
+        )
+    }
+
+    /// Check the message identified by `mobile_id`, `message_type` and
+    /// `sequence`, received at `now`.
+    pub fn check_key(
+        &mut self, mobile_id: &MobileID, message_type: MessageType,
+        sequence: SequenceNumber, now: Instant,
+    ) -> Verdict {
+        if sequence.data() == 0 {
+            return Verdict::New;
+        }
+
+        let window = self.window;
+        match self.purged_at {
+            Some(at) if now.saturating_duration_since(at) < window => {}
+            Some(_) => self.purge(now),
+            None => self.purged_at = Some(now),
+        }
+
+        let key = (mobile_id.clone(), message_type);
+        let stream = self.streams.entry(key).or_insert_with(|| Stream {
+            newest: sequence,
+            seen: HashMap::new(),
+        });
+
+        if sequence.is_newer_than(&stream.newest) {
+            stream.newest = sequence;
+        }
+        let newest = stream.newest;
+        stream.seen.retain(|seen, at| {
+            now.duration_since(*at) < window
+                && newest.distance_from(seen) <= MAX_BEHIND
+        });
+
+        if stream.seen.contains_key(&sequence) {
+            return Verdict::Duplicate;
+        }
+        if newest.distance_from(&sequence) <= MAX_BEHIND {
+            stream.seen.insert(sequence, now);
+        }
+        Verdict::New
+    }
+
+    /// Forget what is older than the window, so LMUs that went silent do
+    /// not hold memory. Checks call it once per window.
+    pub fn purge(&mut self, now: Instant) {
+        self.purged_at = Some(now);
+        let window = self.window;
+        self.streams.retain(|_, stream| {
+            stream.seen.retain(|_, at| now.duration_since(*at) < window);
+            !stream.seen.is_empty()
+        });
+    }
+
+    /// Number of messages remembered.
+    pub fn len(&self) -> usize {
+        self.streams.values().map(|stream| stream.seen.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dedup, Verdict};
+    use crate::message_header::{MessageType, SequenceNumber};
+    use crate::options_header::MobileID;
+    use std::time::{Duration, Instant};
+
+    fn check(dedup: &mut Dedup, seq: u16, now: Instant) -> Verdict {
+        dedup.check_key(
+            &MobileID::new(&[0x46, 0x34, 0x66, 0x32, 0x35]),
+            MessageType::EventReport,
+            SequenceNumber::new(seq),
+            now,
+        )
+    }
+
+    #[test]
+    fn test_dedup_window() {
+        let mut dedup = Dedup::new(Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert_eq!(check(&mut dedup, 10, start), Verdict::New);
+        assert_eq!(check(&mut dedup, 11, start), Verdict::New);
+        assert_eq!(check(&mut dedup, 10, start), Verdict::Duplicate);
+        assert_eq!(
+            check(&mut dedup, 10, start + Duration::from_secs(59)),
+            Verdict::Duplicate
+        );
+        assert_eq!(
+            check(&mut dedup, 10, start + Duration::from_secs(61)),
+            Verdict::New
+        );
+
+        // Another message type has its own numbers.
+        assert_eq!(
+            dedup.check_key(
+                &MobileID::new(&[0x46, 0x34, 0x66, 0x32, 0x35]),
+                MessageType::UserData,
+                SequenceNumber::new(11),
+                start,
+            ),
+            Verdict::New
+        );
+        assert_eq!(check(&mut dedup, 0, start), Verdict::New);
+        assert_eq!(check(&mut dedup, 0, start), Verdict::New);
+    }
+
+    #[test]
+    fn test_dedup_rollover() {
+        let mut dedup = Dedup::new(Duration::from_secs(3600));
+        let now = Instant::now();
+
+        for seq in 65530..=65535 {
+            assert_eq!(check(&mut dedup, seq, now), Verdict::New);
+        }
+        assert_eq!(check(&mut dedup, 1, now), Verdict::New);
+        assert_eq!(check(&mut dedup, 65534, now), Verdict::Duplicate);
+        assert_eq!(check(&mut dedup, 1, now), Verdict::Duplicate);
+
+        // Half the circle later the old numbers are forgotten, even within
+        // the window, and come back as new messages.
+        for seq in (2..40000).step_by(1000) {
+            check(&mut dedup, seq, now);
+        }
+        assert_eq!(check(&mut dedup, 65534, now), Verdict::New);
+    }
+
+    #[test]
+    fn test_dedup_purge() {
+        let mut dedup = Dedup::new(Duration::from_secs(10));
+        let start = Instant::now();
+        check(&mut dedup, 1, start);
+        check(&mut dedup, 2, start);
+        assert_eq!(dedup.len(), 2);
+
+        dedup.purge(start + Duration::from_secs(11));
+        assert!(dedup.is_empty());
+    }
+
+    #[test]
+    fn test_dedup_purges_silent_lmus() {
+        let mut dedup = Dedup::new(Duration::from_secs(10));
+        let start = Instant::now();
+        check(&mut dedup, 1, start);
+        assert_eq!(dedup.streams.len(), 1);
+
+        // Another LMU a window later, the first one is forgotten.
+        let other = MobileID::new(&[0x01, 0x02, 0x03]);
+        let later = start + Duration::from_secs(11);
+        let seq = SequenceNumber::new(1);
+        dedup.check_key(&other, MessageType::EventReport, seq, later);
+        assert_eq!(dedup.streams.len(), 1);
+        assert_eq!(dedup.len(), 1);
+    }
+}
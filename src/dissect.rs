@@ -0,0 +1,613 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Field level dissection.
+//!
+//! [`dissect`] walks a message the way the parser does and returns one
+//! [`FieldSpan`] per field, giving the bytes, or bits, each value was read
+//! from. It never fails: a message cut short ends with a
+//! [`SpanKind::Truncated`] span and bytes left after the body with a
+//! [`SpanKind::Trailing`] one. [`render`] prints the spans as a tree next
+//! to their hex, Wireshark style.
+//!
+//! ```
+//! use calamp_rs::dissect::{dissect, render};
+//!
+//! let spans = dissect(&[0x00, 0x01, 0x3a, 0x86]);
+//! print!("{}", render(&spans, false));
+//! ```
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::ops::Range;
+
+use crate::layout::{self, BitDecode, BitField, Decode, Field};
+use crate::message_header::{MessageType, ServiceType};
+use crate::messages::event_report::NetworkTechnology;
+use crate::options_header::{MobileID, MobileIDType, OPTIONS_HEADER_FLAG};
+use crate::timestamp::Timestamp;
+
+/// What a [`FieldSpan`] covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanKind {
+    /// A header, body or option made of the fields that follow it.
+    Group,
+
+    /// A field read from whole bytes.
+    Field,
+
+    /// Bits of the field before it.
+    Bits,
+
+    /// Bytes left after the message.
+    Trailing,
+
+    /// The bytes left when the message ends before a field does.
+    Truncated,
+}
+
+/// A field of a dissected message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSpan {
+    pub name: &'static str,
+    pub kind: SpanKind,
+
+    /// Nesting level, 0 for the headers and the body.
+    pub depth: usize,
+
+    /// Offset of the first byte in the message.
+    pub offset: usize,
+
+    /// Bits of the byte the value was read from, bit 0 being the least
+    /// significant one. Only set for [`SpanKind::Bits`].
+    pub bits: Option<Range<u8>>,
+
+    /// The bytes of the field, the whole byte for bits.
+    pub raw: Vec<u8>,
+
+    /// The decoded value, empty for groups.
+    pub value: String,
+}
+
+impl FieldSpan {
+    pub fn len(&self) -> usize {
+        self.raw.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+
+    /// Byte range of the field in the message.
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.raw.len()
+    }
+}
+
+/// Dissect `input`, a message as received.
+///
+/// Only Event Report and Ack/Nak bodies are dissected field by field,
+/// other bodies are a single `body` span.
+pub fn dissect(input: &[u8]) -> Vec<FieldSpan> {
+    let mut d = Dissector {
+        input,
+        pos: 0,
+        depth: 0,
+        spans: Vec::new(),
+    };
+    if d.message().is_ok() && d.pos < input.len() {
+        let rest = &input[d.pos..];
+        d.pos = input.len();
+        d.push(
+            "trailing",
+            SpanKind::Trailing,
+            rest,
+            None,
+            format!("{} bytes after the message", rest.len()),
+        );
+    }
+    d.spans
+}
+
+/// The message ended before the field being read.
+struct Truncated;
+
+type Result<T> = core::result::Result<T, Truncated>;
+
+struct Dissector<'a> {
+    input: &'a [u8],
+    pos: usize,
+    depth: usize,
+    spans: Vec<FieldSpan>,
+}
+
+impl<'a> Dissector<'a> {
+    fn push(
+        &mut self, name: &'static str, kind: SpanKind, raw: &[u8],
+        bits: Option<Range<u8>>, value: String,
+    ) {
+        let offset = match kind {
+            SpanKind::Bits => self.pos - 1,
+            _ => self.pos - raw.len(),
+        };
+        self.spans.push(FieldSpan {
+            name,
+            kind,
+            depth: self.depth,
+            offset,
+            bits,
+            raw: raw.to_vec(),
+            value,
+        });
+    }
+
+    /// Read a `len` bytes field, its value given by `value`.
+    fn field<F>(
+        &mut self, name: &'static str, len: usize, value: F,
+    ) -> Result<&'a [u8]>
+    where
+        F: FnOnce(&[u8]) -> String,
+    {
+        let input = self.input;
+        let rest = &input[self.pos..];
+        if rest.len() < len {
+            self.pos = input.len();
+            self.push(
+                name,
+                SpanKind::Truncated,
+                rest,
+                None,
+                format!("truncated, {} more bytes needed", len - rest.len()),
+            );
+            return Err(Truncated);
+        }
+        let raw = &rest[..len];
+        self.pos += len;
+        self.push(name, SpanKind::Field, raw, None, value(raw));
+        Ok(raw)
+    }
+
+    /// Read `fields`, returning their bytes.
+    fn fields(&mut self, fields: &[Field]) -> Result<&'a [u8]> {
+        let start = self.pos;
+        for field in fields {
+            let raw = self
+                .field(field.name, field.len, |raw| value(field.decode, raw))?;
+            if let Decode::Bits(bits) = field.decode {
+                self.bits(raw[0], bits);
+            }
+        }
+        Ok(&self.input[start..self.pos])
+    }
+
+    /// The bit fields of `byte`, the field just read, as children of it.
+    fn bits(&mut self, byte: u8, bits: &[BitField]) {
+        self.depth += 1;
+        for bit in bits {
+            self.push(
+                bit.name,
+                SpanKind::Bits,
+                &[byte],
+                Some(bit.shift..bit.shift + bit.width),
+                bit_value(bit, byte),
+            );
+        }
+        self.depth -= 1;
+    }
+
+    /// Dissect the fields read by `f` as children of a `name` group.
+    fn group<T, F>(&mut self, name: &'static str, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        let start = self.pos;
+        let index = self.spans.len();
+        self.push(name, SpanKind::Group, &[], None, String::new());
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+        self.spans[index].raw = self.input[start..self.pos].to_vec();
+        res
+    }
+
+    /// A length byte and as many bytes of `value`.
+    fn sized<F>(&mut self, value: &'static str, f: F) -> Result<&'a [u8]>
+    where
+        F: FnOnce(&[u8]) -> String,
+    {
+        let len = self.field("length", 1, |raw| raw[0].to_string())?[0];
+        self.field(value, usize::from(len), f)
+    }
+
+    fn message(&mut self) -> Result<()> {
+        if self
+            .input
+            .first()
+            .is_some_and(|b| b & OPTIONS_HEADER_FLAG != 0)
+        {
+            self.group("options_header", Self::options_header)?;
+        }
+        let header = self
+            .group("message_header", |d| d.fields(&layout::MESSAGE_HEADER))?;
+        let offset = layout::offset(&layout::MESSAGE_HEADER, "message_type");
+        match MessageType::from_u8(header[offset]) {
+            MessageType::EventReport => {
+                self.group("event_report", Self::event_report)
+            }
+            MessageType::AckNak => self
+                .group("ack_nak", |d| d.fields(&layout::ACK_NAK).map(|_| ())),
+            _ => {
+                let len = self.input.len() - self.pos;
+                if len > 0 {
+                    self.field("body", len, |_| "not dissected".to_string())?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn options_header(&mut self) -> Result<()> {
+        let options = self.fields(&layout::OPTIONS)?[0];
+
+        let mut mobile_id = None;
+        for (bit, (name, field)) in layout::OPTION_FIELDS.iter().enumerate() {
+            if options & 1 << bit == 0 {
+                continue;
+            }
+            let raw = self.group(name, |d| {
+                d.sized(field.name, |raw| {
+                    if field.len == 0 || raw.len() == field.len {
+                        value(field.decode, raw)
+                    } else {
+                        format!("{}, expected {} bytes", hex(raw), field.len)
+                    }
+                })
+            })?;
+
+            match (*name, mobile_id.take()) {
+                ("mobile_id", _) => {
+                    mobile_id = Some((self.spans.len() - 1, MobileID::new(raw)))
+                }
+                // The Mobile ID comes first, it is decoded once its type is
+                // known.
+                ("mobile_id_type", Some((index, mobile_id))) => {
+                    let mobile_id_type = match raw {
+                        [b] => MobileIDType::from_u8(*b),
+                        _ => continue,
+                    };
+                    let decoded = mobile_id.decode(&mobile_id_type);
+                    let span = &mut self.spans[index];
+                    if decoded != span.value {
+                        span.value = format!("{} ({})", span.value, decoded);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn event_report(&mut self) -> Result<()> {
+        let fixed = self.fields(&layout::EVENT_REPORT)?;
+        let accums = fixed[layout::EVENT_REPORT_ACCUMS];
+        let append = fixed[layout::EVENT_REPORT_APPEND];
+        let count = layout::ACCUMS_BITS[0].get(accums);
+
+        if count != 0 {
+            self.group("accumulators", |d| {
+                let accumulator = layout::ACCUMULATOR;
+                for n in 0..count {
+                    d.field(accumulator.name, accumulator.len, |raw| {
+                        format!("[{}] {}", n, be(raw))
+                    })?;
+                }
+                Ok(())
+            })?;
+        }
+        if append != 0 {
+            self.group("appended", |d| {
+                for _ in 0..append.count_ones() {
+                    d.group("block", |d| d.sized("data", hex))?;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// The value of a field read from `raw`.
+fn value(decode: Decode, raw: &[u8]) -> String {
+    let unsigned = be(raw);
+
+    match decode {
+        Decode::Unsigned => unsigned.to_string(),
+        Decode::Hex => hex(raw),
+        Decode::Timestamp => Timestamp::new(unsigned).to_string(),
+        Decode::Scaled {
+            signed: is_signed,
+            factor,
+            decimals,
+            unit,
+        } => {
+            let n = if is_signed {
+                // Sign extend from the field length.
+                let bits = 32 - 8 * raw.len() as u32;
+                f64::from(((unsigned << bits) as i32) >> bits)
+            } else {
+                f64::from(unsigned)
+            };
+            format!("{:.*}{}", decimals, n * factor, unit)
+        }
+        Decode::ServiceType => ServiceType::from_u8(raw[0]).to_string(),
+        Decode::MessageType => MessageType::from_u8(raw[0]).to_string(),
+        Decode::MobileIDType => MobileIDType::from_u8(raw[0]).to_string(),
+        Decode::Ack => match layout::NAK_REASONS.get(usize::from(raw[0])) {
+            Some(reason) => reason.to_string(),
+            None => format!("NAK, reason {}", raw[0]),
+        },
+        Decode::Bits(_) => format!("{:#04x}", raw[0]),
+    }
+}
+
+fn bit_value(bit: &BitField, byte: u8) -> String {
+    let value = bit.get(byte);
+    match bit.decode {
+        BitDecode::Flag => (value != 0).to_string(),
+        BitDecode::ClearFlag => (value == 0).to_string(),
+        BitDecode::Number => value.to_string(),
+        BitDecode::NetworkTechnology => {
+            NetworkTechnology::parse(value).to_string()
+        }
+    }
+}
+
+/// Big endian unsigned value of up to 4 bytes.
+fn be(raw: &[u8]) -> u32 {
+    raw.iter().fold(0, |acc, b| acc << 8 | u32::from(*b))
+}
+
+fn hex(raw: &[u8]) -> String {
+    raw.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Bytes shown on one line of [`render`].
+const BYTES_PER_LINE: usize = 8;
+
+/// Width of the hex column of [`render`].
+const HEX_WIDTH: usize = BYTES_PER_LINE * 3 - 1;
+
+/// Colours of the fields, in turn.
+const PALETTE: [&str; 6] = [
+    "\x1b[32m", "\x1b[33m", "\x1b[34m", "\x1b[35m", "\x1b[36m", "\x1b[37m",
+];
+const BOLD: &str = "\x1b[1m";
+const ALERT: &str = "\x1b[1;37;41m";
+const RESET: &str = "\x1b[0m";
+
+/// Print `spans` as a tree, each field next to its offset and its hex, or
+/// its bits for bit fields.
+///
+/// With `color` the fields get ANSI colours, the bits the colour of their
+/// byte, and trailing or truncated bytes are shown in white on red.
+pub fn render(spans: &[FieldSpan], color: bool) -> String {
+    let mut out = String::new();
+    let mut field = 0;
+    let mut paint = "";
+
+    for span in spans {
+        paint = match span.kind {
+            SpanKind::Group => BOLD,
+            SpanKind::Field => {
+                field += 1;
+                PALETTE[(field - 1) % PALETTE.len()]
+            }
+            SpanKind::Bits => paint,
+            SpanKind::Trailing | SpanKind::Truncated => ALERT,
+        };
+        let (start, end) = if color { (paint, RESET) } else { ("", "") };
+
+        let mut chunks = span.raw.chunks(BYTES_PER_LINE);
+        let first = match (span.kind, &span.bits) {
+            (SpanKind::Group, _) => String::new(),
+            (SpanKind::Bits, Some(bits)) => bit_pattern(span.raw[0], bits),
+            _ => chunks.next().map(hex_line).unwrap_or_default(),
+        };
+        let label = match span.kind {
+            SpanKind::Group => format!("{} ({} bytes)", span.name, span.len()),
+            _ => format!("{}: {}", span.name, span.value),
+        };
+        let _ = writeln!(
+            out,
+            "{:04x}  {}{:<width$}{}  {:indent$}{}{}{}",
+            span.offset,
+            start,
+            first,
+            end,
+            "",
+            start,
+            label,
+            end,
+            width = HEX_WIDTH,
+            indent = span.depth * 2,
+        );
+
+        // Fields longer than a line go on below.
+        if !matches!(span.kind, SpanKind::Group | SpanKind::Bits) {
+            for (n, chunk) in chunks.enumerate() {
+                let _ = writeln!(
+                    out,
+                    "{:04x}  {}{}{}",
+                    span.offset + (n + 1) * BYTES_PER_LINE,
+                    start,
+                    hex_line(chunk),
+                    end,
+                );
+            }
+        }
+    }
+    out
+}
+
+fn hex_line(raw: &[u8]) -> String {
+    let bytes: Vec<String> = raw.iter().map(|b| format!("{:02x}", b)).collect();
+    bytes.join(" ")
+}
+
+/// `byte` with the bits outside `bits` as dots, e.g. `..1. ....`.
+fn bit_pattern(byte: u8, bits: &Range<u8>) -> String {
+    let mut pattern = String::new();
+    for bit in (0..8).rev() {
+        pattern.push(match (bits.contains(&bit), byte & 1 << bit != 0) {
+            (false, _) => '.',
+            (true, true) => '1',
+            (true, false) => '0',
+        });
+        if bit == 4 {
+            pattern.push(' ');
+        }
+    }
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dissect, render, FieldSpan, SpanKind};
+    use crate::fixtures::DATA;
+
+    fn find<'a>(spans: &'a [FieldSpan], name: &str) -> &'a FieldSpan {
+        spans
+            .iter()
+            .find(|span| span.name == name && span.kind != SpanKind::Bits)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_dissect_event_report() {
+        let spans = dissect(&DATA);
+
+        // The fields follow each other and cover the whole message.
+        let mut offset = 0;
+        for span in spans.iter().filter(|span| span.kind == SpanKind::Field) {
+            assert_eq!(span.offset, offset, "{}", span.name);
+            assert_eq!(span.raw, DATA[span.range()]);
+            offset += span.len();
+        }
+        assert_eq!(offset, DATA.len());
+        assert!(spans.iter().all(|span| span.kind != SpanKind::Trailing));
+
+        let header = find(&spans, "options_header");
+        assert_eq!((header.kind, header.range()), (SpanKind::Group, 0..9));
+        let mobile_id = find(&spans, "mobile_id");
+        assert_eq!(mobile_id.range(), 1..7);
+        assert_eq!(spans[12].name, "value");
+        assert_eq!(spans[12].value, "4634663235");
+
+        let routing = &spans[2 + 3];
+        assert_eq!(routing.name, "routing");
+        assert_eq!(routing.kind, SpanKind::Bits);
+        assert_eq!((routing.offset, routing.bits.clone()), (0, Some(3..4)));
+        assert_eq!(routing.value, "false");
+
+        assert_eq!(find(&spans, "sequence_number").value, "14982");
+        assert_eq!(find(&spans, "update_time").value, "2021-01-03T03:30:28Z");
+        assert_eq!(find(&spans, "satellites").value, "6");
+        let technology = spans
+            .iter()
+            .find(|span| span.name == "network_technology")
+            .unwrap();
+        assert_eq!(technology.bits, Some(6..8));
+        assert_eq!(find(&spans, "accumulator").value, "[0] 12768");
+    }
+
+    #[test]
+    fn test_dissect_trailing_and_truncated() {
+        let mut data = DATA.to_vec();
+        data.extend_from_slice(&[0xde, 0xad]);
+        let spans = dissect(&data);
+        let last = spans.last().unwrap();
+        assert_eq!(last.kind, SpanKind::Trailing);
+        assert_eq!((last.offset, last.raw.clone()), (117, vec![0xde, 0xad]));
+
+        let spans = dissect(&DATA[..15]);
+        let last = spans.last().unwrap();
+        assert_eq!(last.kind, SpanKind::Truncated);
+        assert_eq!(last.name, "update_time");
+        assert_eq!(last.range(), 13..15);
+        assert_eq!(last.value, "truncated, 2 more bytes needed");
+        // The groups end where the message does.
+        assert_eq!(find(&spans, "event_report").range(), 13..15);
+
+        let spans = dissect(&[0x00, 0x04, 0x00, 0x01, 0xaa]);
+        assert_eq!(find(&spans, "body").value, "not dissected");
+    }
+
+    #[test]
+    fn test_dissect_mobile_id_type_length() {
+        let spans = dissect(&[0x82, 0x02, 0x01, 0x00, 0x00, 0x07, 0x00, 0x01]);
+        assert_eq!(find(&spans, "mobile_id_type").range(), 1..4);
+        assert_eq!(find(&spans, "value").value, "0100, expected 1 bytes");
+        let message_type = find(&spans, "message_type");
+        assert_eq!(message_type.value, "MessageType::UnitRequest");
+    }
+
+    #[test]
+    fn test_render() {
+        let spans = dissect(&DATA[..20]);
+        let text = render(&spans, false);
+        let lines: Vec<&str> = text.lines().collect();
+        let line = |offset: &str, hex: &str, depth: usize, label: &str| {
+            format!(
+                "{}  {:<23}  {:indent$}{}",
+                offset,
+                hex,
+                "",
+                label,
+                indent = depth * 2
+            )
+        };
+
+        assert_eq!(lines[0], line("0000", "", 0, "options_header (9 bytes)"));
+        assert_eq!(lines[1], line("0000", "83", 1, "options: 0x83"));
+        assert_eq!(lines[2], line("0000", ".... ...1", 2, "mobile_id: true"));
+        assert_eq!(lines[9], line("0000", "1... ....", 2, "always_set: true"));
+        assert_eq!(
+            lines[12],
+            line("0002", "46 34 66 32 35", 2, "value: 4634663235")
+        );
+        assert_eq!(
+            lines[21],
+            line(
+                "000d",
+                "5f f1 3a 54",
+                1,
+                "update_time: 2021-01-03T03:30:28Z"
+            )
+        );
+        assert_eq!(
+            lines[22],
+            line(
+                "0011",
+                "5f f1 3a",
+                1,
+                "time_of_fix: truncated, 1 more bytes needed"
+            )
+        );
+
+        let colored = render(&spans, true);
+        assert!(colored.contains("\x1b[1;37;41m5f f1 3a"));
+    }
+}
@@ -0,0 +1,74 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+use core::fmt;
+
+use crate::message_header::MessageType;
+
+/// Errors returned by the calamp parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The raw value does not match any variant known to this crate.
+    UnknownValue {
+        /// Name of the enum the value was converted to.
+        kind: &'static str,
+
+        /// The raw value.
+        value: u8,
+    },
+
+    /// The input ended before the message was complete.
+    Incomplete {
+        /// Minimum number of bytes still missing.
+        needed: usize,
+    },
+
+    /// The message type has no decoder in this crate.
+    UnsupportedMessageType(MessageType),
+
+    /// The time is zero or cannot be represented as a date.
+    InvalidTimestamp(u32),
+
+    /// The bytes at `offset` do not form a valid field.
+    Malformed {
+        /// Offset from the start of the message.
+        offset: usize,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Error::UnknownValue { kind, value } => {
+                write!(f, "unknown {} value: {}", kind, value)
+            }
+            Error::Incomplete { needed } => {
+                write!(f, "incomplete message, {} more bytes needed", needed)
+            }
+            Error::UnsupportedMessageType(message_type) => {
+                write!(f, "unsupported message type: {}", message_type)
+            }
+            Error::InvalidTimestamp(secs) => {
+                write!(f, "invalid timestamp: {}", secs)
+            }
+            Error::Malformed { offset } => {
+                write!(f, "malformed message at offset {}", offset)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
@@ -0,0 +1,277 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Message framing for byte streams.
+//!
+//! Over UDP every datagram holds one message. When LMDirect is tunneled over
+//! TCP or a serial link the boundaries are lost, [`Framer`] buffers the
+//! incoming chunks and cuts them back into messages using the length implied
+//! by the message itself.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use nom::bytes::streaming::take;
+use nom::IResult;
+
+use crate::error::Error;
+use crate::layout::{
+    EVENT_REPORT_ACCUMS, EVENT_REPORT_APPEND, EVENT_REPORT_LEN,
+};
+use crate::message_header::MessageType;
+use crate::messages::ack_nak::ACK_NAK_LEN;
+use crate::options_header::{OPTIONS_HEADER, OPTIONS_HEADER_FLAG};
+use crate::utils;
+use crate::Message;
+
+fn length_field(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    let (i, len) = utils::pu8(input)?;
+    take(len)(i)
+}
+
+/// Options header, when there is one, and message header, up to the
+/// message body.
+fn header(input: &[u8]) -> IResult<&[u8], MessageType> {
+    let (mut i, options) = utils::pu8(input)?;
+    if options & OPTIONS_HEADER_FLAG == 0 {
+        i = input;
+    } else {
+        // Option fields are length prefixed, in the order of the bits set.
+        for bit in 0..7 {
+            if options & (1 << bit) != 0 {
+                i = length_field(i)?.0;
+            }
+        }
+    }
+
+    let (i, _service_type) = utils::pu8(i)?;
+    let (i, message_type) = MessageType::parse(i)?;
+    let (i, _sequence_number) = utils::pu16(i)?;
+    Ok((i, message_type))
+}
+
+fn event_report(input: &[u8]) -> IResult<&[u8], ()> {
+    let (i, fixed) = take(EVENT_REPORT_LEN)(input)?;
+    let accums = fixed[EVENT_REPORT_ACCUMS];
+    let append = fixed[EVENT_REPORT_APPEND];
+    let (mut i, _) = take(usize::from(accums & 0x3f) * 4)(i)?;
+    // Appended data blocks are length prefixed, in the order of the bits
+    // set in the Append byte.
+    for bit in 0..8 {
+        if append & (1 << bit) != 0 {
+            i = length_field(i)?.0;
+        }
+    }
+    Ok((i, ()))
+}
+
+/// Length of the message at the start of `input`.
+///
+/// Returns [`Error::Incomplete`] with the number of bytes still missing when
+/// `input` holds only part of the message, and
+/// [`Error::UnsupportedMessageType`] when the length of the message type
+/// cannot be known.
+pub fn frame_length(input: &[u8]) -> Result<usize, Error> {
+    let err = |e| utils::error(input, e);
+
+    let (i, message_type) = header(input).map_err(err)?;
+    let rest = match message_type {
+        MessageType::Null => i,
+        MessageType::AckNak => take(ACK_NAK_LEN)(i).map_err(err)?.0,
+        MessageType::EventReport => event_report(i).map_err(err)?.0,
+        other => return Err(Error::UnsupportedMessageType(other)),
+    };
+    Ok(input.len() - rest.len())
+}
+
+/// Offset of the first options header marker in `input`, or its length when
+/// there is none.
+pub(crate) fn sync(input: &[u8]) -> usize {
+    input
+        .iter()
+        .position(|b| *b == OPTIONS_HEADER)
+        .unwrap_or(input.len())
+}
+
+/// Result of [`Framer::decode`].
+#[derive(Debug)]
+pub enum Frame {
+    /// A complete message.
+    Message(Box<Message>),
+
+    /// At least this many more bytes are needed for the next message.
+    Needed(usize),
+}
+
+/// Cuts a byte stream into LMDirect messages.
+///
+/// Bytes before the options header marker (0x83) are discarded, so the
+/// framer resynchronises by itself after garbage on the link. A complete
+/// frame that can not be parsed is dropped and its error returned, the
+/// next call carries on with the following bytes.
+#[derive(Debug, Default)]
+pub struct Framer {
+    buf: Vec<u8>,
+    discarded: usize,
+}
+
+impl Framer {
+    pub fn new() -> Self {
+        Framer::default()
+    }
+
+    /// Append a chunk read from the stream.
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.buf.extend_from_slice(chunk);
+    }
+
+    /// Bytes buffered and not yet returned as a message.
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Total bytes dropped while looking for the start of a message.
+    pub fn discarded(&self) -> usize {
+        self.discarded
+    }
+
+    /// Decode the next message from the buffered bytes.
+    pub fn decode(&mut self) -> Result<Frame, Error> {
+        self.resync();
+        if self.buf.is_empty() {
+            return Ok(Frame::Needed(1));
+        }
+
+        let len = match frame_length(&self.buf) {
+            Ok(len) => len,
+            Err(Error::Incomplete { needed }) => {
+                return Ok(Frame::Needed(needed))
+            }
+            Err(e) => {
+                // Not a message we can frame: skip the marker and let the
+                // next call look for another one.
+                self.drop_front(1);
+                return Err(e);
+            }
+        };
+
+        let msg = Message::parse(&self.buf[..len]);
+        self.buf.drain(..len);
+        msg.map(|msg| Frame::Message(Box::new(msg)))
+    }
+
+    /// Drop everything before the next options header marker.
+    fn resync(&mut self) {
+        self.drop_front(sync(&self.buf));
+    }
+
+    fn drop_front(&mut self, n: usize) {
+        self.discarded += n;
+        self.buf.drain(..n);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{frame_length, Frame, Framer};
+    use crate::error::Error;
+    use crate::fixtures::DATA;
+    use crate::message_header::MessageType;
+
+    fn expect_message(framer: &mut Framer) {
+        match framer.decode() {
+            Ok(Frame::Message(msg)) => {
+                assert_eq!(msg.message_header.sequence_number.data(), 14982)
+            }
+            other => panic!("expected a message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_frame_length() {
+        assert_eq!(frame_length(&DATA), Ok(117));
+        assert_eq!(
+            frame_length(&DATA[..60]),
+            Err(Error::Incomplete { needed: 57 })
+        );
+        assert_eq!(
+            frame_length(&[0x83, 0x01, 0x01, 0x01, 0x01, 0x00, 0x07, 0x00]),
+            Err(Error::Incomplete { needed: 1 })
+        );
+        assert_eq!(
+            frame_length(&[0x83, 0x01, 0x01, 0x01, 0x01, 0x00, 0x07, 0x00, 1]),
+            Err(Error::UnsupportedMessageType(MessageType::UnitRequest))
+        );
+        // Without options header.
+        assert_eq!(frame_length(&DATA[9..]), Ok(108));
+        // The Mobile ID Type is as long as its length byte says.
+        assert_eq!(
+            frame_length(&[0x82, 0x02, 0x01, 0x00, 0x00, 0x07, 0x00, 0x01]),
+            Err(Error::UnsupportedMessageType(MessageType::UnitRequest))
+        );
+    }
+
+    #[test]
+    fn test_framer_byte_by_byte() {
+        let mut framer = Framer::new();
+        for b in DATA[..116].iter() {
+            framer.push(&[*b]);
+            match framer.decode() {
+                Ok(Frame::Needed(n)) => assert!(n >= 1),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        framer.push(&DATA[116..]);
+        expect_message(&mut framer);
+        assert_eq!(framer.buffered(), 0);
+    }
+
+    #[test]
+    fn test_framer_needed() {
+        let mut framer = Framer::new();
+        framer.push(&DATA[..100]);
+        match framer.decode() {
+            Ok(Frame::Needed(n)) => assert_eq!(n, 17),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_framer_resync_after_garbage() {
+        let mut framer = Framer::new();
+        framer.push(&[0x00, 0xff, 0x12]);
+        framer.push(&DATA);
+        framer.push(&[0x42, 0x42]);
+        framer.push(&DATA[..50]);
+        framer.push(&DATA[50..]);
+
+        expect_message(&mut framer);
+        expect_message(&mut framer);
+        assert_eq!(framer.discarded(), 5);
+        assert!(matches!(framer.decode(), Ok(Frame::Needed(1))));
+    }
+
+    #[test]
+    fn test_framer_skips_unsupported() {
+        let mut framer = Framer::new();
+        framer.push(&[0x83, 0x01, 0x01, 0x01, 0x01, 0x00, 0x07, 0x00, 0x01]);
+        framer.push(&DATA);
+
+        assert_eq!(
+            framer.decode().unwrap_err(),
+            Error::UnsupportedMessageType(MessageType::UnitRequest)
+        );
+        expect_message(&mut framer);
+    }
+}
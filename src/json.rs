@@ -0,0 +1,256 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Stable JSON representation of messages.
+//!
+//! The `serde` derives follow the Rust types, which change with the crate.
+//! [`MessageJson`] is the shape meant for logs and pipelines instead, one
+//! object per message, written on one line by [`Message::to_json_line`].
+//! Fields are only ever added within a schema version, a change to an
+//! existing field bumps [`SCHEMA_VERSION`].
+//!
+//! ```json
+//! {"schema_version":1,"message_type":"event_report",
+//!  "service_type":"acknowledged","sequence_number":14982,
+//!  "mobile_id":"4634663235","mobile_id_type":"esn",
+//!  "update_time":"2021-01-03T03:30:28Z",
+//!  "time_of_fix":"2021-01-03T03:30:31Z",
+//!  "position":{"latitude":-23.6812936,"longitude":-46.7478976,
+//!   "altitude":796.08,"speed":0.396,"heading":0,"satellites":6,"hdop":3.0},
+//!  "fix_status":{"predicted":false,...,"twod_fix":true,...},
+//!  "comm_state":{"available":false,...,"network_technology":"cdma_gsm"},
+//!  "rssi":-115,"carrier":0,"inputs":{"ignition":false,...},
+//!  "unit_status":{"ota_update":true,...},"event_index":123,
+//!  "event_code":33,"accumulators":[12768,4122,...],"appended":""}
+//! ```
+//!
+//! - Names of message types, service types, Mobile ID types and network
+//!   technologies are snake case, `unknown_<n>` for values this crate
+//!   does not know.
+//! - `mobile_id` is decoded by its type, or hex without a type.
+//!   `mobile_id` and `mobile_id_type` are null without options header.
+//! - Times are ISO-8601 in UTC, null when the LMU had no valid time.
+//! - `position` holds degrees, meters, km/h, degrees from true North (null
+//!   when invalid), the satellite count and the HDOP.
+//! - `unit_status` flags are true when the receiver or update is OK, the
+//!   reserved bits are left out.
+//! - `appended` is the hex of the bytes appended to the report.
+
+use std::borrow::Cow;
+
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+
+use crate::messages::event_report::{
+    CommState, FixStatus, Inputs, UnitStatus, COORDINATE_LSB,
+};
+use crate::options_header::MobileIDType;
+use crate::timestamp::Timestamp;
+use crate::Message;
+
+/// Version of the shape of [`MessageJson`].
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A message in the stable JSON schema, see the module documentation.
+#[derive(Debug, Serialize)]
+pub struct MessageJson<'a> {
+    schema_version: u32,
+    message_type: Cow<'static, str>,
+    service_type: Cow<'static, str>,
+    sequence_number: u16,
+    mobile_id: Option<String>,
+    mobile_id_type: Option<Cow<'static, str>>,
+    update_time: Option<String>,
+    time_of_fix: Option<String>,
+    position: Position,
+    fix_status: Flags<7>,
+    comm_state: CommStateJson,
+    rssi: i16,
+    carrier: u16,
+    inputs: Flags<8>,
+    unit_status: Flags<4>,
+    event_index: u8,
+    event_code: u8,
+    accumulators: &'a [u32],
+    appended: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Position {
+    latitude: f64,
+    longitude: f64,
+    altitude: f64,
+    speed: f64,
+    heading: Option<u16>,
+    satellites: u8,
+    hdop: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct CommStateJson {
+    #[serde(flatten)]
+    flags: Flags<6>,
+    network_technology: Cow<'static, str>,
+}
+
+/// Named flags, serialized as an object in this order.
+#[derive(Debug)]
+struct Flags<const N: usize>([(&'static str, bool); N]);
+
+impl<const N: usize> Serialize for Flags<N> {
+    fn serialize<S: Serializer>(
+        &self, serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(N))?;
+        for (name, set) in &self.0 {
+            map.serialize_entry(name, set)?;
+        }
+        map.end()
+    }
+}
+
+impl<'a> MessageJson<'a> {
+    pub fn new(msg: &'a Message) -> Self {
+        let header = &msg.message_header;
+        let options_header = msg.options_header.as_ref();
+        let report = &msg.msg;
+
+        MessageJson {
+            schema_version: SCHEMA_VERSION,
+            message_type: header.message_type.name(),
+            service_type: header.service_type.name(),
+            sequence_number: header.sequence_number.data(),
+            mobile_id: options_header.and_then(|o| o.mobile_id_string()),
+            mobile_id_type: options_header
+                .and_then(|o| o.mobile_id_type.as_ref())
+                .map(MobileIDType::name),
+            update_time: time(report.update_time),
+            time_of_fix: time(report.time_of_fix),
+            position: Position {
+                // Divided rather than scaled by the LSB, for the shortest
+                // decimal form.
+                latitude: f64::from(report.latitude) / COORDINATE_LSB.recip(),
+                longitude: f64::from(report.longitude) / COORDINATE_LSB.recip(),
+                altitude: report.altitude.meters(),
+                speed: (report.speed.kmh() * 1000.0).round() / 1000.0,
+                heading: report.heading.degrees(),
+                satellites: report.satellites,
+                hdop: f64::from(report.hdop.raw()) / 10.0,
+            },
+            fix_status: fix_status(&report.fix_status),
+            comm_state: comm_state(&report.comm_state),
+            rssi: report.rssi.dbm(),
+            carrier: report.carrier,
+            inputs: inputs(&report.inputs),
+            unit_status: unit_status(&report.unit_status),
+            event_index: report.event_index,
+            event_code: report.event_code,
+            accumulators: &report.accum_list,
+            appended: report
+                .appended
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+        }
+    }
+
+    /// The object on one line, without the newline.
+    pub fn to_line(&self) -> String {
+        serde_json::to_string(self).expect("string keys only")
+    }
+}
+
+fn time(timestamp: Timestamp) -> Option<String> {
+    if timestamp.is_valid() {
+        Some(timestamp.to_string())
+    } else {
+        None
+    }
+}
+
+fn fix_status(s: &FixStatus) -> Flags<7> {
+    Flags([
+        ("predicted", s.predicted),
+        ("diff_corrected", s.diff_corrected),
+        ("last_known", s.last_know),
+        ("invalid_fix", s.invalid_fix),
+        ("twod_fix", s.twod_fix),
+        ("historic", s.historic),
+        ("invalid_time", s.invalid_time),
+    ])
+}
+
+fn comm_state(s: &CommState) -> CommStateJson {
+    CommStateJson {
+        flags: Flags([
+            ("available", s.available),
+            ("network_service", s.network_service),
+            ("data_service", s.data_service),
+            ("connected", s.connected),
+            ("voice_call_active", s.voice_call_active),
+            ("roaming", s.roaming),
+        ]),
+        network_technology: s.network_technology.name(),
+    }
+}
+
+fn inputs(s: &Inputs) -> Flags<8> {
+    Flags([
+        ("ignition", s.ignition),
+        ("input_1", s.input_1),
+        ("input_2", s.input_2),
+        ("input_3", s.input_3),
+        ("input_4", s.input_4),
+        ("input_5", s.input_5),
+        ("input_6", s.input_6),
+        ("input_7", s.input_7),
+    ])
+}
+
+fn unit_status(s: &UnitStatus) -> Flags<4> {
+    Flags([
+        ("ota_update", s.ota_update),
+        ("gps_antenna", s.gps_antenna),
+        ("gps_self_test", s.gps_self_test),
+        ("gps_tracking", s.gps_tracking),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message_header::{MessageType, ServiceType};
+    use crate::messages::event_report::NetworkTechnology;
+    use crate::options_header::MobileIDType;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_names_are_snake_case() {
+        let names = (0..=u8::MAX)
+            .map_while(|value| MessageType::try_from(value).ok())
+            .map(|message_type| message_type.name())
+            .chain((0..3).map(|v| ServiceType::from_u8(v).name()))
+            .chain((0..8).map(|v| MobileIDType::from_u8(v).name()))
+            .chain((0..4).map(|v| NetworkTechnology::parse(v).name()));
+        for name in names {
+            assert!(
+                name.chars().all(|c| c.is_ascii_lowercase() || c == '_'),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(MessageType::IDReport.name(), "id_report");
+        assert_eq!(MessageType::Unknown(21).name(), "unknown_21");
+        assert_eq!(ServiceType::Unknown(9).name(), "unknown_9");
+    }
+}
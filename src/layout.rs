@@ -0,0 +1,401 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Field layouts of the messages.
+//!
+//! The fixed size parts of the headers and bodies, in wire order, with how
+//! each field is decoded. [`dissect`](crate::dissect) walks them and the
+//! [`wireshark`](crate::wireshark) generator turns them into Lua, the
+//! [`framer`](crate::framer) and [`MessageRef`](crate::message_ref) take
+//! their lengths and offsets from them.
+//!
+//! The parsers in [`messages`](crate::messages) and the headers are written
+//! by hand, not generated from these tables: the tests below check that
+//! both agree, field by field, so a change to one must come with the other.
+
+use crate::messages::event_report::COORDINATE_LSB;
+
+/// How the bytes of a field are decoded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Decode {
+    Unsigned,
+    Hex,
+
+    /// Seconds since the Unix epoch.
+    Timestamp,
+
+    /// A number times `factor`, shown with `decimals` and `unit`.
+    Scaled {
+        signed: bool,
+        factor: f64,
+        decimals: usize,
+        unit: &'static str,
+    },
+
+    ServiceType,
+    MessageType,
+    MobileIDType,
+
+    /// The result of an Ack/Nak, see [`NAK_REASONS`].
+    Ack,
+
+    /// A byte made of bit fields.
+    Bits(&'static [BitField]),
+}
+
+/// How the bits of a [`BitField`] are decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BitDecode {
+    /// True when the bit is set.
+    Flag,
+
+    /// True when the bit is clear.
+    ClearFlag,
+
+    Number,
+    NetworkTechnology,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BitField {
+    pub name: &'static str,
+
+    /// Position of the least significant bit.
+    pub shift: u8,
+    pub width: u8,
+    pub decode: BitDecode,
+}
+
+impl BitField {
+    const fn flag(name: &'static str, shift: u8) -> Self {
+        BitField {
+            name,
+            shift,
+            width: 1,
+            decode: BitDecode::Flag,
+        }
+    }
+
+    const fn clear_flag(name: &'static str, shift: u8) -> Self {
+        BitField {
+            name,
+            shift,
+            width: 1,
+            decode: BitDecode::ClearFlag,
+        }
+    }
+
+    /// Mask of the bits in their byte.
+    pub fn mask(&self) -> u8 {
+        (((1u16 << self.width) - 1) << self.shift) as u8
+    }
+
+    /// The value of the bits in `byte`.
+    pub fn get(&self, byte: u8) -> u8 {
+        (byte & self.mask()) >> self.shift
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Field {
+    pub name: &'static str,
+    pub len: usize,
+    pub decode: Decode,
+}
+
+const fn field(name: &'static str, len: usize, decode: Decode) -> Field {
+    Field { name, len, decode }
+}
+
+const fn scaled(
+    signed: bool, factor: f64, decimals: usize, unit: &'static str,
+) -> Decode {
+    Decode::Scaled {
+        signed,
+        factor,
+        decimals,
+        unit,
+    }
+}
+
+/// Total length of `fields`.
+pub(crate) const fn len(fields: &[Field]) -> usize {
+    let mut len = 0;
+    let mut i = 0;
+    while i < fields.len() {
+        len += fields[i].len;
+        i += 1;
+    }
+    len
+}
+
+/// Offset of the field `name` in `fields`.
+///
+/// # Panics
+///
+/// Panics when there is no such field.
+pub(crate) fn offset(fields: &[Field], name: &str) -> usize {
+    let index = fields.iter().position(|f| f.name == name).unwrap();
+    fields[..index].iter().map(|f| f.len).sum()
+}
+
+/// The options byte, the Mobile ID is bit 0.
+pub(crate) const OPTIONS_BITS: [BitField; 8] = [
+    BitField::flag("mobile_id", 0),
+    BitField::flag("mobile_id_type", 1),
+    BitField::flag("authentication_world", 2),
+    BitField::flag("routing", 3),
+    BitField::flag("forwarding", 4),
+    BitField::flag("response_redirection", 5),
+    BitField::flag("options_extension", 6),
+    BitField::flag("always_set", 7),
+];
+
+pub(crate) const OPTIONS: [Field; 1] =
+    [field("options", 1, Decode::Bits(&OPTIONS_BITS))];
+
+/// The value of an option field, as long as its length byte says.
+const OPTION_VALUE: Field = field("value", 0, Decode::Hex);
+
+/// The option fields in order, present when their bit of the options byte
+/// is set. Each one is a length byte, then as many bytes read as the value
+/// field, whose length is the only one the parser accepts when not zero.
+pub(crate) const OPTION_FIELDS: [(&str, Field); 7] = [
+    ("mobile_id", OPTION_VALUE),
+    ("mobile_id_type", field("value", 1, Decode::MobileIDType)),
+    ("authentication_world", OPTION_VALUE),
+    ("routing", OPTION_VALUE),
+    ("forwarding", OPTION_VALUE),
+    ("response_redirection", OPTION_VALUE),
+    ("options_extension", OPTION_VALUE),
+];
+
+pub(crate) const MESSAGE_HEADER: [Field; 3] = [
+    field("service_type", 1, Decode::ServiceType),
+    field("message_type", 1, Decode::MessageType),
+    field("sequence_number", 2, Decode::Unsigned),
+];
+
+/// The Fix Status bits, bit 0 is not read by the parser.
+const FIX_STATUS_BITS: [BitField; 8] = [
+    BitField::flag("unused", 0),
+    BitField::flag("predicted", 1),
+    BitField::flag("diff_corrected", 2),
+    BitField::flag("last_know", 3),
+    BitField::flag("invalid_fix", 4),
+    BitField::flag("twod_fix", 5),
+    BitField::flag("historic", 6),
+    BitField::flag("invalid_time", 7),
+];
+
+const COMM_STATE_BITS: [BitField; 7] = [
+    BitField::flag("available", 0),
+    BitField::flag("network_service", 1),
+    BitField::flag("data_service", 2),
+    BitField::flag("connected", 3),
+    BitField::flag("voice_call_active", 4),
+    BitField::flag("roaming", 5),
+    BitField {
+        name: "network_technology",
+        shift: 6,
+        width: 2,
+        decode: BitDecode::NetworkTechnology,
+    },
+];
+
+const INPUTS_BITS: [BitField; 8] = [
+    BitField::flag("ignition", 0),
+    BitField::flag("input_1", 1),
+    BitField::flag("input_2", 2),
+    BitField::flag("input_3", 3),
+    BitField::flag("input_4", 4),
+    BitField::flag("input_5", 5),
+    BitField::flag("input_6", 6),
+    BitField::flag("input_7", 7),
+];
+
+const UNIT_STATUS_BITS: [BitField; 8] = [
+    BitField::clear_flag("ota_update", 0),
+    BitField::clear_flag("gps_antenna", 1),
+    BitField::clear_flag("gps_self_test", 2),
+    BitField::clear_flag("gps_tracking", 3),
+    BitField::clear_flag("reserved_1", 4),
+    BitField::clear_flag("reserved_2", 5),
+    BitField::clear_flag("reserved_3", 6),
+    BitField::clear_flag("unused", 7),
+];
+
+/// The number of accumulators, and their Reporting Format Type.
+pub(crate) const ACCUMS_BITS: [BitField; 2] = [
+    BitField {
+        name: "count",
+        shift: 0,
+        width: 6,
+        decode: BitDecode::Number,
+    },
+    BitField {
+        name: "format",
+        shift: 6,
+        width: 2,
+        decode: BitDecode::Number,
+    },
+];
+
+/// The Event Report fields before the accumulators and appended blocks.
+pub(crate) const EVENT_REPORT: [Field; 19] = [
+    field("update_time", 4, Decode::Timestamp),
+    field("time_of_fix", 4, Decode::Timestamp),
+    field("latitude", 4, scaled(true, COORDINATE_LSB, 7, "°")),
+    field("longitude", 4, scaled(true, COORDINATE_LSB, 7, "°")),
+    field("altitude", 4, scaled(true, 0.01, 2, " m")),
+    field("speed", 4, scaled(false, 0.036, 2, " km/h")),
+    field("heading", 2, scaled(false, 1.0, 0, "°")),
+    field("satellites", 1, Decode::Unsigned),
+    field("fix_status", 1, Decode::Bits(&FIX_STATUS_BITS)),
+    field("carrier", 2, Decode::Unsigned),
+    field("rssi", 2, scaled(true, 1.0, 0, " dBm")),
+    field("comm_state", 1, Decode::Bits(&COMM_STATE_BITS)),
+    field("hdop", 1, scaled(false, 0.1, 1, "")),
+    field("inputs", 1, Decode::Bits(&INPUTS_BITS)),
+    field("unit_status", 1, Decode::Bits(&UNIT_STATUS_BITS)),
+    field("event_index", 1, Decode::Unsigned),
+    field("event_code", 1, Decode::Unsigned),
+    field("accums", 1, Decode::Bits(&ACCUMS_BITS)),
+    // One bit per appended block.
+    field("append", 1, Decode::Bits(&[])),
+    // Followed by the accumulators and the appended blocks.
+];
+
+/// Length of the fixed part of the Event Report, up to the accumulators.
+pub(crate) const EVENT_REPORT_LEN: usize = len(&EVENT_REPORT);
+
+/// Offset of the Accums byte in the Event Report.
+pub(crate) const EVENT_REPORT_ACCUMS: usize = EVENT_REPORT_LEN - 2;
+
+/// Offset of the Append byte in the Event Report.
+pub(crate) const EVENT_REPORT_APPEND: usize = EVENT_REPORT_LEN - 1;
+
+/// One accumulator.
+pub(crate) const ACCUMULATOR: Field = field("accumulator", 4, Decode::Unsigned);
+
+pub(crate) const ACK_NAK: [Field; 4] = [
+    field("message_type", 1, Decode::MessageType),
+    field("ack", 1, Decode::Ack),
+    field("spare", 1, Decode::Unsigned),
+    field("app_version", 3, Decode::Hex),
+];
+
+/// The results of an Ack/Nak, 0 is an ACK.
+pub(crate) const NAK_REASONS: [&str; 8] = [
+    "ACK",
+    "NAK, no reason",
+    "NAK, not supported message type",
+    "NAK, not supported operation",
+    "NAK, unable to pass to serial port",
+    "NAK, authentication failure",
+    "NAK, mobile id lookup failure",
+    "NAK, non zero sequence number same as last received",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_header::MessageHeader;
+    use crate::messages::ack_nak::{AckNak, ACK_NAK_LEN};
+    use crate::messages::event_report::{
+        CommState, EventReport, FixStatus, Inputs, UnitStatus,
+    };
+    use crate::options_header::OptionsHeader;
+    use core::fmt::Debug;
+
+    /// The parser reads exactly `len(fields)` bytes.
+    fn assert_len<T, F>(fields: &[Field], parse: F)
+    where
+        F: Fn(&[u8]) -> nom::IResult<&[u8], T>,
+    {
+        let zeros = vec![0; len(fields)];
+        assert_eq!(parse(&zeros).ok().unwrap().0, &[] as &[u8]);
+        assert!(parse(&zeros[1..]).is_err());
+    }
+
+    #[test]
+    fn test_layout_lengths() {
+        assert_len(&MESSAGE_HEADER, MessageHeader::parse);
+        assert_len(&EVENT_REPORT, EventReport::parse_fields);
+        assert_eq!(offset(&EVENT_REPORT, "accums"), EVENT_REPORT_ACCUMS);
+        assert_eq!(offset(&EVENT_REPORT, "append"), EVENT_REPORT_APPEND);
+        assert_len(&ACK_NAK, AckNak::parse);
+        assert_eq!(len(&ACK_NAK), ACK_NAK_LEN);
+
+        let mut body = vec![0; len(&EVENT_REPORT) + 8];
+        let accums = 0xc2;
+        body[offset(&EVENT_REPORT, "accums")] = accums;
+        let (_, report) = EventReport::parse_fields(&body).unwrap();
+        assert_eq!(report.accum_list.len(), 2);
+        assert_eq!(ACCUMS_BITS[0].get(accums), 2);
+    }
+
+    /// Each flag of `bits` is the only one set by the parser when its bit
+    /// alone is set, or clear for inverted flags.
+    fn assert_flags<T, F>(bits: &[BitField], parse: F)
+    where
+        T: Debug,
+        F: Fn(&[u8]) -> nom::IResult<&[u8], T>,
+    {
+        for bit in bits.iter().filter(|bit| bit.width == 1) {
+            let byte = match bit.decode {
+                BitDecode::ClearFlag => !bit.mask(),
+                _ => bit.mask(),
+            };
+            let parsed = format!("{:?}", parse(&[byte]).unwrap().1);
+            assert!(
+                parsed.contains(&format!("{}: true", bit.name)),
+                "{}",
+                parsed
+            );
+            assert_eq!(parsed.matches("true").count(), 1, "{}", parsed);
+        }
+    }
+
+    #[test]
+    fn test_layout_bits() {
+        assert_flags(&FIX_STATUS_BITS, FixStatus::parse);
+        assert_flags(&COMM_STATE_BITS, CommState::parse);
+        assert_flags(&INPUTS_BITS, Inputs::parse);
+        assert_flags(&UNIT_STATUS_BITS, UnitStatus::parse);
+
+        let (_, comm_state) = CommState::parse(&[0x80]).unwrap();
+        let technology = &COMM_STATE_BITS[6];
+        assert_eq!(technology.name, "network_technology");
+        assert_eq!(
+            u8::from(comm_state.network_technology),
+            technology.get(0x80)
+        );
+    }
+
+    #[test]
+    fn test_layout_options() {
+        for (bit, (name, value)) in OPTION_FIELDS.iter().enumerate() {
+            assert_eq!(OPTIONS_BITS[bit].name, *name);
+            let mut input = vec![0x80 | OPTIONS_BITS[bit].mask()];
+            input.push(value.len as u8);
+            input.extend(vec![0; value.len]);
+            let (rest, header) = OptionsHeader::parse(&input).unwrap();
+            assert!(rest.is_empty());
+            let parsed = format!("{:?}", header.unwrap());
+            assert!(parsed.contains(&format!("{}: Some", name)), "{}", parsed);
+            assert_eq!(parsed.matches("Some").count(), 1, "{}", parsed);
+        }
+    }
+}
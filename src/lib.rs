@@ -13,15 +13,56 @@
 //
 
 //! Calamp LMDirect message parser.
+//!
+//! The parser builds without the standard library: disable the default
+//! `std` feature to use it with `alloc` only.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "pcap")]
+pub mod capture;
+#[cfg(feature = "tokio")]
+pub mod codec;
+#[cfg(feature = "std")]
+pub mod command;
+#[cfg(feature = "std")]
+pub mod dedup;
+pub mod dissect;
+pub mod error;
+#[cfg(test)]
+mod fixtures;
+pub mod framer;
+#[cfg(feature = "json")]
+pub mod json;
+mod layout;
 pub mod message_header;
+pub mod message_ref;
 pub mod messages;
 pub mod options_header;
+#[cfg(feature = "replay")]
+pub mod replay;
+#[cfg(feature = "std")]
+pub mod server;
+#[cfg(feature = "std")]
+pub mod session;
+#[cfg(feature = "simulator")]
+pub mod simulator;
+pub mod timestamp;
+pub mod units;
 mod utils;
+pub mod wireshark;
 
-use message_header::MessageHeader;
+pub use error::Error;
+pub use message_ref::MessageRef;
+pub use timestamp::Timestamp;
+
+use message_header::{MessageHeader, MessageType};
 use messages::event_report::EventReport;
 use options_header::OptionsHeader;
 
@@ -34,15 +75,45 @@ pub struct Message {
 }
 
 impl Message {
-    pub fn parse(input: &[u8]) -> Self {
-        let (i, options_header) = OptionsHeader::parse(input).unwrap();
-        let (i, message_header) = MessageHeader::parse(i).unwrap();
-        let msg = EventReport::parse(i).unwrap();
+    /// Parse a complete message, only Event Reports are supported as body.
+    pub fn parse(input: &[u8]) -> Result<Self, Error> {
+        let err = |e| utils::error(input, e);
+        let (i, options_header) = OptionsHeader::parse(input).map_err(err)?;
+        let (i, message_header) = MessageHeader::parse(i).map_err(err)?;
+        if message_header.message_type != MessageType::EventReport {
+            return Err(Error::UnsupportedMessageType(
+                message_header.message_type,
+            ));
+        }
+        let (_, msg) = EventReport::parse_fields(i).map_err(err)?;
 
-        Message {
+        Ok(Message {
             options_header,
             message_header,
             msg,
+        })
+    }
+
+    /// Append the encoded message to `buf`.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        if let Some(options_header) = &self.options_header {
+            options_header.encode(buf);
         }
+        self.message_header.encode(buf);
+        self.msg.encode(buf);
+    }
+
+    /// The message as one line of JSON, in the stable schema of [`json`],
+    /// without the newline.
+    #[cfg(feature = "json")]
+    pub fn to_json_line(&self) -> String {
+        json::MessageJson::new(self).to_line()
+    }
+
+    /// The message as sent on the wire.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode(&mut buf);
+        buf
     }
 }
@@ -12,15 +12,22 @@
 // OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
 //
 
-use nom::error::ErrorKind;
-use nom::number::streaming::{be_u16, be_u8};
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::convert::TryFrom;
+use core::fmt;
 use nom::IResult;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
-use std::fmt;
 
-#[derive(PartialEq, Eq)]
+use crate::error::Error;
+use crate::utils;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub enum MessageType {
     /// Null message
     Null,
@@ -52,29 +59,99 @@ pub enum MessageType {
     DeviceVersion,
     /// Application message with accumulators
     ApplicationMessageWithAccumulators,
+    /// Message type not known to this crate
+    Unknown(u8),
 }
 
 impl MessageType {
     pub fn parse(input: &[u8]) -> IResult<&[u8], MessageType> {
-        let (i, b): (&[u8], u8) = be_u8::<_, (_, ErrorKind)>(input).unwrap();
+        let (i, b) = utils::pu8(input)?;
+        Ok((i, MessageType::from_u8(b)))
+    }
 
+    pub(crate) fn from_u8(b: u8) -> MessageType {
         match b {
-            0 => Ok((i, MessageType::Null)),
-            1 => Ok((i, MessageType::AckNak)),
-            2 => Ok((i, MessageType::EventReport)),
-            3 => Ok((i, MessageType::IDReport)),
-            4 => Ok((i, MessageType::UserData)),
-            5 => Ok((i, MessageType::ApplicationData)),
-            6 => Ok((i, MessageType::ConfigurationParameter)),
-            7 => Ok((i, MessageType::UnitRequest)),
-            8 => Ok((i, MessageType::LocateReport)),
-            9 => Ok((i, MessageType::UserDataWithAccumulators)),
-            10 => Ok((i, MessageType::MiniEventReport)),
-            11 => Ok((i, MessageType::MiniUserData)),
-            12 => Ok((i, MessageType::MiniApplication)),
-            13 => Ok((i, MessageType::DeviceVersion)),
-            14 => Ok((i, MessageType::ApplicationMessageWithAccumulators)),
-            _ => panic!("not found"),
+            0 => MessageType::Null,
+            1 => MessageType::AckNak,
+            2 => MessageType::EventReport,
+            3 => MessageType::IDReport,
+            4 => MessageType::UserData,
+            5 => MessageType::ApplicationData,
+            6 => MessageType::ConfigurationParameter,
+            7 => MessageType::UnitRequest,
+            8 => MessageType::LocateReport,
+            9 => MessageType::UserDataWithAccumulators,
+            10 => MessageType::MiniEventReport,
+            11 => MessageType::MiniUserData,
+            12 => MessageType::MiniApplication,
+            13 => MessageType::DeviceVersion,
+            14 => MessageType::ApplicationMessageWithAccumulators,
+            _ => MessageType::Unknown(b),
+        }
+    }
+
+    /// Snake case name, `unknown_<n>` for a value without a name.
+    pub fn name(&self) -> Cow<'static, str> {
+        Cow::Borrowed(match *self {
+            MessageType::Null => "null",
+            MessageType::AckNak => "ack_nak",
+            MessageType::EventReport => "event_report",
+            MessageType::IDReport => "id_report",
+            MessageType::UserData => "user_data",
+            MessageType::ApplicationData => "application_data",
+            MessageType::ConfigurationParameter => "configuration_parameter",
+            MessageType::UnitRequest => "unit_request",
+            MessageType::LocateReport => "locate_report",
+            MessageType::UserDataWithAccumulators => {
+                "user_data_with_accumulators"
+            }
+            MessageType::MiniEventReport => "mini_event_report",
+            MessageType::MiniUserData => "mini_user_data",
+            MessageType::MiniApplication => "mini_application",
+            MessageType::DeviceVersion => "device_version",
+            MessageType::ApplicationMessageWithAccumulators => {
+                "application_message_with_accumulators"
+            }
+            MessageType::Unknown(value) => {
+                return Cow::Owned(format!("unknown_{}", value))
+            }
+        })
+    }
+}
+
+impl TryFrom<u8> for MessageType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match MessageType::from_u8(value) {
+            MessageType::Unknown(value) => Err(Error::UnknownValue {
+                kind: "MessageType",
+                value,
+            }),
+            message_type => Ok(message_type),
+        }
+    }
+}
+
+impl From<MessageType> for u8 {
+    fn from(message_type: MessageType) -> u8 {
+        match message_type {
+            MessageType::Null => 0,
+            MessageType::AckNak => 1,
+            MessageType::EventReport => 2,
+            MessageType::IDReport => 3,
+            MessageType::UserData => 4,
+            MessageType::ApplicationData => 5,
+            MessageType::ConfigurationParameter => 6,
+            MessageType::UnitRequest => 7,
+            MessageType::LocateReport => 8,
+            MessageType::UserDataWithAccumulators => 9,
+            MessageType::MiniEventReport => 10,
+            MessageType::MiniUserData => 11,
+            MessageType::MiniApplication => 12,
+            MessageType::DeviceVersion => 13,
+            MessageType::ApplicationMessageWithAccumulators => 14,
+            MessageType::Unknown(value) => value,
         }
     }
 }
@@ -111,6 +188,9 @@ impl fmt::Display for MessageType {
             MessageType::ApplicationMessageWithAccumulators => {
                 write!(f, "MessageType::ApplicationMessageWithAccumulators")
             }
+            MessageType::Unknown(value) => {
+                write!(f, "MessageType::Unknown({})", value)
+            }
         }
     }
 }
@@ -147,12 +227,16 @@ impl fmt::Debug for MessageType {
             MessageType::ApplicationMessageWithAccumulators => {
                 write!(f, "MessageType::ApplicationMessageWithAccumulators")
             }
+            MessageType::Unknown(value) => {
+                write!(f, "MessageType::Unknown({})", value)
+            }
         }
     }
 }
 
-#[derive(PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub enum ServiceType {
     /// Unacknowledged Request
     Unacknowledged,
@@ -162,17 +246,62 @@ pub enum ServiceType {
 
     /// Response to an Acknowledged Request
     ResponseToAnAcknowledged,
+
+    /// Service type not known to this crate
+    Unknown(u8),
 }
 
 impl ServiceType {
     pub fn parse(input: &[u8]) -> IResult<&[u8], ServiceType> {
-        let (i, b): (&[u8], u8) = be_u8::<_, (_, ErrorKind)>(input).unwrap();
+        let (i, b) = utils::pu8(input)?;
+        Ok((i, ServiceType::from_u8(b)))
+    }
 
+    pub(crate) fn from_u8(b: u8) -> ServiceType {
         match b {
-            0 => Ok((i, ServiceType::Unacknowledged)),
-            1 => Ok((i, ServiceType::Acknowledged)),
-            2 => Ok((i, ServiceType::ResponseToAnAcknowledged)),
-            _ => panic!("not found"),
+            0 => ServiceType::Unacknowledged,
+            1 => ServiceType::Acknowledged,
+            2 => ServiceType::ResponseToAnAcknowledged,
+            _ => ServiceType::Unknown(b),
+        }
+    }
+
+    /// Snake case name, `unknown_<n>` for a value without a name.
+    pub fn name(&self) -> Cow<'static, str> {
+        Cow::Borrowed(match *self {
+            ServiceType::Unacknowledged => "unacknowledged",
+            ServiceType::Acknowledged => "acknowledged",
+            ServiceType::ResponseToAnAcknowledged => {
+                "response_to_an_acknowledged"
+            }
+            ServiceType::Unknown(value) => {
+                return Cow::Owned(format!("unknown_{}", value))
+            }
+        })
+    }
+}
+
+impl TryFrom<u8> for ServiceType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match ServiceType::from_u8(value) {
+            ServiceType::Unknown(value) => Err(Error::UnknownValue {
+                kind: "ServiceType",
+                value,
+            }),
+            service_type => Ok(service_type),
+        }
+    }
+}
+
+impl From<ServiceType> for u8 {
+    fn from(service_type: ServiceType) -> u8 {
+        match service_type {
+            ServiceType::Unacknowledged => 0,
+            ServiceType::Acknowledged => 1,
+            ServiceType::ResponseToAnAcknowledged => 2,
+            ServiceType::Unknown(value) => value,
         }
     }
 }
@@ -187,6 +316,9 @@ impl fmt::Display for ServiceType {
             ServiceType::ResponseToAnAcknowledged => {
                 write!(f, "ServiceType::ResponseToAnAcknowledged")
             }
+            ServiceType::Unknown(value) => {
+                write!(f, "ServiceType::Unknown({})", value)
+            }
         }
     }
 }
@@ -201,6 +333,9 @@ impl fmt::Debug for ServiceType {
             ServiceType::ResponseToAnAcknowledged => {
                 write!(f, "ServiceType::ResponseToAnAcknowledged")
             }
+            ServiceType::Unknown(value) => {
+                write!(f, "ServiceType::Unknown({})", value)
+            }
         }
     }
 }
@@ -229,7 +364,7 @@ impl fmt::Debug for ServiceType {
 /// sequence number for the new message. If different, it will process the
 /// message normally. If the same, it will not process the message and will
 /// return a NAK response with the 'ACK' field set to 7.
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SequenceNumber(u16);
 
@@ -246,16 +381,61 @@ impl fmt::Debug for SequenceNumber {
 }
 
 impl SequenceNumber {
+    pub fn new(value: u16) -> Self {
+        SequenceNumber(value)
+    }
+
     pub fn parse(input: &[u8]) -> IResult<&[u8], SequenceNumber> {
-        let (i, b): (&[u8], u16) = be_u16::<_, (_, ErrorKind)>(input).unwrap();
+        let (i, b) = utils::pu16(input)?;
         Ok((i, SequenceNumber(b)))
     }
 
     pub fn data(&self) -> u16 {
         self.0
     }
+
+    /// The number the LMU uses after this one, rolling over from 65535 to 1.
+    pub fn next(&self) -> SequenceNumber {
+        match self.0 {
+            u16::MAX => SequenceNumber(1),
+            n => SequenceNumber(n + 1),
+        }
+    }
+
+    /// Order two sequence numbers across the rollover.
+    ///
+    /// The numbers 1 to 65535 form a circle, a number is newer than the
+    /// 32767 numbers behind it and older than the 32767 ahead of it. Zero
+    /// is not part of the sequence and is not ordered.
+    pub fn wrapping_cmp(&self, other: &SequenceNumber) -> Option<Ordering> {
+        if self.0 == 0 || other.0 == 0 {
+            return None;
+        }
+        match u32::from(self.distance_from(other)) {
+            0 => Some(Ordering::Equal),
+            d if d <= SEQUENCE_SPAN / 2 => Some(Ordering::Greater),
+            _ => Some(Ordering::Less),
+        }
+    }
+
+    /// How many numbers come after `other` up to this one, going forward
+    /// around the circle of [`SequenceNumber::wrapping_cmp`].
+    pub fn distance_from(&self, other: &SequenceNumber) -> u16 {
+        let distance = (u32::from(self.0) + SEQUENCE_SPAN - u32::from(other.0))
+            % SEQUENCE_SPAN;
+        distance as u16
+    }
+
+    /// Whether this number was sent after `other`, see
+    /// [`SequenceNumber::wrapping_cmp`].
+    pub fn is_newer_than(&self, other: &SequenceNumber) -> bool {
+        self.wrapping_cmp(other) == Some(Ordering::Greater)
+    }
 }
 
+/// Count of sequence numbers in use, 1 to 65535.
+const SEQUENCE_SPAN: u32 = u16::MAX as u32;
+
 #[derive(Debug)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MessageHeader {
@@ -266,9 +446,9 @@ pub struct MessageHeader {
 
 impl MessageHeader {
     pub fn parse(input: &[u8]) -> IResult<&[u8], MessageHeader> {
-        let (i, service_type) = ServiceType::parse(input).unwrap();
-        let (i, message_type) = MessageType::parse(i).unwrap();
-        let (i, sequence_number) = SequenceNumber::parse(i).unwrap();
+        let (i, service_type) = ServiceType::parse(input)?;
+        let (i, message_type) = MessageType::parse(i)?;
+        let (i, sequence_number) = SequenceNumber::parse(i)?;
 
         Ok((
             i,
@@ -279,12 +459,21 @@ impl MessageHeader {
             },
         ))
     }
+
+    /// Append the four header bytes.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(u8::from(self.service_type));
+        buf.push(u8::from(self.message_type));
+        buf.extend_from_slice(&self.sequence_number.0.to_be_bytes());
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{MessageHeader, MessageType, ServiceType};
+    use super::{MessageHeader, MessageType, SequenceNumber, ServiceType};
+    use crate::error::Error;
     use crate::options_header::OptionsHeader;
+    use std::convert::TryFrom;
 
     #[test]
     fn test_parse_message_headers() {
@@ -326,4 +515,66 @@ mod tests {
             String::from("SequenceNumber(14982)")
         );
     }
+
+    #[test]
+    fn test_parse_unknown_message_header() {
+        let data: [u8; 4] = [0x05, 0x2a, 0x00, 0x01];
+
+        let (_, message_header) = MessageHeader::parse(&data).unwrap();
+        assert_eq!(message_header.service_type, ServiceType::Unknown(5));
+        assert_eq!(message_header.message_type, MessageType::Unknown(42));
+        assert_eq!(
+            format!("{}", message_header.message_type),
+            String::from("MessageType::Unknown(42)")
+        );
+        assert_eq!(u8::from(message_header.message_type), 42);
+        assert_eq!(u8::from(message_header.service_type), 5);
+    }
+
+    #[test]
+    fn test_sequence_number_wrapping() {
+        use core::cmp::Ordering;
+
+        let seq = SequenceNumber::new;
+        assert_eq!(seq(65535).next(), seq(1));
+        assert_eq!(seq(7).next(), seq(8));
+        assert!(seq(1).is_newer_than(&seq(65535)));
+        assert!(seq(10).is_newer_than(&seq(65530)));
+        assert!(!seq(65535).is_newer_than(&seq(1)));
+        assert!(seq(300).is_newer_than(&seq(200)));
+        assert_eq!(seq(5).wrapping_cmp(&seq(5)), Some(Ordering::Equal));
+        assert_eq!(seq(1).wrapping_cmp(&seq(32769)), Some(Ordering::Greater));
+        assert_eq!(seq(32769).wrapping_cmp(&seq(1)), Some(Ordering::Less));
+        assert_eq!(seq(1).wrapping_cmp(&seq(32768)), Some(Ordering::Less));
+        assert_eq!(seq(0).wrapping_cmp(&seq(1)), None);
+    }
+
+    #[test]
+    fn test_sequence_number_distance() {
+        let seq = SequenceNumber::new;
+        assert_eq!(seq(10).distance_from(&seq(7)), 3);
+        assert_eq!(seq(7).distance_from(&seq(10)), 65532);
+        assert_eq!(seq(2).distance_from(&seq(65535)), 2);
+        assert_eq!(seq(5).distance_from(&seq(5)), 0);
+    }
+
+    #[test]
+    fn test_message_type_conversions() {
+        for value in 0..=14u8 {
+            let message_type = MessageType::try_from(value).unwrap();
+            assert_eq!(u8::from(message_type), value);
+        }
+        assert_eq!(
+            MessageType::try_from(15),
+            Err(Error::UnknownValue {
+                kind: "MessageType",
+                value: 15
+            })
+        );
+        assert_eq!(
+            ServiceType::try_from(2),
+            Ok(ServiceType::ResponseToAnAcknowledged)
+        );
+        assert!(ServiceType::try_from(3).is_err());
+    }
 }
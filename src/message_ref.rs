@@ -0,0 +1,416 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Zero-copy view of a LMDirect message.
+//!
+//! [`MessageRef`] borrows the datagram buffer and only checks that every
+//! field is present; values are decoded when their accessor is called and
+//! nothing is allocated. Use [`MessageRef::to_owned`] to get a [`Message`].
+
+use crate::error::Error;
+use crate::layout::{
+    self, EVENT_REPORT_ACCUMS, EVENT_REPORT_APPEND, EVENT_REPORT_LEN,
+};
+use crate::message_header::{
+    MessageHeader, MessageType, SequenceNumber, ServiceType,
+};
+use crate::messages::event_report::{
+    CommState, EventReport, FixStatus, Inputs, UnitStatus, COORDINATE_LSB,
+};
+use crate::options_header::{
+    MobileID, MobileIDType, OptionsHeader, OPTIONS_HEADER_FLAG,
+};
+use crate::timestamp::Timestamp;
+use crate::units::{Altitude, Hdop, Heading, Rssi, Speed};
+use crate::Message;
+
+/// Service type, message type and sequence number.
+const MESSAGE_HEADER_LEN: usize = layout::len(&layout::MESSAGE_HEADER);
+
+/// Lower 6 bits of the Accums field hold the number of accumulators.
+const ACCUM_COUNT_MASK: u8 = 0x3f;
+
+fn take(data: &[u8], offset: usize, len: usize) -> Result<&[u8], Error> {
+    match data.get(offset..offset + len) {
+        Some(bytes) => Ok(bytes),
+        None => Err(Error::Incomplete {
+            needed: offset + len - data.len(),
+        }),
+    }
+}
+
+fn be_u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn be_u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// Borrowed LMDirect message.
+#[derive(Debug, Clone, Copy)]
+pub struct MessageRef<'a> {
+    options: Option<u8>,
+
+    /// Option fields without their length byte, indexed by options bit.
+    fields: [Option<&'a [u8]>; 7],
+    header: &'a [u8],
+    body: &'a [u8],
+}
+
+impl<'a> MessageRef<'a> {
+    /// Check the layout of the message and remember where each part starts.
+    ///
+    /// Only Event Reports are supported as message body, like
+    /// [`Message::parse`].
+    pub fn parse(data: &'a [u8]) -> Result<MessageRef<'a>, Error> {
+        let first = take(data, 0, 1)?[0];
+        let mut offset = 0;
+        let mut options = None;
+        let mut fields = [None; 7];
+
+        if first & OPTIONS_HEADER_FLAG != 0 {
+            options = Some(first);
+            offset += 1;
+            // Every option field is a length byte followed by its data, in
+            // the order of the bits set in the options byte.
+            for (bit, field) in fields.iter_mut().enumerate() {
+                if first & (1 << bit) == 0 {
+                    continue;
+                }
+                let len = usize::from(take(data, offset, 1)?[0]);
+                // The Mobile ID Type is a single byte.
+                if bit == 1 && len != 1 {
+                    return Err(Error::Malformed { offset });
+                }
+                *field = Some(take(data, offset + 1, len)?);
+                offset += 1 + len;
+            }
+        }
+
+        let header = take(data, offset, MESSAGE_HEADER_LEN)?;
+        offset += MESSAGE_HEADER_LEN;
+
+        let message_type = MessageType::from_u8(header[1]);
+        if message_type != MessageType::EventReport {
+            return Err(Error::UnsupportedMessageType(message_type));
+        }
+
+        let fixed = take(data, offset, EVENT_REPORT_LEN)?;
+        let accums = usize::from(fixed[EVENT_REPORT_ACCUMS] & ACCUM_COUNT_MASK);
+        let append = fixed[EVENT_REPORT_APPEND];
+        let mut len = EVENT_REPORT_LEN + accums * 4;
+        take(data, offset, len)?;
+        // Appended data blocks are length prefixed, in the order of the bits
+        // set in the Append byte.
+        for _ in 0..append.count_ones() {
+            len += 1 + usize::from(take(data, offset + len, 1)?[0]);
+            take(data, offset, len)?;
+        }
+        let body = take(data, offset, len)?;
+
+        Ok(MessageRef {
+            options,
+            fields,
+            header,
+            body,
+        })
+    }
+
+    /// Raw options byte, if the options header is present.
+    pub fn options(&self) -> Option<u8> {
+        self.options
+    }
+
+    /// Raw bytes of the mobile id.
+    pub fn mobile_id(&self) -> Option<&'a [u8]> {
+        self.fields[0]
+    }
+
+    pub fn mobile_id_type(&self) -> Option<MobileIDType> {
+        let field = self.fields[1]?;
+        field.first().copied().map(MobileIDType::from_u8)
+    }
+
+    pub fn service_type(&self) -> ServiceType {
+        ServiceType::from_u8(self.header[0])
+    }
+
+    pub fn message_type(&self) -> MessageType {
+        MessageType::from_u8(self.header[1])
+    }
+
+    pub fn sequence_number(&self) -> SequenceNumber {
+        SequenceNumber::new(be_u16_at(self.header, 2))
+    }
+
+    pub fn message_header(&self) -> MessageHeader {
+        MessageHeader {
+            service_type: self.service_type(),
+            message_type: self.message_type(),
+            sequence_number: self.sequence_number(),
+        }
+    }
+
+    pub fn event_report(&self) -> EventReportRef<'a> {
+        EventReportRef { data: self.body }
+    }
+
+    /// Decode every field into the owned [`Message`].
+    pub fn to_owned(&self) -> Message {
+        let options_header = self.options.map(|_| {
+            let raw = |bit: usize| self.fields[bit].map(<[u8]>::to_vec);
+            OptionsHeader {
+                mobile_id: self.mobile_id().map(MobileID::new),
+                mobile_id_type: self.mobile_id_type(),
+                authentication_world: raw(2),
+                routing: raw(3),
+                forwarding: raw(4),
+                response_redirection: raw(5),
+                options_extension: raw(6),
+            }
+        });
+
+        Message {
+            options_header,
+            message_header: self.message_header(),
+            msg: EventReport::parse(self.body)
+                .expect("event report layout checked by MessageRef::parse"),
+        }
+    }
+}
+
+/// Borrowed Event Report body, fields are decoded on access.
+#[derive(Debug, Clone, Copy)]
+pub struct EventReportRef<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> EventReportRef<'a> {
+    pub fn update_time(&self) -> Timestamp {
+        Timestamp::new(be_u32_at(self.data, 0))
+    }
+
+    pub fn time_of_fix(&self) -> Timestamp {
+        Timestamp::new(be_u32_at(self.data, 4))
+    }
+
+    /// Latitude with a 1x10^-7 degree lsb.
+    pub fn latitude(&self) -> i32 {
+        be_u32_at(self.data, 8) as i32
+    }
+
+    /// Longitude with a 1x10^-7 degree lsb.
+    pub fn longitude(&self) -> i32 {
+        be_u32_at(self.data, 12) as i32
+    }
+
+    pub fn latitude_deg(&self) -> f64 {
+        f64::from(self.latitude()) * COORDINATE_LSB
+    }
+
+    pub fn longitude_deg(&self) -> f64 {
+        f64::from(self.longitude()) * COORDINATE_LSB
+    }
+
+    pub fn altitude(&self) -> Altitude {
+        Altitude::new(be_u32_at(self.data, 16) as i32)
+    }
+
+    pub fn speed(&self) -> Speed {
+        Speed::new(be_u32_at(self.data, 20))
+    }
+
+    pub fn heading(&self) -> Heading {
+        Heading::new(be_u16_at(self.data, 24))
+    }
+
+    pub fn satellites(&self) -> u8 {
+        self.data[26]
+    }
+
+    pub fn fix_status(&self) -> FixStatus {
+        FixStatus::parse(&self.data[27..28]).unwrap().1
+    }
+
+    pub fn carrier(&self) -> u16 {
+        be_u16_at(self.data, 28)
+    }
+
+    pub fn rssi(&self) -> Rssi {
+        Rssi::new(be_u16_at(self.data, 30) as i16)
+    }
+
+    pub fn comm_state(&self) -> CommState {
+        CommState::parse(&self.data[32..33]).unwrap().1
+    }
+
+    pub fn hdop(&self) -> Hdop {
+        Hdop::new(self.data[33])
+    }
+
+    pub fn inputs(&self) -> Inputs {
+        Inputs::parse(&self.data[34..35]).unwrap().1
+    }
+
+    pub fn unit_status(&self) -> UnitStatus {
+        UnitStatus::parse(&self.data[35..36]).unwrap().1
+    }
+
+    pub fn event_index(&self) -> u8 {
+        self.data[36]
+    }
+
+    pub fn event_code(&self) -> u8 {
+        self.data[37]
+    }
+
+    /// Raw Accums field, count and reporting format type.
+    pub fn accums(&self) -> u8 {
+        self.data[EVENT_REPORT_ACCUMS]
+    }
+
+    pub fn append(&self) -> u8 {
+        self.data[EVENT_REPORT_APPEND]
+    }
+
+    /// Number of 4-byte values in the accumulator list.
+    pub fn accum_count(&self) -> usize {
+        usize::from(self.accums() & ACCUM_COUNT_MASK)
+    }
+
+    /// Accumulator `n`, if present.
+    pub fn accumulator(&self, n: usize) -> Option<u32> {
+        if n < self.accum_count() {
+            Some(be_u32_at(self.data, EVENT_REPORT_LEN + n * 4))
+        } else {
+            None
+        }
+    }
+
+    pub fn accumulators(&self) -> impl Iterator<Item = u32> + 'a {
+        let end = EVENT_REPORT_LEN + self.accum_count() * 4;
+        self.data[EVENT_REPORT_LEN..end]
+            .chunks_exact(4)
+            .map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]))
+    }
+
+    /// Raw appended data blocks, each with its length byte.
+    pub fn appended(&self) -> &'a [u8] {
+        &self.data[EVENT_REPORT_LEN + self.accum_count() * 4..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MessageRef;
+    use crate::error::Error;
+    use crate::fixtures::DATA;
+    use crate::message_header::{MessageType, ServiceType};
+    use crate::options_header::MobileIDType;
+
+    #[test]
+    fn test_message_ref_matches_owned_parse() {
+        let msg_ref = MessageRef::parse(&DATA).unwrap();
+        assert_eq!(msg_ref.mobile_id(), Some(&DATA[2..7]));
+        assert_eq!(msg_ref.mobile_id_type(), Some(MobileIDType::Esn));
+        assert_eq!(msg_ref.service_type(), ServiceType::Acknowledged);
+        assert_eq!(msg_ref.message_type(), MessageType::EventReport);
+        assert_eq!(msg_ref.sequence_number().data(), 14982);
+
+        let report = msg_ref.event_report();
+        assert_eq!(report.update_time().secs(), 1609644628);
+        assert_eq!(report.latitude(), -236812936);
+        assert_eq!(report.rssi().dbm(), -115);
+        assert_eq!(report.event_index(), 123);
+        assert_eq!(report.accum_count(), 16);
+        assert_eq!(report.accumulators().count(), 16);
+        assert_eq!(report.accumulator(1), Some(0x101a));
+        assert_eq!(report.accumulator(16), None);
+
+        let owned = msg_ref.to_owned();
+        let parsed = crate::Message::parse(&DATA).unwrap();
+        assert_eq!(owned.msg, parsed.msg);
+        assert_eq!(
+            owned.options_header.unwrap().mobile_id,
+            parsed.options_header.unwrap().mobile_id
+        );
+        assert_eq!(
+            owned.message_header.sequence_number,
+            parsed.message_header.sequence_number
+        );
+    }
+
+    #[test]
+    fn test_message_ref_truncated() {
+        assert_eq!(
+            MessageRef::parse(&DATA[..100]).unwrap_err(),
+            Error::Incomplete { needed: 17 }
+        );
+        assert_eq!(
+            MessageRef::parse(&DATA[..4]).unwrap_err(),
+            Error::Incomplete { needed: 3 }
+        );
+        assert_eq!(
+            MessageRef::parse(&[]).unwrap_err(),
+            Error::Incomplete { needed: 1 }
+        );
+    }
+
+    #[test]
+    fn test_message_ref_append_blocks() {
+        let mut data = DATA.to_vec();
+        data[52] = 0x21;
+        let err = crate::Message::parse(&data).unwrap_err();
+        assert_eq!(MessageRef::parse(&data).unwrap_err(), err);
+
+        data.extend_from_slice(&[0x02, 0xab, 0xcd]);
+        assert_eq!(
+            MessageRef::parse(&data).unwrap_err(),
+            Error::Incomplete { needed: 1 }
+        );
+
+        data.push(0x00);
+        let msg_ref = MessageRef::parse(&data).unwrap();
+        let report = msg_ref.event_report();
+        assert_eq!(report.accumulators().count(), 16);
+        assert_eq!(report.appended(), &[0x02, 0xab, 0xcd, 0x00]);
+        assert_eq!(
+            msg_ref.to_owned().msg,
+            crate::Message::parse(&data).unwrap().msg
+        );
+    }
+
+    #[test]
+    fn test_message_ref_mobile_id_type_length() {
+        let data = [0x82, 0x02, 0x01, 0x00, 0x00, 0x07, 0x00, 0x01];
+        let err = Error::Malformed { offset: 1 };
+        assert_eq!(MessageRef::parse(&data).unwrap_err(), err);
+        assert_eq!(crate::Message::parse(&data).unwrap_err(), err);
+    }
+
+    #[test]
+    fn test_message_ref_unsupported_type() {
+        let data = [0x83, 0x01, 0x01, 0x01, 0x01, 0x00, 0x07, 0x00, 0x01];
+        assert_eq!(
+            MessageRef::parse(&data).unwrap_err(),
+            Error::UnsupportedMessageType(MessageType::UnitRequest)
+        );
+    }
+}
@@ -0,0 +1,190 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+use alloc::vec::Vec;
+
+use nom::IResult;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error as CalampError;
+use crate::message_header::{MessageHeader, MessageType, ServiceType};
+use crate::options_header::OptionsHeader;
+use crate::utils;
+
+/// Length of the Ack/Nak body: type, ack, spare and app version.
+pub const ACK_NAK_LEN: usize = 6;
+
+/// Ack/Nak message
+///
+/// Sent in response to an Acknowledged Request, it carries the type of the
+/// message being acknowledged and the result. The Sequence Number of the
+/// message header is the one of the request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AckNak {
+    /// The Message Type of the message being acknowledged.
+    pub message_type: MessageType,
+
+    /// 0 for an ACK, a NAK reason otherwise: 1 no reason, 2 not supported
+    /// message type, 3 not supported operation, 4 unable to pass to serial
+    /// port, 5 authentication failure, 6 mobile id lookup failure, 7 non
+    /// zero sequence number same as last received.
+    pub ack: u8,
+
+    /// App version of the sender, zero when sent by a server.
+    pub app_version: [u8; 3],
+}
+
+impl AckNak {
+    /// Positive acknowledgement of a message of `message_type`.
+    pub fn ack(message_type: MessageType) -> Self {
+        AckNak {
+            message_type,
+            ack: 0,
+            app_version: [0; 3],
+        }
+    }
+
+    /// Negative acknowledgement with a NAK `reason`.
+    pub fn nak(message_type: MessageType, reason: u8) -> Self {
+        AckNak {
+            message_type,
+            ack: reason,
+            app_version: [0; 3],
+        }
+    }
+
+    pub fn is_ack(&self) -> bool {
+        self.ack == 0
+    }
+
+    pub fn parse(input: &[u8]) -> IResult<&[u8], AckNak> {
+        let (i, message_type) = MessageType::parse(input)?;
+        let (i, ack) = utils::pu8(i)?;
+        let (i, _spare) = utils::pu8(i)?;
+        let (i, a) = utils::pu8(i)?;
+        let (i, b) = utils::pu8(i)?;
+        let (i, c) = utils::pu8(i)?;
+
+        Ok((
+            i,
+            AckNak {
+                message_type,
+                ack,
+                app_version: [a, b, c],
+            },
+        ))
+    }
+
+    /// Append the Ack/Nak body.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(u8::from(self.message_type));
+        buf.push(self.ack);
+        buf.push(0);
+        buf.extend_from_slice(&self.app_version);
+    }
+
+    /// Append a complete Ack/Nak message answering the request with
+    /// `options_header` and `message_header`. The options header of the
+    /// request is sent back so the reply names the same LMU.
+    pub fn encode_reply(
+        &self, options_header: Option<&OptionsHeader>,
+        message_header: &MessageHeader, buf: &mut Vec<u8>,
+    ) {
+        if let Some(options_header) = options_header {
+            options_header.encode(buf);
+        }
+        MessageHeader {
+            service_type: ServiceType::ResponseToAnAcknowledged,
+            message_type: MessageType::AckNak,
+            sequence_number: message_header.sequence_number,
+        }
+        .encode(buf);
+        self.encode(buf);
+    }
+}
+
+/// A complete Ack/Nak message, as sent by an LMU answering a request.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AckNakMessage {
+    pub options_header: Option<OptionsHeader>,
+    pub message_header: MessageHeader,
+    pub ack_nak: AckNak,
+}
+
+impl AckNakMessage {
+    pub fn parse(input: &[u8]) -> Result<Self, CalampError> {
+        let err = |e| utils::error(input, e);
+        let (i, options_header) = OptionsHeader::parse(input).map_err(err)?;
+        let (i, message_header) = MessageHeader::parse(i).map_err(err)?;
+        if message_header.message_type != MessageType::AckNak {
+            return Err(CalampError::UnsupportedMessageType(
+                message_header.message_type,
+            ));
+        }
+        let (_, ack_nak) = AckNak::parse(i).map_err(err)?;
+
+        Ok(AckNakMessage {
+            options_header,
+            message_header,
+            ack_nak,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AckNak;
+    use crate::message_header::{
+        MessageHeader, MessageType, SequenceNumber, ServiceType,
+    };
+    use crate::options_header::OptionsHeader;
+
+    #[test]
+    fn test_encode_reply() {
+        let request: [u8; 13] = [
+            0x83, 0x05, 0x46, 0x34, 0x66, 0x32, 0x35, 0x01, 0x01, 0x01, 0x02,
+            0x3a, 0x86,
+        ];
+        let (i, options_header) = OptionsHeader::parse(&request).unwrap();
+        let (_, message_header) = MessageHeader::parse(i).unwrap();
+
+        let mut buf = Vec::new();
+        AckNak::ack(message_header.message_type).encode_reply(
+            options_header.as_ref(),
+            &message_header,
+            &mut buf,
+        );
+        assert_eq!(
+            buf,
+            [
+                0x83, 0x05, 0x46, 0x34, 0x66, 0x32, 0x35, 0x01, 0x01, 0x02,
+                0x01, 0x3a, 0x86, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ]
+        );
+
+        let (i, reply_header) = MessageHeader::parse(&buf[9..]).unwrap();
+        assert_eq!(
+            reply_header.service_type,
+            ServiceType::ResponseToAnAcknowledged
+        );
+        assert_eq!(reply_header.message_type, MessageType::AckNak);
+        assert_eq!(reply_header.sequence_number, SequenceNumber::new(14982));
+        let (_, ack_nak) = AckNak::parse(i).unwrap();
+        assert!(ack_nak.is_ack());
+        assert_eq!(ack_nak.message_type, MessageType::EventReport);
+    }
+}
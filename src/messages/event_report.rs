@@ -12,7 +12,11 @@
 // OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
 //
 
-use std::fmt;
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
 
 use nom::bits::{bits, streaming};
 use nom::error::Error;
@@ -20,9 +24,9 @@ use nom::IResult;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
-#[cfg(feature = "chrono")]
-use chrono::{DateTime, TimeZone, Utc};
-
+use crate::error::Error as CalampError;
+use crate::timestamp::Timestamp;
+use crate::units::{Altitude, Hdop, Heading, Rssi, Speed};
 use crate::utils;
 
 #[derive(Debug, PartialEq)]
@@ -53,12 +57,15 @@ pub struct FixStatus {
 
     /// This bit is set only after a power-up or reset before a valid time-sync has been obtained.
     pub invalid_time: bool,
+
+    /// Bit 0, not defined by the protocol.
+    pub unused: bool,
 }
 
 impl FixStatus {
     pub fn parse(input: &[u8]) -> IResult<&[u8], FixStatus> {
         #[allow(clippy::type_complexity)]
-        let (i, b): (&[u8], (u8, u8, u8, u8, u8, u8, u8)) =
+        let (i, b): (&[u8], (u8, u8, u8, u8, u8, u8, u8, u8)) =
             bits::<_, _, Error<(&[u8], usize)>, _, _>(nom::sequence::tuple((
                 streaming::take(1u8),
                 streaming::take(1u8),
@@ -67,6 +74,7 @@ impl FixStatus {
                 streaming::take(1u8),
                 streaming::take(1u8),
                 streaming::take(1u8),
+                streaming::take(1u8),
             )))(input)?;
         Ok((
             i,
@@ -78,13 +86,28 @@ impl FixStatus {
                 twod_fix: b.2 == 1,
                 historic: b.1 == 1,
                 invalid_time: b.0 == 1,
+                unused: b.7 == 1,
             },
         ))
     }
 }
 
-#[derive(PartialEq)]
+impl From<&FixStatus> for u8 {
+    fn from(fix_status: &FixStatus) -> u8 {
+        u8::from(fix_status.invalid_time) << 7
+            | u8::from(fix_status.historic) << 6
+            | u8::from(fix_status.twod_fix) << 5
+            | u8::from(fix_status.invalid_fix) << 4
+            | u8::from(fix_status.last_know) << 3
+            | u8::from(fix_status.diff_corrected) << 2
+            | u8::from(fix_status.predicted) << 1
+            | u8::from(fix_status.unused)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub enum NetworkTechnology {
     /// 2G
     CdmaGsm,
@@ -97,16 +120,61 @@ pub enum NetworkTechnology {
 
     /// Reserved network
     Reserved,
+
+    /// Network technology not known to this crate
+    Unknown(u8),
 }
 
 impl NetworkTechnology {
+    /// Map the two Network Technology bits (bits 6-7 of Comm State).
     pub fn parse(input: u8) -> NetworkTechnology {
         match input {
-            0 => NetworkTechnology::CdmaGsm,
-            1 => NetworkTechnology::Umts,
-            10 => NetworkTechnology::Lte,
-            11 => NetworkTechnology::Reserved,
-            _ => panic!("not found"),
+            0b00 => NetworkTechnology::CdmaGsm,
+            0b01 => NetworkTechnology::Umts,
+            0b10 => NetworkTechnology::Lte,
+            0b11 => NetworkTechnology::Reserved,
+            _ => NetworkTechnology::Unknown(input),
+        }
+    }
+
+    /// Snake case name, `unknown_<n>` for a value without a name.
+    pub fn name(&self) -> Cow<'static, str> {
+        Cow::Borrowed(match *self {
+            NetworkTechnology::CdmaGsm => "cdma_gsm",
+            NetworkTechnology::Umts => "umts",
+            NetworkTechnology::Lte => "lte",
+            NetworkTechnology::Reserved => "reserved",
+            NetworkTechnology::Unknown(value) => {
+                return Cow::Owned(format!("unknown_{}", value))
+            }
+        })
+    }
+}
+
+impl TryFrom<u8> for NetworkTechnology {
+    type Error = CalampError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match NetworkTechnology::parse(value) {
+            NetworkTechnology::Unknown(value) => {
+                Err(CalampError::UnknownValue {
+                    kind: "NetworkTechnology",
+                    value,
+                })
+            }
+            network_technology => Ok(network_technology),
+        }
+    }
+}
+
+impl From<NetworkTechnology> for u8 {
+    fn from(network_technology: NetworkTechnology) -> u8 {
+        match network_technology {
+            NetworkTechnology::CdmaGsm => 0b00,
+            NetworkTechnology::Umts => 0b01,
+            NetworkTechnology::Lte => 0b10,
+            NetworkTechnology::Reserved => 0b11,
+            NetworkTechnology::Unknown(value) => value,
         }
     }
 }
@@ -122,6 +190,9 @@ impl fmt::Display for NetworkTechnology {
             NetworkTechnology::Reserved => {
                 write!(f, "NetworkTechnology::Reserved")
             }
+            NetworkTechnology::Unknown(value) => {
+                write!(f, "NetworkTechnology::Unknown({})", value)
+            }
         }
     }
 }
@@ -137,6 +208,9 @@ impl fmt::Debug for NetworkTechnology {
             NetworkTechnology::Reserved => {
                 write!(f, "NetworkTechnology::Reserved")
             }
+            NetworkTechnology::Unknown(value) => {
+                write!(f, "NetworkTechnology::Unknown({})", value)
+            }
         }
     }
 }
@@ -168,16 +242,18 @@ pub struct CommState {
 
 impl CommState {
     pub fn parse(input: &[u8]) -> IResult<&[u8], CommState> {
+        // Bits are taken most significant first: Network Technology lives in
+        // bits 6-7 and Available in bit 0.
         #[allow(clippy::type_complexity)]
         let (i, b): (&[u8], (u8, u8, u8, u8, u8, u8, u8)) =
             bits::<_, _, Error<(&[u8], usize)>, _, _>(nom::sequence::tuple((
+                streaming::take(2u8),
                 streaming::take(1u8),
                 streaming::take(1u8),
                 streaming::take(1u8),
                 streaming::take(1u8),
                 streaming::take(1u8),
                 streaming::take(1u8),
-                streaming::take(2u8),
             )))(input)?;
         Ok((
             i,
@@ -194,6 +270,18 @@ impl CommState {
     }
 }
 
+impl From<&CommState> for u8 {
+    fn from(comm_state: &CommState) -> u8 {
+        (u8::from(comm_state.network_technology) & 0b11) << 6
+            | u8::from(comm_state.roaming) << 5
+            | u8::from(comm_state.voice_call_active) << 4
+            | u8::from(comm_state.connected) << 3
+            | u8::from(comm_state.data_service) << 2
+            | u8::from(comm_state.network_service) << 1
+            | u8::from(comm_state.available)
+    }
+}
+
 #[derive(Debug, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Inputs {
@@ -237,6 +325,19 @@ impl Inputs {
     }
 }
 
+impl From<&Inputs> for u8 {
+    fn from(inputs: &Inputs) -> u8 {
+        u8::from(inputs.input_7) << 7
+            | u8::from(inputs.input_6) << 6
+            | u8::from(inputs.input_5) << 5
+            | u8::from(inputs.input_4) << 4
+            | u8::from(inputs.input_3) << 3
+            | u8::from(inputs.input_2) << 2
+            | u8::from(inputs.input_1) << 1
+            | u8::from(inputs.ignition)
+    }
+}
+
 #[derive(Debug, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct UnitStatus {
@@ -295,6 +396,20 @@ impl UnitStatus {
     }
 }
 
+/// The fields are parsed as "bit is clear", so they are inverted back.
+impl From<&UnitStatus> for u8 {
+    fn from(unit_status: &UnitStatus) -> u8 {
+        !(u8::from(unit_status.unused) << 7
+            | u8::from(unit_status.reserved_3) << 6
+            | u8::from(unit_status.reserved_2) << 5
+            | u8::from(unit_status.reserved_1) << 4
+            | u8::from(unit_status.gps_tracking) << 3
+            | u8::from(unit_status.gps_self_test) << 2
+            | u8::from(unit_status.gps_antenna) << 1
+            | u8::from(unit_status.ota_update))
+    }
+}
+
 /// Event Report messages
 ///
 /// Initiated by the LMU and are generated by the LMU’s Programmable Event
@@ -305,35 +420,29 @@ impl UnitStatus {
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct EventReport {
     /// The time tag of the message in seconds.
-    #[cfg(feature = "chrono")]
-    pub update_time: DateTime<Utc>,
-    #[cfg(not(feature = "chrono"))]
-    pub update_time: u32,
+    pub update_time: Timestamp,
 
     /// The last known time of fix from the GPS satellites.
-    #[cfg(feature = "chrono")]
-    pub time_of_fix: DateTime<Utc>,
-    #[cfg(not(feature = "chrono"))]
-    pub time_of_fix: u32,
+    pub time_of_fix: Timestamp,
 
     /// The latitude reading of the GPS receiver, measured in degrees with a
     /// 1x10^-7 degree lsb, signed 2’s complement.
-    pub latitude: f32,
+    pub latitude: i32,
 
     /// The longitude reading of the GPS receiver, measured in degrees with a
     /// 1x10^-7 degree lsb, signed 2’s complement.
-    pub longitude: f32,
+    pub longitude: i32,
 
     /// The altitude reading of the GPS receiver measured in centimeters above
     /// the WGS-84 Datum, signed 2’s complement.
-    pub altitude: f32,
+    pub altitude: Altitude,
 
     /// The speed as reported by the GPS receiver, measured in centimeters per
     /// second.
-    pub speed: f32,
+    pub speed: Speed,
 
     /// The heading value reported in degrees from true North.
-    pub heading: u16,
+    pub heading: Heading,
 
     /// The number of satellites used in the GPS solution.
     pub satellites: u8,
@@ -349,7 +458,7 @@ pub struct EventReport {
     // pub carrier_ms: u16,
     /// The received signal strength of the wireless modem in dBm. This value is
     /// signed in a 2’s complement format.
-    pub rssi: i16,
+    pub rssi: Rssi,
 
     /// The current state of the wireless modem bit mapped as follows
     ///
@@ -378,7 +487,7 @@ pub struct EventReport {
 
     /// The GPS Horizontal Dilution of Precision - it is a unit-less value
     /// reported with a 0.1 lsb.
-    pub hdop: u8,
+    pub hdop: Hdop,
 
     /// The current state of the inputs, bit mapped as follows:
     ///
@@ -447,74 +556,156 @@ pub struct EventReport {
     /// Format Type also defined in the Accums field. Refer to Appendix G,
     /// 'Accumulator Reporting Formats' for details.
     pub accum_list: Vec<u32>,
+
+    /// The data types flagged in 'Append' as sent, each one still starting
+    /// with its length byte.
+    pub appended: Vec<u8>,
 }
 
+/// Scale of the latitude and longitude fields, 1x10^-7 degree lsb.
+pub(crate) const COORDINATE_LSB: f64 = 1e-7;
+
 impl EventReport {
+    /// Latitude in degrees, full precision.
+    pub fn latitude_deg(&self) -> f64 {
+        f64::from(self.latitude) * COORDINATE_LSB
+    }
+
+    /// Longitude in degrees, full precision.
+    pub fn longitude_deg(&self) -> f64 {
+        f64::from(self.longitude) * COORDINATE_LSB
+    }
+
     /// Parse event report
-    pub fn parse(input: &[u8]) -> std::io::Result<EventReport> {
-        let mut accum_list: Vec<u32> = vec![];
-        #[cfg(feature = "chrono")]
-        let (i, update_time) = utils::pdt(input).unwrap();
-        #[cfg(not(feature = "chrono"))]
-        let (i, update_time) = utils::pu32(input).unwrap();
-        let (i, time_of_fix) = utils::pu32(i).unwrap();
-        let (i, latitude) = utils::pf32(i).unwrap();
-        let (i, longitude) = utils::pf32(i).unwrap();
-        let (i, altitude) = utils::pf32(i).unwrap();
-        let (i, speed) = utils::pf32(i).unwrap();
-        let (i, heading) = utils::pu16(i).unwrap();
-        let (i, satellites) = utils::pu8(i).unwrap();
-        let (i, fix_status) = FixStatus::parse(i).unwrap();
-        let (i, carrier) = utils::pu16(i).unwrap();
-        let (i, rssi) = utils::p16(i).unwrap();
-        let (i, comm_state) = CommState::parse(i).unwrap();
-        let (i, hdop) = utils::pu8(i).unwrap();
-        let (i, inputs) = Inputs::parse(i).unwrap();
-        let (i, unit_status) = UnitStatus::parse(i).unwrap();
-        let (i, event_index) = utils::pu8(i).unwrap();
-        let (i, event_code) = utils::pu8(i).unwrap();
-        let (i, accums) = utils::pu8(i).unwrap();
-        let (i, append) = utils::pu8(i).unwrap();
+    pub fn parse(input: &[u8]) -> Result<EventReport, CalampError> {
+        EventReport::parse_fields(input)
+            .map(|(_, event_report)| event_report)
+            .map_err(|e| utils::error(input, e))
+    }
+
+    /// Append the Event Report body, the inverse of [`EventReport::parse`].
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.update_time.secs().to_be_bytes());
+        buf.extend_from_slice(&self.time_of_fix.secs().to_be_bytes());
+        buf.extend_from_slice(&self.latitude.to_be_bytes());
+        buf.extend_from_slice(&self.longitude.to_be_bytes());
+        buf.extend_from_slice(&self.altitude.raw().to_be_bytes());
+        buf.extend_from_slice(&self.speed.raw().to_be_bytes());
+        buf.extend_from_slice(&self.heading.raw().to_be_bytes());
+        buf.push(self.satellites);
+        buf.push(u8::from(&self.fix_status));
+        buf.extend_from_slice(&self.carrier.to_be_bytes());
+        buf.extend_from_slice(&self.rssi.raw().to_be_bytes());
+        buf.push(u8::from(&self.comm_state));
+        buf.push(self.hdop.raw());
+        buf.push(u8::from(&self.inputs));
+        buf.push(u8::from(&self.unit_status));
+        buf.push(self.event_index);
+        buf.push(self.event_code);
+        buf.push(self.accums);
+        buf.push(self.append);
+        for accum in &self.accum_list {
+            buf.extend_from_slice(&accum.to_be_bytes());
+        }
+        buf.extend_from_slice(&self.appended);
+    }
+
+    pub(crate) fn parse_fields(input: &[u8]) -> IResult<&[u8], EventReport> {
+        let mut accum_list: Vec<u32> = Vec::new();
+        let (i, update_time) = utils::pu32(input)?;
+        let (i, time_of_fix) = utils::pu32(i)?;
+        let (i, latitude) = utils::pi32(i)?;
+        let (i, longitude) = utils::pi32(i)?;
+        let (i, altitude) = utils::pi32(i)?;
+        let (i, speed) = utils::pu32(i)?;
+        let (i, heading) = utils::pu16(i)?;
+        let (i, satellites) = utils::pu8(i)?;
+        let (i, fix_status) = FixStatus::parse(i)?;
+        let (i, carrier) = utils::pu16(i)?;
+        let (i, rssi) = utils::p16(i)?;
+        let (i, comm_state) = CommState::parse(i)?;
+        let (i, hdop) = utils::pu8(i)?;
+        let (i, inputs) = Inputs::parse(i)?;
+        let (i, unit_status) = UnitStatus::parse(i)?;
+        let (i, event_index) = utils::pu8(i)?;
+        let (i, event_code) = utils::pu8(i)?;
+        let (i, accums) = utils::pu8(i)?;
+        let (i, append) = utils::pu8(i)?;
         let mut inp = i;
-        for _ in 0..accums {
-            let (x, v) = utils::pu32(inp).unwrap();
+        // The upper 2 bits are the Accumulator Reporting Format Type.
+        for _ in 0..(accums & 0x3f) {
+            let (x, v) = utils::pu32(inp)?;
             inp = x;
             accum_list.push(v);
         }
+        let start = inp;
+        for bit in 0..8 {
+            if append & (1 << bit) != 0 {
+                let (x, len) = utils::pu8(inp)?;
+                inp = nom::bytes::streaming::take(len)(x)?.0;
+            }
+        }
+        let appended = start[..start.len() - inp.len()].to_vec();
 
-        Ok(EventReport {
-            update_time,
-            time_of_fix,
-            latitude,
-            longitude,
-            altitude,
-            speed,
-            heading,
-            satellites,
-            fix_status,
-            carrier,
-            rssi,
-            comm_state,
-            hdop,
-            inputs,
-            unit_status,
-            event_index,
-            event_code,
-            accums,
-            append,
-            accum_list,
-        })
+        Ok((
+            inp,
+            EventReport {
+                update_time: Timestamp::new(update_time),
+                time_of_fix: Timestamp::new(time_of_fix),
+                latitude,
+                longitude,
+                altitude: Altitude::new(altitude),
+                speed: Speed::new(speed),
+                heading: Heading::new(heading),
+                satellites,
+                fix_status,
+                carrier,
+                rssi: Rssi::new(rssi),
+                comm_state,
+                hdop: Hdop::new(hdop),
+                inputs,
+                unit_status,
+                event_index,
+                event_code,
+                accums,
+                append,
+                appended,
+                accum_list,
+            },
+        ))
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use super::EventReport;
+    use super::{CommState, EventReport, FixStatus};
     use crate::message_header::MessageHeader;
     use crate::messages::event_report::NetworkTechnology;
     use crate::options_header::OptionsHeader;
 
+    #[test]
+    fn test_encode_event_report() {
+        let data: [u8; 107] = [
+            0x5f, 0xf1, 0x3a, 0x54, 0x5f, 0xf1, 0x3a, 0x57, 0xf1, 0xe2, 0x85,
+            0x78, 0xe4, 0x22, 0xd6, 0x40, 0x00, 0x01, 0x36, 0xf8, 0x00, 0x00,
+            0x00, 0x0b, 0x00, 0x00, 0x06, 0x20, 0x00, 0x00, 0xff, 0x8d, 0x02,
+            0x1e, 0x1e, 0x00, 0x7b, 0x21, 0x10, 0x01, 0x00, 0x00, 0x31, 0xe0,
+            0x00, 0x00, 0x10, 0x1a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x22, 0x2a,
+            0x32, 0x00, 0x00, 0x03, 0xf1, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x01, 0xc8, 0x2d, 0x3f, 0x01, 0xc8, 0x2d, 0x3f, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x01, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0xab, 0xcd,
+        ];
+        let event_report = EventReport::parse(&data).unwrap();
+        assert_eq!(event_report.appended, [0x02, 0xab, 0xcd]);
+
+        let mut buf = Vec::new();
+        event_report.encode(&mut buf);
+        assert_eq!(buf, data);
+    }
+
     #[test]
     fn test_parse_event_report_message() {
         let data: [u8; 117] = [
@@ -534,32 +725,61 @@ mod tests {
         let (i, _) = MessageHeader::parse(i).unwrap();
         let event_report = EventReport::parse(i).unwrap();
 
-        assert_eq!(event_report.update_time, 1609644628);
-        assert_eq!(event_report.time_of_fix, 1609644631);
-        approx::assert_relative_eq!(event_report.latitude, -23.6812936);
-        approx::assert_relative_eq!(
-            event_report.longitude,
-            -46.747897599999995
-        );
-        approx::assert_relative_eq!(event_report.altitude, 0.0079608);
-        approx::assert_relative_eq!(event_report.speed, 0.0000011);
-        assert_eq!(event_report.heading, 0);
+        assert_eq!(event_report.update_time.secs(), 1609644628);
+        assert_eq!(event_report.time_of_fix.secs(), 1609644631);
+        assert_eq!(event_report.latitude, -236812936);
+        assert_eq!(event_report.longitude, -467478976);
+        approx::assert_relative_eq!(event_report.latitude_deg(), -23.6812936);
+        approx::assert_relative_eq!(event_report.longitude_deg(), -46.7478976);
+        assert_eq!(event_report.altitude.raw(), 79608);
+        approx::assert_relative_eq!(event_report.altitude.meters(), 796.08);
+        assert_eq!(event_report.speed.raw(), 11);
+        approx::assert_relative_eq!(event_report.speed.mps(), 0.11);
+        approx::assert_relative_eq!(event_report.speed.kmh(), 0.396);
+        assert_eq!(event_report.heading.degrees(), Some(0));
         assert_eq!(event_report.satellites, 6);
-        assert_eq!(event_report.fix_status.twod_fix, true);
+        assert!(event_report.fix_status.twod_fix);
         assert_eq!(event_report.carrier, 0);
-        assert_eq!(event_report.rssi, -115);
-        assert_eq!(event_report.comm_state.available, false);
+        assert_eq!(event_report.rssi.dbm(), -115);
+        assert!(!event_report.comm_state.available);
+        assert!(event_report.comm_state.network_service);
         assert_eq!(
             event_report.comm_state.network_technology,
             NetworkTechnology::CdmaGsm
         );
-        assert_eq!(event_report.hdop, 30);
-        assert_eq!(event_report.inputs.ignition, false);
-        assert_eq!(event_report.unit_status.gps_antenna, true);
+        assert_eq!(event_report.hdop.raw(), 30);
+        approx::assert_relative_eq!(event_report.hdop.value(), 3.0);
+        assert!(!event_report.inputs.ignition);
+        assert!(event_report.unit_status.gps_antenna);
         assert_eq!(event_report.event_index, 123);
         assert_eq!(event_report.event_code, 33);
         assert_eq!(event_report.accums, 16);
         assert_eq!(event_report.append, 0);
         assert_eq!(event_report.accum_list.len(), 16);
     }
+
+    #[test]
+    fn test_fix_status_round_trip() {
+        for byte in [0x01, 0x21, 0xff] {
+            let (_, fix_status) = FixStatus::parse(&[byte]).unwrap();
+            assert_eq!(u8::from(&fix_status), byte);
+        }
+        let (_, fix_status) = FixStatus::parse(&[0x01]).unwrap();
+        assert!(fix_status.unused);
+        assert!(!fix_status.predicted);
+    }
+
+    #[test]
+    fn test_parse_comm_state_lte() {
+        let (_, comm_state) = CommState::parse(&[0b1010_0011]).unwrap();
+        assert_eq!(comm_state.network_technology, NetworkTechnology::Lte);
+        assert!(comm_state.available);
+        assert!(comm_state.network_service);
+        assert!(!comm_state.data_service);
+        assert!(comm_state.roaming);
+
+        let (_, comm_state) = CommState::parse(&[0b1100_0000]).unwrap();
+        assert_eq!(comm_state.network_technology, NetworkTechnology::Reserved);
+        assert_eq!(u8::from(NetworkTechnology::Lte), 0b10);
+    }
 }
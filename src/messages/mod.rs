@@ -1 +1,2 @@
+pub mod ack_nak;
 pub mod event_report;
@@ -12,19 +12,33 @@
 // OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
 //
 
-use std::fmt;
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+use core::net::Ipv4Addr;
 
 use nom::bits::{bits, streaming};
-use nom::error::{Error, ErrorKind};
-use nom::number::streaming::be_u8;
+use nom::error::Error;
 use nom::IResult;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
-const OPTIONS_HEADER: u8 = 0x83;
+use crate::error::Error as CalampError;
+use crate::utils;
 
-#[derive(Clone, PartialEq, Eq)]
+/// Options byte with the Mobile ID and Mobile ID Type fields set, sent by
+/// nearly every LMU. Used to find the start of a message in a byte stream.
+pub(crate) const OPTIONS_HEADER: u8 = 0x83;
+
+/// Bit 7 of the first byte is always set when the options header exists.
+pub(crate) const OPTIONS_HEADER_FLAG: u8 = 0x80;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub enum MobileIDType {
     Off,
 
@@ -51,23 +65,89 @@ pub enum MobileIDType {
     /// CDMA Mobile Equipment ID (MEID) or International Mobile Equipment
     /// Identifier (IMEI) of the wireless modem
     Cdma,
+
+    /// Mobile ID type not known to this crate
+    Unknown(u8),
 }
 
 impl MobileIDType {
+    /// Parse the field, length byte included. The type takes one byte,
+    /// other lengths are rejected.
     pub fn parse(input: &[u8]) -> IResult<&[u8], MobileIDType> {
-        let (i, _): (&[u8], u8) = be_u8::<_, (_, ErrorKind)>(input).unwrap();
-        let (i, b): (&[u8], u8) = be_u8::<_, (_, ErrorKind)>(i).unwrap();
+        let (i, value) = skip_field(input)?;
+        match value {
+            [b] => Ok((i, MobileIDType::from_u8(*b))),
+            _ => Err(nom::Err::Error(Error::new(
+                input,
+                nom::error::ErrorKind::LengthValue,
+            ))),
+        }
+    }
+
+    /// Append the field, length byte included.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(1);
+        buf.push(u8::from(*self));
+    }
 
+    pub(crate) fn from_u8(b: u8) -> MobileIDType {
         match b {
-            0 => Ok((i, MobileIDType::Off)),
-            1 => Ok((i, MobileIDType::Esn)),
-            2 => Ok((i, MobileIDType::Equipment)),
-            3 => Ok((i, MobileIDType::Subscriber)),
-            4 => Ok((i, MobileIDType::Defined)),
-            5 => Ok((i, MobileIDType::PhoneNumber)),
-            6 => Ok((i, MobileIDType::IpAddress)),
-            7 => Ok((i, MobileIDType::Cdma)),
-            _ => panic!("not found"),
+            0 => MobileIDType::Off,
+            1 => MobileIDType::Esn,
+            2 => MobileIDType::Equipment,
+            3 => MobileIDType::Subscriber,
+            4 => MobileIDType::Defined,
+            5 => MobileIDType::PhoneNumber,
+            6 => MobileIDType::IpAddress,
+            7 => MobileIDType::Cdma,
+            _ => MobileIDType::Unknown(b),
+        }
+    }
+
+    /// Snake case name, `unknown_<n>` for a value without a name.
+    pub fn name(&self) -> Cow<'static, str> {
+        Cow::Borrowed(match *self {
+            MobileIDType::Off => "off",
+            MobileIDType::Esn => "esn",
+            MobileIDType::Equipment => "equipment",
+            MobileIDType::Subscriber => "subscriber",
+            MobileIDType::Defined => "defined",
+            MobileIDType::PhoneNumber => "phone_number",
+            MobileIDType::IpAddress => "ip_address",
+            MobileIDType::Cdma => "cdma",
+            MobileIDType::Unknown(value) => {
+                return Cow::Owned(format!("unknown_{}", value))
+            }
+        })
+    }
+}
+
+impl TryFrom<u8> for MobileIDType {
+    type Error = CalampError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match MobileIDType::from_u8(value) {
+            MobileIDType::Unknown(value) => Err(CalampError::UnknownValue {
+                kind: "MobileIDType",
+                value,
+            }),
+            mobile_id_type => Ok(mobile_id_type),
+        }
+    }
+}
+
+impl From<MobileIDType> for u8 {
+    fn from(mobile_id_type: MobileIDType) -> u8 {
+        match mobile_id_type {
+            MobileIDType::Off => 0,
+            MobileIDType::Esn => 1,
+            MobileIDType::Equipment => 2,
+            MobileIDType::Subscriber => 3,
+            MobileIDType::Defined => 4,
+            MobileIDType::PhoneNumber => 5,
+            MobileIDType::IpAddress => 6,
+            MobileIDType::Cdma => 7,
+            MobileIDType::Unknown(value) => value,
         }
     }
 }
@@ -83,6 +163,9 @@ impl fmt::Display for MobileIDType {
             MobileIDType::PhoneNumber => write!(f, "MobileIDType::PhoneNumber"),
             MobileIDType::IpAddress => write!(f, "MobileIDType::IpAddress"),
             MobileIDType::Cdma => write!(f, "MobileIDType::Cdma"),
+            MobileIDType::Unknown(value) => {
+                write!(f, "MobileIDType::Unknown({})", value)
+            }
         }
     }
 }
@@ -98,31 +181,170 @@ impl fmt::Debug for MobileIDType {
             MobileIDType::PhoneNumber => write!(f, "MobileIDType::PhoneNumber"),
             MobileIDType::IpAddress => write!(f, "MobileIDType::IpAddress"),
             MobileIDType::Cdma => write!(f, "MobileIDType::Cdma"),
+            MobileIDType::Unknown(value) => {
+                write!(f, "MobileIDType::Unknown({})", value)
+            }
         }
     }
 }
 
-#[derive(Debug)]
+/// Mobile ID
+///
+/// The raw bytes of the identifier as sent by the LMU. How they should be
+/// read depends on the accompanying [`MobileIDType`]: ESN, IMEI, IMSI, MEID
+/// and phone numbers are BCD packed, the IP address is four octets and the
+/// user defined ID is ASCII. Use [`MobileID::decode`] to pick the right
+/// representation, or one of the typed accessors directly.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-pub struct MobileID(pub String);
+pub struct MobileID(Vec<u8>);
 
 impl MobileID {
+    pub fn new(bytes: &[u8]) -> Self {
+        Self(bytes.to_vec())
+    }
+
+    /// Number of raw bytes in the identifier.
     pub fn len(&self) -> usize {
         self.0.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
     pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
-        let (i, a): (&[u8], u8) = be_u8::<_, (_, ErrorKind)>(input).unwrap();
+        let (i, a) = utils::pu8(input)?;
         let (i, b): (&[u8], &[u8]) = nom::bytes::streaming::take(a)(i)?;
-        let mut id = String::from("");
-        for d in b.iter() {
-            id.push_str(&format!("{0:2x}", d))
+        Ok((i, Self::new(b)))
+    }
+
+    /// Append the field, length byte included.
+    ///
+    /// # Panics
+    ///
+    /// Panics when the identifier is longer than 255 bytes.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let len = u8::try_from(self.0.len()).expect("mobile id too long");
+        buf.push(len);
+        buf.extend_from_slice(&self.0);
+    }
+
+    /// Pack decimal `digits` as BCD, high nibble first, with a filler
+    /// nibble after an odd number of digits. The inverse of
+    /// [`MobileID::as_bcd_digits`], `None` when `digits` is not all digits.
+    pub fn from_bcd_digits(digits: &str) -> Option<Self> {
+        let nibbles = digits
+            .bytes()
+            .map(|b| {
+                if b.is_ascii_digit() {
+                    Some(b - b'0')
+                } else {
+                    None
+                }
+            })
+            .collect::<Option<Vec<u8>>>()?;
+        let bytes = nibbles
+            .chunks(2)
+            .map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0x0f))
+            .collect::<Vec<u8>>();
+        Some(Self(bytes))
+    }
+
+    /// BCD packed digits, high nibble first. Filler nibbles (0xF) are
+    /// skipped; any other nibble above 9 makes the decoding fail.
+    pub fn as_bcd_digits(&self) -> Option<String> {
+        let mut digits = String::with_capacity(self.0.len() * 2);
+        for b in self.0.iter() {
+            for nibble in [b >> 4, b & 0x0f].iter() {
+                match nibble {
+                    0..=9 => digits.push((b'0' + nibble) as char),
+                    0x0f => (),
+                    _ => return None,
+                }
+            }
         }
-        Ok((i, Self(id)))
+        Some(digits)
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.len() == 0
+    /// Electronic Serial Number, BCD packed (usually 10 digits).
+    pub fn as_esn(&self) -> Option<String> {
+        self.as_bcd_digits()
+    }
+
+    /// International Mobile Equipment Identifier, BCD packed with a filler
+    /// nibble. A valid IMEI has 14 to 16 digits (IMEI or IMEISV).
+    pub fn as_imei(&self) -> Option<String> {
+        self.as_bcd_digits()
+            .filter(|digits| (14..=16).contains(&digits.len()))
+    }
+
+    /// Mobile Equipment Identifier of a CDMA modem, 56 bits written as 14
+    /// upper case hex digits. A longer identifier is accepted when the extra
+    /// leading bytes are zero padding.
+    pub fn as_meid(&self) -> Option<String> {
+        let start = self.0.len().checked_sub(7)?;
+        if self.0[..start].iter().any(|b| *b != 0) {
+            return None;
+        }
+        let mut meid = String::with_capacity(14);
+        for d in self.0[start..].iter() {
+            meid.push_str(&format!("{:02X}", d))
+        }
+        Some(meid)
+    }
+
+    /// IPv4 address of the LMU, the identifier must be exactly four octets.
+    pub fn as_ipv4(&self) -> Option<Ipv4Addr> {
+        match self.0[..] {
+            [a, b, c, d] => Some(Ipv4Addr::new(a, b, c, d)),
+            _ => None,
+        }
+    }
+
+    /// User defined identifier, only if every byte is printable ASCII.
+    pub fn as_ascii(&self) -> Option<&str> {
+        if self.0.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+            core::str::from_utf8(&self.0).ok()
+        } else {
+            None
+        }
+    }
+
+    /// Lower case hex of the raw bytes, zero padded.
+    pub fn to_hex(&self) -> String {
+        let mut id = String::with_capacity(self.0.len() * 2);
+        for d in self.0.iter() {
+            id.push_str(&format!("{:02x}", d))
+        }
+        id
+    }
+
+    /// Decode the identifier according to its type, falling back to hex
+    /// when the bytes do not fit the expected representation.
+    pub fn decode(&self, mobile_id_type: &MobileIDType) -> String {
+        let decoded = match mobile_id_type {
+            MobileIDType::Esn => self.as_esn(),
+            MobileIDType::Equipment => self.as_imei(),
+            MobileIDType::Cdma => self.as_meid(),
+            MobileIDType::Subscriber | MobileIDType::PhoneNumber => {
+                self.as_bcd_digits()
+            }
+            MobileIDType::IpAddress => self.as_ipv4().map(|ip| ip.to_string()),
+            MobileIDType::Defined => self.as_ascii().map(String::from),
+            MobileIDType::Off | MobileIDType::Unknown(_) => None,
+        };
+        decoded.unwrap_or_else(|| self.to_hex())
+    }
+}
+
+impl fmt::Display for MobileID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_hex())
     }
 }
 
@@ -189,7 +411,7 @@ impl OptionsStatus {
 }
 
 fn is_options_header(input: u8) -> bool {
-    input == OPTIONS_HEADER
+    input & OPTIONS_HEADER_FLAG != 0
 }
 
 #[derive(Debug)]
@@ -201,27 +423,37 @@ pub struct OptionsHeader {
     /// Mobile id type
     pub mobile_id_type: Option<MobileIDType>,
 
-    /// Authentication World
-    pub authentication_world: Option<bool>,
+    /// Authentication World, the raw field without its length byte.
+    pub authentication_world: Option<Vec<u8>>,
 
-    /// Routing
-    pub routing: Option<bool>,
+    /// Routing, the raw field without its length byte.
+    pub routing: Option<Vec<u8>>,
 
-    /// Forwarding
-    pub forwarding: Option<bool>,
+    /// Forwarding, the raw field without its length byte.
+    pub forwarding: Option<Vec<u8>>,
 
-    /// Response Redirection
-    pub response_redirection: Option<bool>,
+    /// Response Redirection, the raw field without its length byte.
+    pub response_redirection: Option<Vec<u8>>,
 
-    /// Options Extension
-    pub options_extension: Option<bool>,
+    /// Options Extension, the raw field without its length byte.
+    pub options_extension: Option<Vec<u8>>,
 }
 
 impl OptionsHeader {
+    /// The mobile id decoded according to the mobile id type, when both are
+    /// present. Without a type the raw bytes are rendered as hex.
+    pub fn mobile_id_string(&self) -> Option<String> {
+        let mobile_id = self.mobile_id.as_ref()?;
+        match &self.mobile_id_type {
+            Some(tp) => Some(mobile_id.decode(tp)),
+            None => Some(mobile_id.to_hex()),
+        }
+    }
+
     pub fn parse(input: &[u8]) -> IResult<&[u8], Option<Self>> {
-        // FIX: return error
-        if !is_options_header(input[0]) {
-            panic!("");
+        let (_, first) = utils::pu8(input)?;
+        if !is_options_header(first) {
+            return Ok((input, None));
         }
 
         let (i, opt_status) = parse_options_status(input)?;
@@ -234,11 +466,11 @@ impl OptionsHeader {
             response_redirection: None,
             options_extension: None,
         };
-        let mut inp: &[u8] = &[];
+        let mut inp: &[u8] = i;
 
         // check mobile id
         if opt_status.is_mobile_id() {
-            let (i, mob_id) = MobileID::parse(i)?;
+            let (i, mob_id) = MobileID::parse(inp)?;
             opt_header.mobile_id = Some(mob_id);
             inp = i;
         }
@@ -250,29 +482,80 @@ impl OptionsHeader {
             inp = i;
         }
 
-        if opt_status.is_authentication_world() {
-            unimplemented!()
-        }
-        if opt_status.is_routing() {
-            unimplemented!()
+        // The remaining fields are not decoded yet, their bytes are kept so
+        // the header encodes back unchanged.
+        let fields = [
+            (
+                opt_status.is_authentication_world(),
+                &mut opt_header.authentication_world,
+            ),
+            (opt_status.is_routing(), &mut opt_header.routing),
+            (opt_status.is_forwarding(), &mut opt_header.forwarding),
+            (
+                opt_status.is_response_redirection(),
+                &mut opt_header.response_redirection,
+            ),
+            (
+                opt_status.is_options_extension(),
+                &mut opt_header.options_extension,
+            ),
+        ];
+        for (present, field) in fields {
+            if present {
+                let (i, raw) = skip_field(inp)?;
+                *field = Some(raw.to_vec());
+                inp = i;
+            }
         }
 
-        if opt_status.is_forwarding() {
-            unimplemented!()
-        }
+        Ok((inp, Some(opt_header)))
+    }
 
-        if opt_status.is_response_redirection() {
-            unimplemented!()
+    /// Append the options byte and the option fields.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let raw = [
+            &self.authentication_world,
+            &self.routing,
+            &self.forwarding,
+            &self.response_redirection,
+            &self.options_extension,
+        ];
+        let flags = [
+            self.mobile_id.is_some(),
+            self.mobile_id_type.is_some(),
+            raw[0].is_some(),
+            raw[1].is_some(),
+            raw[2].is_some(),
+            raw[3].is_some(),
+            raw[4].is_some(),
+        ];
+        let options = flags
+            .iter()
+            .enumerate()
+            .filter(|(_, set)| **set)
+            .fold(OPTIONS_HEADER_FLAG, |acc, (bit, _)| acc | 1 << bit);
+        buf.push(options);
+
+        if let Some(mobile_id) = &self.mobile_id {
+            mobile_id.encode(buf);
         }
-
-        if opt_status.is_options_extension() {
-            unimplemented!()
+        if let Some(mobile_id_type) = &self.mobile_id_type {
+            mobile_id_type.encode(buf);
+        }
+        for field in raw.iter().copied().flatten() {
+            let len = u8::try_from(field.len()).expect("option too long");
+            buf.push(len);
+            buf.extend_from_slice(field);
         }
-
-        Ok((inp, Some(opt_header)))
     }
 }
 
+/// Skip an option field: a length byte followed by that many bytes.
+fn skip_field(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    let (i, len) = utils::pu8(input)?;
+    nom::bytes::streaming::take(len)(i)
+}
+
 fn parse_options_status(input: &[u8]) -> IResult<&[u8], OptionsStatus> {
     #[allow(clippy::type_complexity)]
     let (i, b): (&[u8], (u8, u8, u8, u8, u8, u8, u8, u8)) =
@@ -303,7 +586,8 @@ fn parse_options_status(input: &[u8]) -> IResult<&[u8], OptionsStatus> {
 
 #[cfg(test)]
 mod tests {
-    use super::{MobileIDType, OptionsHeader};
+    use super::{MobileID, MobileIDType, OptionsHeader};
+    use std::net::Ipv4Addr;
 
     #[test]
     fn test_parse_options_headers() {
@@ -322,24 +606,94 @@ mod tests {
         ];
 
         let (i, opt_header) = OptionsHeader::parse(&data).unwrap();
-        match opt_header {
-            Some(opt_h) => {
-                assert_eq!(i.len(), 108);
+        let opt_h = opt_header.unwrap();
+        assert_eq!(i.len(), 108);
 
-                if let Some(mob_id) = opt_h.mobile_id {
-                    assert_eq!(mob_id.len(), 10);
-                    assert_eq!(mob_id.0, String::from("4634663235"));
-                }
+        assert_eq!(opt_h.mobile_id_string(), Some(String::from("4634663235")));
 
-                if let Some(mob_id_tp) = opt_h.mobile_id_type {
-                    assert_eq!(mob_id_tp, MobileIDType::Esn);
-                    assert_eq!(
-                        format!("{}", mob_id_tp),
-                        String::from("MobileIDType::Esn")
-                    );
-                }
-            }
-            None => (),
+        if let Some(mob_id) = opt_h.mobile_id {
+            assert_eq!(mob_id.len(), 5);
+            assert_eq!(mob_id.as_bytes(), &[0x46, 0x34, 0x66, 0x32, 0x35]);
+            assert_eq!(mob_id.as_esn(), Some(String::from("4634663235")));
+        }
+
+        if let Some(mob_id_tp) = opt_h.mobile_id_type {
+            assert_eq!(mob_id_tp, MobileIDType::Esn);
+            assert_eq!(
+                format!("{}", mob_id_tp),
+                String::from("MobileIDType::Esn")
+            );
         }
     }
+
+    #[test]
+    fn test_mobile_id_typed_accessors() {
+        let imei =
+            MobileID::new(&[0x35, 0x82, 0x40, 0x05, 0x12, 0x34, 0x56, 0x7f]);
+        assert_eq!(imei.as_imei(), Some(String::from("358240051234567")));
+        assert_eq!(
+            imei.decode(&MobileIDType::Equipment),
+            String::from("358240051234567")
+        );
+
+        let meid = MobileID::new(&[0xa1, 0x00, 0x00, 0x09, 0x1f, 0x2b, 0x3c]);
+        assert_eq!(meid.as_meid(), Some(String::from("A10000091F2B3C")));
+        assert_eq!(
+            meid.decode(&MobileIDType::Cdma),
+            String::from("A10000091F2B3C")
+        );
+        let padded =
+            MobileID::new(&[0x00, 0xa1, 0x00, 0x00, 0x09, 0x1f, 0x2b, 0x3c]);
+        assert_eq!(padded.as_meid(), Some(String::from("A10000091F2B3C")));
+        assert_eq!(MobileID::new(&[0xa1, 0x00]).as_meid(), None);
+
+        let ip = MobileID::new(&[10, 0, 1, 5]);
+        assert_eq!(ip.as_ipv4(), Some(Ipv4Addr::new(10, 0, 1, 5)));
+        assert_eq!(
+            ip.decode(&MobileIDType::IpAddress),
+            String::from("10.0.1.5")
+        );
+        assert_eq!(ip.as_imei(), None);
+
+        let defined = MobileID::new(b"TRUCK-42");
+        assert_eq!(defined.as_ascii(), Some("TRUCK-42"));
+        assert_eq!(defined.as_bcd_digits(), None);
+        assert_eq!(
+            defined.decode(&MobileIDType::Defined),
+            String::from("TRUCK-42")
+        );
+
+        assert_eq!(MobileID::from_bcd_digits("358240051234567"), Some(imei));
+        let even = MobileID::from_bcd_digits("4000000001").unwrap();
+        assert_eq!(even.as_bytes(), &[0x40, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(even.as_esn(), Some(String::from("4000000001")));
+        assert_eq!(MobileID::from_bcd_digits("12a"), None);
+
+        let short = MobileID::new(&[0x01, 0x0a]);
+        assert_eq!(short.to_hex(), String::from("010a"));
+        assert_eq!(short.decode(&MobileIDType::Esn), String::from("010a"));
+    }
+
+    #[test]
+    fn test_options_header_round_trip() {
+        // Mobile ID, authentication world, routing and options extension.
+        let data = [
+            0xcd, 0x02, 0x12, 0x34, 0x04, 0xde, 0xad, 0xbe, 0xef, 0x03, 0x0a,
+            0x00, 0x01, 0x00,
+        ];
+        let (rest, header) = OptionsHeader::parse(&data).unwrap();
+        assert!(rest.is_empty());
+        let header = header.unwrap();
+        assert_eq!(
+            header.authentication_world,
+            Some(vec![0xde, 0xad, 0xbe, 0xef])
+        );
+        assert_eq!(header.routing, Some(vec![0x0a, 0x00, 0x01]));
+        assert_eq!(header.forwarding, None);
+        assert_eq!(header.options_extension, Some(vec![]));
+
+        let mut buf = Vec::new();
+        header.encode(&mut buf);
+        assert_eq!(buf, data);
+    }
 }
@@ -0,0 +1,563 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Timed replay of recorded traffic.
+//!
+//! [`Replay`] sends the datagrams of a capture to a server, keeping the
+//! time between them, optionally sped up. Every LMU of the capture, told
+//! apart by its address, sends from a socket of its own so the server sees
+//! as many sources as there were. The Mobile IDs and Sequence Numbers can
+//! be rewritten on the way, to replay production traffic against another
+//! server without mixing it with the real LMUs.
+//!
+//! The Ack/Nak messages the server answers with are matched against the
+//! Acknowledged Requests sent.
+//!
+//! ```no_run
+//! use calamp_rs::capture::Capture;
+//! use calamp_rs::replay::Replay;
+//!
+//! # async fn run() -> std::io::Result<()> {
+//! let stats = Replay::new("127.0.0.1:20500".parse().unwrap())
+//!     .speed(10.0)
+//!     .to_port(20500)
+//!     .run(Capture::open("incident.pcap")?)
+//!     .await?;
+//! println!("{} of {} acks", stats.acked + stats.nakd, stats.expected);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant};
+
+use crate::capture::Datagram;
+use crate::message_header::{
+    MessageHeader, MessageType, SequenceNumber, ServiceType,
+};
+use crate::messages::ack_nak::AckNakMessage;
+use crate::options_header::{MobileID, MobileIDType, OptionsHeader};
+use crate::server::MAX_DATAGRAM;
+
+/// What a replay sent and got back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Datagrams sent.
+    pub datagrams: u64,
+
+    /// LMUs of the capture, one socket each.
+    pub sources: u64,
+
+    /// Acknowledged Requests sent, each one expects an Ack/Nak.
+    pub expected: u64,
+    pub acked: u64,
+    pub nakd: u64,
+
+    /// Acknowledged Requests not answered.
+    pub missing: u64,
+
+    /// Ack/Naks matching no request sent.
+    pub unexpected: u64,
+}
+
+/// Sends recorded datagrams to a server.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    target: SocketAddr,
+    speed: f64,
+    port: Option<u16>,
+    first_mobile_id: Option<u64>,
+    renumber: bool,
+    wait: Duration,
+}
+
+impl Replay {
+    /// Replay to `target` at the recorded pace, waiting 2 seconds for the
+    /// last acks.
+    pub fn new(target: SocketAddr) -> Self {
+        Replay {
+            target,
+            speed: 1.0,
+            port: None,
+            first_mobile_id: None,
+            renumber: false,
+            wait: Duration::from_secs(2),
+        }
+    }
+
+    /// Divide the time between two datagrams by `speed`, `f64::INFINITY`
+    /// sends them as fast as possible.
+    pub fn speed(mut self, speed: f64) -> Self {
+        self.speed = speed.max(1e-3);
+        self
+    }
+
+    /// Only replay the datagrams sent to `port`, the server port of the
+    /// capture, leaving out the answers of the server.
+    pub fn to_port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Give the LMUs ESNs counting up from `first`, in the order they
+    /// appear.
+    pub fn rewrite_mobile_ids(mut self, first: u64) -> Self {
+        self.first_mobile_id = Some(first);
+        self
+    }
+
+    /// Number the requests of every LMU again from 1, in the order they
+    /// are sent. Copies of a request keep sharing their number.
+    pub fn renumber(mut self, renumber: bool) -> Self {
+        self.renumber = renumber;
+        self
+    }
+
+    /// Time to wait for the acks still missing after the last datagram.
+    pub fn wait(mut self, wait: Duration) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    /// Send `datagrams`, in order, and wait for the acks. A read error of
+    /// the capture stops the replay.
+    pub async fn run<I>(&self, datagrams: I) -> io::Result<Stats>
+    where
+        I: IntoIterator<Item = io::Result<Datagram>>,
+    {
+        let (tx, mut replies) = mpsc::unbounded_channel();
+        let mut run = Run {
+            replay: self,
+            sources: HashMap::new(),
+            sockets: Vec::new(),
+            readers: Vec::new(),
+            rewriter: Rewriter::new(self.first_mobile_id, self.renumber),
+            pending: HashMap::new(),
+            stats: Stats::default(),
+            tx,
+        };
+        let res = run.send_all(datagrams, &mut replies).await;
+
+        let deadline = Instant::now() + self.wait;
+        while res.is_ok() && !run.pending.is_empty() {
+            tokio::select! {
+                _ = time::sleep_until(deadline) => break,
+                Some((lmu, reply)) = replies.recv() => {
+                    run.on_reply(lmu, &reply)
+                }
+            }
+        }
+        for reader in &run.readers {
+            reader.abort();
+        }
+        res?;
+        run.stats.missing = run.pending.values().sum();
+        Ok(run.stats)
+    }
+}
+
+/// State of one replay.
+struct Run<'a> {
+    replay: &'a Replay,
+
+    /// Index of the socket of each LMU address.
+    sources: HashMap<SocketAddr, usize>,
+    sockets: Vec<Arc<UdpSocket>>,
+    readers: Vec<JoinHandle<()>>,
+    rewriter: Rewriter,
+
+    /// Copies sent of each request waiting for an Ack/Nak, by socket,
+    /// sequence number and message type.
+    pending: HashMap<(usize, SequenceNumber, MessageType), u64>,
+    stats: Stats,
+    tx: mpsc::UnboundedSender<(usize, Vec<u8>)>,
+}
+
+impl Run<'_> {
+    async fn send_all<I>(
+        &mut self, datagrams: I,
+        replies: &mut mpsc::UnboundedReceiver<(usize, Vec<u8>)>,
+    ) -> io::Result<()>
+    where
+        I: IntoIterator<Item = io::Result<Datagram>>,
+    {
+        let mut start: Option<(SystemTime, Instant)> = None;
+        for datagram in datagrams {
+            let datagram = datagram?;
+            if self
+                .replay
+                .port
+                .is_some_and(|port| datagram.dst.port() != port)
+            {
+                continue;
+            }
+
+            let (first, started) =
+                *start.get_or_insert((datagram.time, Instant::now()));
+            let offset =
+                datagram.time.duration_since(first).unwrap_or_default();
+            let at = started + offset.div_f64(self.replay.speed);
+            loop {
+                tokio::select! {
+                    _ = time::sleep_until(at) => break,
+                    Some((lmu, reply)) = replies.recv() => {
+                        self.on_reply(lmu, &reply)
+                    }
+                }
+            }
+
+            let lmu = self.socket(datagram.src).await?;
+            let payload = self.rewriter.rewrite(lmu, &datagram.payload);
+            if let Some(key) = request(lmu, &payload) {
+                *self.pending.entry(key).or_default() += 1;
+                self.stats.expected += 1;
+            }
+            self.sockets[lmu]
+                .send_to(&payload, self.replay.target)
+                .await?;
+            self.stats.datagrams += 1;
+        }
+        Ok(())
+    }
+
+    /// Index of the socket sending for the LMU at `src`, bound on first
+    /// use.
+    async fn socket(&mut self, src: SocketAddr) -> io::Result<usize> {
+        if let Some(lmu) = self.sources.get(&src) {
+            return Ok(*lmu);
+        }
+        let bind = match self.replay.target {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(0.into()),
+        };
+        let socket = Arc::new(UdpSocket::bind((bind, 0)).await?);
+        let lmu = self.sockets.len();
+        self.readers.push(tokio::spawn(read_replies(
+            lmu,
+            Arc::clone(&socket),
+            self.replay.target,
+            self.tx.clone(),
+        )));
+        self.sockets.push(socket);
+        self.sources.insert(src, lmu);
+        self.stats.sources += 1;
+        Ok(lmu)
+    }
+
+    fn on_reply(&mut self, lmu: usize, reply: &[u8]) {
+        let msg = match AckNakMessage::parse(reply) {
+            Ok(msg) => msg,
+            Err(_) => return,
+        };
+        let key = (
+            lmu,
+            msg.message_header.sequence_number,
+            msg.ack_nak.message_type,
+        );
+        match self.pending.get_mut(&key) {
+            Some(copies) => {
+                *copies -= 1;
+                if *copies == 0 {
+                    self.pending.remove(&key);
+                }
+                if msg.ack_nak.is_ack() {
+                    self.stats.acked += 1;
+                } else {
+                    self.stats.nakd += 1;
+                }
+            }
+            None => self.stats.unexpected += 1,
+        }
+    }
+}
+
+/// The key of the Ack/Nak answering `datagram`, when it is an Acknowledged
+/// Request.
+fn request(
+    lmu: usize, datagram: &[u8],
+) -> Option<(usize, SequenceNumber, MessageType)> {
+    let (i, _) = OptionsHeader::parse(datagram).ok()?;
+    let (_, header) = MessageHeader::parse(i).ok()?;
+    if header.service_type != ServiceType::Acknowledged {
+        return None;
+    }
+    Some((lmu, header.sequence_number, header.message_type))
+}
+
+/// Pass what the server sends to socket `lmu` to `tx`.
+async fn read_replies(
+    lmu: usize, socket: Arc<UdpSocket>, server: SocketAddr,
+    tx: mpsc::UnboundedSender<(usize, Vec<u8>)>,
+) {
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    loop {
+        match socket.recv_from(&mut buf).await {
+            Ok((len, src)) if src == server => {
+                if tx.send((lmu, buf[..len].to_vec())).is_err() {
+                    return;
+                }
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => {}
+            Err(_) => return,
+        }
+    }
+}
+
+/// Requests further behind the newest one of their socket than this are
+/// forgotten, the counter of the LMU gets back to them only after a
+/// rollover.
+const MAX_BEHIND: u16 = u16::MAX / 4;
+
+/// New numbers of the requests sent from one socket.
+#[derive(Debug)]
+struct Renumbering {
+    next: SequenceNumber,
+
+    /// Newest number of the LMU.
+    newest: Option<SequenceNumber>,
+    numbers: HashMap<SequenceNumber, SequenceNumber>,
+
+    /// The keys of `numbers`, oldest first.
+    seen: VecDeque<SequenceNumber>,
+}
+
+impl Renumbering {
+    fn new() -> Self {
+        Renumbering {
+            next: SequenceNumber::new(1),
+            newest: None,
+            numbers: HashMap::new(),
+            seen: VecDeque::new(),
+        }
+    }
+
+    /// New number of the request numbered `sequence` by the LMU, the same
+    /// one for a retransmission of a recent request.
+    fn renumber(&mut self, sequence: SequenceNumber) -> SequenceNumber {
+        if let Some(number) = self.numbers.get(&sequence) {
+            return *number;
+        }
+        let number = self.next;
+        self.next = number.next();
+        // Zero is not part of the sequence, its requests can not be told
+        // apart.
+        if sequence.data() == 0 {
+            return number;
+        }
+
+        let newest = match self.newest {
+            Some(newest) if !sequence.is_newer_than(&newest) => newest,
+            _ => sequence,
+        };
+        self.newest = Some(newest);
+        self.numbers.insert(sequence, number);
+        self.seen.push_back(sequence);
+        while let Some(oldest) = self.seen.front() {
+            if newest.distance_from(oldest) <= MAX_BEHIND {
+                break;
+            }
+            self.numbers.remove(oldest);
+            self.seen.pop_front();
+        }
+        number
+    }
+}
+
+/// Rewrites the headers of the datagrams replayed.
+#[derive(Debug)]
+struct Rewriter {
+    first_mobile_id: Option<u64>,
+    renumber: bool,
+    mobile_ids: HashMap<MobileID, MobileID>,
+
+    /// Request numbers of each socket.
+    sequence_numbers: HashMap<usize, Renumbering>,
+}
+
+impl Rewriter {
+    fn new(first_mobile_id: Option<u64>, renumber: bool) -> Self {
+        Rewriter {
+            first_mobile_id,
+            renumber,
+            mobile_ids: HashMap::new(),
+            sequence_numbers: HashMap::new(),
+        }
+    }
+
+    /// `datagram` sent from socket `lmu` with its Mobile ID and Sequence
+    /// Number rewritten. The other bytes are copied as sent, datagrams
+    /// whose headers do not parse are not changed.
+    fn rewrite(&mut self, lmu: usize, datagram: &[u8]) -> Vec<u8> {
+        if self.first_mobile_id.is_none() && !self.renumber {
+            return datagram.to_vec();
+        }
+        let (i, options_header) = match OptionsHeader::parse(datagram) {
+            Ok(parsed) => parsed,
+            Err(_) => return datagram.to_vec(),
+        };
+        let header = match MessageHeader::parse(i) {
+            Ok((_, header)) => header,
+            Err(_) => return datagram.to_vec(),
+        };
+        let mut buf = datagram.to_vec();
+
+        // Answers carry the number of the request of the server.
+        if self.renumber
+            && header.service_type != ServiceType::ResponseToAnAcknowledged
+        {
+            let number = self
+                .sequence_numbers
+                .entry(lmu)
+                .or_insert_with(Renumbering::new)
+                .renumber(header.sequence_number);
+            // Service Type and Message Type come first.
+            let at = datagram.len() - i.len() + 2;
+            buf[at..at + 2].copy_from_slice(&number.data().to_be_bytes());
+        }
+
+        let options_header = match options_header {
+            Some(options_header) => options_header,
+            None => return buf,
+        };
+        if let (Some(first), Some(mobile_id)) =
+            (self.first_mobile_id, &options_header.mobile_id)
+        {
+            let count = self.mobile_ids.len() as u64;
+            let new =
+                self.mobile_ids.entry(mobile_id.clone()).or_insert_with(|| {
+                    let digits = (first + count).to_string();
+                    MobileID::from_bcd_digits(&digits).expect("decimal digits")
+                });
+            // The Mobile ID follows the options byte, its type comes next.
+            let end = 2 + mobile_id.len();
+            if options_header.mobile_id_type.is_some() {
+                buf[end + 1] = u8::from(MobileIDType::Esn);
+            }
+            let mut field = Vec::with_capacity(1 + new.len());
+            new.encode(&mut field);
+            buf.splice(1..end, field);
+        }
+        buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rewriter, MAX_BEHIND};
+    use crate::message_header::{MessageHeader, ServiceType};
+    use crate::options_header::OptionsHeader;
+
+    const REQUEST: [u8; 13] = [
+        0x83, 0x05, 0x46, 0x34, 0x66, 0x32, 0x35, 0x01, 0x01, 0x01, 0x02, 0x3a,
+        0x86,
+    ];
+
+    fn headers(datagram: &[u8]) -> (String, u16, ServiceType) {
+        let (i, options_header) = OptionsHeader::parse(datagram).unwrap();
+        let (_, header) = MessageHeader::parse(i).unwrap();
+        (
+            options_header.unwrap().mobile_id_string().unwrap(),
+            header.sequence_number.data(),
+            header.service_type,
+        )
+    }
+
+    #[test]
+    fn test_rewrite_nothing() {
+        let mut rewriter = Rewriter::new(None, false);
+        assert_eq!(rewriter.rewrite(0, &REQUEST), REQUEST);
+        assert_eq!(rewriter.rewrite(0, &[0x83]), [0x83]);
+    }
+
+    #[test]
+    fn test_rewrite_mobile_ids_and_sequence_numbers() {
+        let mut rewriter = Rewriter::new(Some(5_000_000_000), true);
+        let mut other = REQUEST;
+        other[6] = 0x36;
+        let mut later = REQUEST;
+        later[12] = 0x87;
+
+        let first = rewriter.rewrite(0, &REQUEST);
+        assert_eq!(first.len(), REQUEST.len());
+        assert_eq!(
+            headers(&first),
+            ("5000000000".into(), 1, ServiceType::Acknowledged)
+        );
+        assert_eq!(headers(&rewriter.rewrite(0, &later)).1, 2);
+        // A retransmission keeps its number.
+        assert_eq!(rewriter.rewrite(0, &REQUEST), first);
+        // Every socket counts on its own.
+        assert_eq!(
+            headers(&rewriter.rewrite(1, &other)),
+            ("5000000001".into(), 1, ServiceType::Acknowledged)
+        );
+
+        let mut answer = REQUEST;
+        answer[9] = 0x02;
+        let (id, sequence_number, _) = headers(&rewriter.rewrite(0, &answer));
+        assert_eq!(id, "5000000000");
+        assert_eq!(sequence_number, 0x3a86);
+    }
+
+    #[test]
+    fn test_rewrite_sequence_numbers_across_rollover() {
+        let mut rewriter = Rewriter::new(None, true);
+        let request = |n: u32| {
+            let mut request = REQUEST;
+            request[11..13].copy_from_slice(&(n as u16).to_be_bytes());
+            request
+        };
+
+        // The LMU skips numbers, it gets back to 1 after 21845 requests,
+        // while the replay has not rolled over yet.
+        for k in 0..21845 {
+            let number = headers(&rewriter.rewrite(0, &request(1 + 3 * k))).1;
+            assert_eq!(u32::from(number), k + 1);
+        }
+        let renumbering = &rewriter.sequence_numbers[&0];
+        assert!(renumbering.numbers.len() <= usize::from(MAX_BEHIND));
+        assert_eq!(renumbering.numbers.len(), renumbering.seen.len());
+
+        // A new request, not a retransmission of the first one.
+        assert_eq!(headers(&rewriter.rewrite(0, &request(1))).1, 21846);
+        assert_eq!(headers(&rewriter.rewrite(0, &request(1))).1, 21846);
+    }
+
+    #[test]
+    fn test_rewrite_keeps_other_options() {
+        let mut rewriter = Rewriter::new(Some(5_000_000_000), true);
+        // A shorter CDMA Mobile ID, then Authentication World and Routing.
+        let request = [
+            0x8f, 0x03, 0x46, 0x34, 0x66, 0x01, 0x07, 0x02, 0xaa, 0xbb, 0x01,
+            0xcc, 0x01, 0x02, 0x3a, 0x86, 0x42,
+        ];
+        let rewritten = rewriter.rewrite(0, &request);
+        assert_eq!(
+            rewritten,
+            [
+                0x8f, 0x05, 0x50, 0x00, 0x00, 0x00, 0x00, 0x01, 0x01, 0x02,
+                0xaa, 0xbb, 0x01, 0xcc, 0x01, 0x02, 0x00, 0x01, 0x42,
+            ]
+        );
+    }
+}
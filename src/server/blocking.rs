@@ -0,0 +1,226 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Blocking UDP server on `std::net`, for programs without an async
+//! runtime.
+//!
+//! The calling thread receives the datagrams and sends the ACKs, a pool of
+//! worker threads decodes them and calls the [`Handler`]. The queue between
+//! them is bounded: when the workers fall behind the receiving thread
+//! blocks and the datagrams wait in the socket buffer.
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use super::{ack_reply, dispatch, Handler, MAX_DATAGRAM};
+use crate::dedup::Dedup;
+
+/// How often the receiving thread looks at the shutdown flag.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+type Job = (SocketAddr, Vec<u8>);
+
+/// Stops a running [`UdpServer`] from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle(Arc<AtomicBool>);
+
+impl ShutdownHandle {
+    /// Ask the server to stop. It stops receiving within 100 ms, the
+    /// datagrams already queued are still handled.
+    pub fn shutdown(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Blocking UDP server.
+///
+/// ```no_run
+/// use calamp_rs::server::blocking::UdpServer;
+/// use calamp_rs::Message;
+/// use std::net::SocketAddr;
+///
+/// let server = UdpServer::bind("0.0.0.0:20500")?.workers(2);
+/// server.run(|src: SocketAddr, msg: Message| {
+///     println!("{}: {}", src, msg.message_header.message_type)
+/// })?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct UdpServer {
+    socket: UdpSocket,
+    auto_ack: bool,
+    dedup: Option<Mutex<Dedup>>,
+    workers: usize,
+    queue_size: usize,
+    shutdown: ShutdownHandle,
+}
+
+impl UdpServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Ok(UdpServer::from_socket(UdpSocket::bind(addr)?))
+    }
+
+    pub fn from_socket(socket: UdpSocket) -> Self {
+        UdpServer {
+            socket,
+            auto_ack: true,
+            dedup: None,
+            workers: 4,
+            queue_size: 1024,
+            shutdown: ShutdownHandle::default(),
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// A handle to the server socket, to send messages to LMUs from the
+    /// server port their NAT lets through, such as the ones of a
+    /// [`CommandQueue`](crate::command::CommandQueue).
+    pub fn try_clone_socket(&self) -> io::Result<UdpSocket> {
+        self.socket.try_clone()
+    }
+
+    /// Answer Acknowledged Requests with an ACK, on by default.
+    pub fn auto_ack(mut self, auto_ack: bool) -> Self {
+        self.auto_ack = auto_ack;
+        self
+    }
+
+    /// Pass the copies of a message received within `window` to
+    /// [`Handler::on_duplicate`] instead of [`Handler::on_message`]. Copies
+    /// are still acked, the LMU retransmits because an ACK was lost.
+    pub fn dedup(mut self, window: Duration) -> Self {
+        self.dedup = Some(Mutex::new(Dedup::new(window)));
+        self
+    }
+
+    /// Number of worker threads calling the handler, 4 by default.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `workers` is zero.
+    pub fn workers(mut self, workers: usize) -> Self {
+        assert!(workers > 0, "a server needs at least one worker");
+        self.workers = workers;
+        self
+    }
+
+    /// Datagrams waiting for a worker before receiving blocks, 1024 by
+    /// default.
+    pub fn queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size;
+        self
+    }
+
+    /// Handle to stop the server once it runs.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Serve on the calling thread until shutdown or a receive error.
+    ///
+    /// The workers finish the queued datagrams before this returns.
+    pub fn run<H: Handler>(mut self, handler: H) -> io::Result<()> {
+        self.socket.set_read_timeout(Some(POLL_INTERVAL))?;
+
+        let (tx, rx) = mpsc::sync_channel::<Job>(self.queue_size);
+        let rx = Arc::new(Mutex::new(rx));
+        let handler = Arc::new(handler);
+        let dedup = Arc::new(self.dedup.take());
+        let workers: Vec<_> = (0..self.workers)
+            .map(|_| {
+                let rx = Arc::clone(&rx);
+                let handler = Arc::clone(&handler);
+                let dedup = Arc::clone(&dedup);
+                thread::spawn(move || {
+                    work(&rx, &*handler, dedup.as_ref().as_ref())
+                })
+            })
+            .collect();
+
+        let res = self.receive(&tx, &*handler);
+
+        drop(tx);
+        for worker in workers {
+            // A panicking handler only takes its own worker down.
+            let _ = worker.join();
+        }
+        res
+    }
+
+    fn receive<H: Handler>(
+        &self, tx: &SyncSender<Job>, handler: &H,
+    ) -> io::Result<()> {
+        let local = self.socket.local_addr()?;
+        let mut buf = [0u8; MAX_DATAGRAM];
+
+        while !self.shutdown.is_shutdown() {
+            let (len, src) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                // A ConnectionReset is an ICMP port unreachable for an ACK
+                // sent to an LMU that has gone away, on some platforms.
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut
+                        || e.kind() == io::ErrorKind::ConnectionReset =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e),
+            };
+            let datagram = &buf[..len];
+            handler.on_datagram(src, local, SystemTime::now(), datagram);
+
+            if self.auto_ack {
+                if let Some(ack) = ack_reply(datagram) {
+                    // A lost ACK only makes the LMU retransmit, so a failed
+                    // send does not stop the server.
+                    let _ = self.socket.send_to(&ack, src);
+                }
+            }
+            if tx.send((src, datagram.to_vec())).is_err() {
+                return Err(io::Error::other(
+                    "every server worker has stopped",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn work<H: Handler>(
+    rx: &Mutex<Receiver<Job>>, handler: &H, dedup: Option<&Mutex<Dedup>>,
+) {
+    loop {
+        let job = match rx.lock() {
+            Ok(rx) => rx.recv(),
+            Err(_) => return,
+        };
+        match job {
+            Ok((src, datagram)) => dispatch(handler, dedup, src, &datagram),
+            Err(_) => return,
+        }
+    }
+}
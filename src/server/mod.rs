@@ -0,0 +1,138 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! LMDirect servers.
+//!
+//! Every server hands the decoded messages to a [`Handler`] and answers
+//! Acknowledged Requests with an ACK by itself. `UdpServer` runs on tokio
+//! and needs the `tokio` feature, [`blocking::UdpServer`] uses plain
+//! threads.
+
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::dedup::{Dedup, Verdict};
+use crate::error::Error;
+use crate::message_header::{MessageHeader, MessageType, ServiceType};
+use crate::messages::ack_nak::{AckNak, AckNakMessage};
+use crate::options_header::OptionsHeader;
+use crate::Message;
+
+pub mod blocking;
+#[cfg(feature = "tokio")]
+mod udp;
+
+#[cfg(feature = "tokio")]
+pub use udp::UdpServer;
+
+/// Largest datagram a server receives.
+pub const MAX_DATAGRAM: usize = 1500;
+
+/// Receives what a server reads from the network.
+///
+/// The async server calls it from the receiving task, a slow handler delays
+/// the following messages there and should hand the work off. The blocking
+/// server calls it from its worker threads.
+pub trait Handler: Send + Sync + 'static {
+    /// A message was received from `src`.
+    fn on_message(&self, src: SocketAddr, msg: Message);
+
+    /// A datagram was received from `src` on `dst`, the address the server
+    /// is bound to, at `time`. Called for every datagram before it is
+    /// decoded, in arrival order on the receiving thread or task, to keep
+    /// the raw traffic such as with `capture::RotatingPcapWriter`. It
+    /// should return quickly. Ignored by default.
+    fn on_datagram(
+        &self, src: SocketAddr, dst: SocketAddr, time: SystemTime,
+        datagram: &[u8],
+    ) {
+        let _ = (src, dst, time, datagram);
+    }
+
+    /// A datagram from `src` could not be decoded. Ignored by default.
+    fn on_error(&self, src: SocketAddr, err: Error) {
+        let _ = (src, err);
+    }
+
+    /// A copy of a message already handled was received from `src`, when
+    /// the server checks for duplicates. It has been acked again but is
+    /// not passed to [`Handler::on_message`]. Ignored by default.
+    fn on_duplicate(&self, src: SocketAddr, msg: Message) {
+        let _ = (src, msg);
+    }
+
+    /// An LMU answered a request sent by the server, see
+    /// [`CommandQueue::on_ack_nak`](crate::command::CommandQueue::on_ack_nak).
+    /// Ignored by default.
+    fn on_ack_nak(&self, src: SocketAddr, msg: AckNakMessage) {
+        let _ = (src, msg);
+    }
+}
+
+impl<F> Handler for F
+where
+    F: Fn(SocketAddr, Message) + Send + Sync + 'static,
+{
+    fn on_message(&self, src: SocketAddr, msg: Message) {
+        self(src, msg)
+    }
+}
+
+/// The ACK to send back for `datagram`, when it is an Acknowledged Request.
+///
+/// Only the headers are read, so messages without a decoder in this crate
+/// are acknowledged as well and the LMU does not keep retransmitting them.
+pub fn ack_reply(datagram: &[u8]) -> Option<Vec<u8>> {
+    let (i, options_header) = OptionsHeader::parse(datagram).ok()?;
+    let (_, message_header) = MessageHeader::parse(i).ok()?;
+    if message_header.service_type != ServiceType::Acknowledged {
+        return None;
+    }
+
+    let mut buf = Vec::new();
+    AckNak::ack(message_header.message_type).encode_reply(
+        options_header.as_ref(),
+        &message_header,
+        &mut buf,
+    );
+    Some(buf)
+}
+
+/// Decode `datagram` and pass the result to `handler`, copies found by
+/// `dedup` go to [`Handler::on_duplicate`].
+pub(crate) fn dispatch<H: Handler>(
+    handler: &H, dedup: Option<&Mutex<Dedup>>, src: SocketAddr, datagram: &[u8],
+) {
+    let msg = match Message::parse(datagram) {
+        Ok(msg) => msg,
+        Err(Error::UnsupportedMessageType(MessageType::AckNak)) => {
+            return match AckNakMessage::parse(datagram) {
+                Ok(msg) => handler.on_ack_nak(src, msg),
+                Err(e) => handler.on_error(src, e),
+            }
+        }
+        Err(e) => return handler.on_error(src, e),
+    };
+    let verdict = match dedup {
+        Some(dedup) => {
+            dedup.lock().unwrap_or_else(|e| e.into_inner()).check(&msg)
+        }
+        None => Verdict::New,
+    };
+    match verdict {
+        Verdict::New => handler.on_message(src, msg),
+        Verdict::Duplicate => handler.on_duplicate(src, msg),
+    }
+}
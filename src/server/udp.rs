@@ -0,0 +1,133 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+use std::future::{self, Future};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use tokio::net::{ToSocketAddrs, UdpSocket};
+
+use super::{ack_reply, dispatch, Handler, MAX_DATAGRAM};
+use crate::dedup::Dedup;
+
+/// Async UDP server.
+///
+/// ```no_run
+/// use calamp_rs::server::UdpServer;
+/// use calamp_rs::Message;
+/// use std::net::SocketAddr;
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let server = UdpServer::bind("0.0.0.0:20500").await?;
+/// server
+///     .run(|src: SocketAddr, msg: Message| {
+///         println!("{}: {}", src, msg.message_header.message_type)
+///     })
+///     .await
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct UdpServer {
+    socket: Arc<UdpSocket>,
+    auto_ack: bool,
+    dedup: Option<Mutex<Dedup>>,
+}
+
+impl UdpServer {
+    pub async fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Ok(UdpServer::from_socket(UdpSocket::bind(addr).await?))
+    }
+
+    pub fn from_socket(socket: UdpSocket) -> Self {
+        UdpServer {
+            socket: Arc::new(socket),
+            auto_ack: true,
+            dedup: None,
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// The server socket, to send messages to LMUs from the server port
+    /// their NAT lets through, such as the ones of a
+    /// [`CommandQueue`](crate::command::CommandQueue).
+    pub fn socket(&self) -> Arc<UdpSocket> {
+        Arc::clone(&self.socket)
+    }
+
+    /// Answer Acknowledged Requests with an ACK, on by default.
+    pub fn auto_ack(mut self, auto_ack: bool) -> Self {
+        self.auto_ack = auto_ack;
+        self
+    }
+
+    /// Pass the copies of a message received within `window` to
+    /// [`Handler::on_duplicate`] instead of [`Handler::on_message`]. Copies
+    /// are still acked, the LMU retransmits because an ACK was lost.
+    pub fn dedup(mut self, window: Duration) -> Self {
+        self.dedup = Some(Mutex::new(Dedup::new(window)));
+        self
+    }
+
+    /// Serve until a receive error.
+    pub async fn run<H: Handler>(self, handler: H) -> io::Result<()> {
+        self.run_until(handler, future::pending()).await
+    }
+
+    /// Serve until `shutdown` completes.
+    ///
+    /// The datagram being handled when `shutdown` completes is acked and
+    /// handed to `handler` before returning, no message is dropped half way.
+    pub async fn run_until<H, F>(
+        self, handler: H, shutdown: F,
+    ) -> io::Result<()>
+    where
+        H: Handler,
+        F: Future<Output = ()>,
+    {
+        let local = self.socket.local_addr()?;
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+        tokio::pin!(shutdown);
+
+        loop {
+            let (len, src) = tokio::select! {
+                _ = &mut shutdown => return Ok(()),
+                res = self.socket.recv_from(&mut buf) => match res {
+                    Ok(received) => received,
+                    // An ICMP port unreachable for an ACK sent to an LMU
+                    // that has gone away, on some platforms.
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionReset => {
+                        continue
+                    }
+                    Err(e) => return Err(e),
+                },
+            };
+            let datagram = &buf[..len];
+            handler.on_datagram(src, local, SystemTime::now(), datagram);
+
+            if self.auto_ack {
+                if let Some(ack) = ack_reply(datagram) {
+                    // A lost ACK only makes the LMU retransmit, so a failed
+                    // send does not stop the server.
+                    let _ = self.socket.send_to(&ack, src).await;
+                }
+            }
+            dispatch(&handler, self.dedup.as_ref(), src, datagram);
+        }
+    }
+}
@@ -0,0 +1,357 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! What is known about each LMU, keyed by its Mobile ID.
+//!
+//! The [`Registry`] is split in shards, each behind its own lock, so the
+//! workers of a server can update it concurrently. Messages without a
+//! Mobile ID cannot be told apart and are not tracked.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::net::SocketAddr;
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use crate::message_header::SequenceNumber;
+use crate::options_header::MobileID;
+use crate::timestamp::Timestamp;
+use crate::Message;
+
+/// Last valid fix reported by an LMU.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    /// Degrees, positive north.
+    pub latitude: f64,
+
+    /// Degrees, positive east.
+    pub longitude: f64,
+
+    pub time_of_fix: Timestamp,
+}
+
+/// State of one LMU.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub mobile_id: MobileID,
+
+    /// Source address of the last message.
+    pub addr: SocketAddr,
+
+    /// Times the source address changed, usually a NAT rebinding.
+    pub addr_changes: u32,
+
+    /// Sequence number of the last message.
+    pub last_sequence: SequenceNumber,
+
+    /// When the last message was received.
+    pub last_seen: SystemTime,
+
+    /// Last position with a valid fix, if any was received.
+    pub last_position: Option<Position>,
+
+    /// Sequence numbers of messages sent to the LMU and not acked yet.
+    pub pending_acks: Vec<SequenceNumber>,
+}
+
+/// What [`Registry::update`] did with a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Update {
+    /// First message from this LMU.
+    New,
+
+    /// Known LMU, same source address.
+    Seen,
+
+    /// Known LMU now sending from another address, the previous one is
+    /// given.
+    AddressChanged(SocketAddr),
+
+    /// The message has no Mobile ID and was not tracked.
+    Anonymous,
+}
+
+/// Sessions of every LMU heard from.
+#[derive(Debug)]
+pub struct Registry {
+    shards: Vec<Mutex<HashMap<MobileID, Session>>>,
+    hasher: RandomState,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::with_shards(16)
+    }
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// # Panics
+    ///
+    /// Panics when `shards` is zero.
+    pub fn with_shards(shards: usize) -> Self {
+        assert!(shards > 0, "a registry needs at least one shard");
+        Registry {
+            shards: (0..shards).map(|_| Mutex::default()).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Record `msg`, received from `src` now.
+    pub fn update(&self, src: SocketAddr, msg: &Message) -> Update {
+        self.update_at(src, msg, SystemTime::now())
+    }
+
+    /// Record `msg`, received from `src` at `now`.
+    pub fn update_at(
+        &self, src: SocketAddr, msg: &Message, now: SystemTime,
+    ) -> Update {
+        let mobile_id = match msg
+            .options_header
+            .as_ref()
+            .and_then(|options_header| options_header.mobile_id.as_ref())
+        {
+            Some(mobile_id) => mobile_id,
+            None => return Update::Anonymous,
+        };
+
+        let report = &msg.msg;
+        let position = if report.fix_status.invalid_fix {
+            None
+        } else {
+            Some(Position {
+                latitude: report.latitude_deg(),
+                longitude: report.longitude_deg(),
+                time_of_fix: report.time_of_fix,
+            })
+        };
+        let sequence = msg.message_header.sequence_number;
+
+        let mut shard = self.shard(mobile_id);
+        match shard.get_mut(mobile_id) {
+            Some(session) => {
+                let update = if session.addr == src {
+                    Update::Seen
+                } else {
+                    session.addr_changes += 1;
+                    Update::AddressChanged(session.addr)
+                };
+                session.addr = src;
+                session.last_sequence = sequence;
+                session.last_seen = now;
+                if position.is_some() {
+                    session.last_position = position;
+                }
+                update
+            }
+            None => {
+                shard.insert(
+                    mobile_id.clone(),
+                    Session {
+                        mobile_id: mobile_id.clone(),
+                        addr: src,
+                        addr_changes: 0,
+                        last_sequence: sequence,
+                        last_seen: now,
+                        last_position: position,
+                        pending_acks: Vec::new(),
+                    },
+                );
+                Update::New
+            }
+        }
+    }
+
+    /// A copy of the session of `mobile_id`.
+    pub fn get(&self, mobile_id: &MobileID) -> Option<Session> {
+        self.shard(mobile_id).get(mobile_id).cloned()
+    }
+
+    /// Forget `mobile_id`, returning its last session.
+    pub fn remove(&self, mobile_id: &MobileID) -> Option<Session> {
+        self.shard(mobile_id).remove(mobile_id)
+    }
+
+    /// Remember that the message `sequence` sent to `mobile_id` waits for
+    /// its ACK. Returns false when the LMU is unknown.
+    pub fn add_pending_ack(
+        &self, mobile_id: &MobileID, sequence: SequenceNumber,
+    ) -> bool {
+        match self.shard(mobile_id).get_mut(mobile_id) {
+            Some(session) => {
+                session.pending_acks.push(sequence);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The ACK for `sequence` arrived from `mobile_id`. Returns false when
+    /// it was not pending.
+    pub fn ack_received(
+        &self, mobile_id: &MobileID, sequence: &SequenceNumber,
+    ) -> bool {
+        let mut shard = self.shard(mobile_id);
+        let pending = match shard.get_mut(mobile_id) {
+            Some(session) => &mut session.pending_acks,
+            None => return false,
+        };
+        match pending.iter().position(|s| s == sequence) {
+            Some(index) => {
+                pending.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Number of LMUs tracked.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| lock(shard).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Call `f` with every session. One shard is locked at a time, so
+    /// updates to the other shards carry on meanwhile.
+    pub fn for_each<F: FnMut(&Session)>(&self, mut f: F) {
+        for shard in &self.shards {
+            lock(shard).values().for_each(&mut f);
+        }
+    }
+
+    /// A copy of every session.
+    pub fn sessions(&self) -> Vec<Session> {
+        let mut sessions = Vec::new();
+        self.for_each(|session| sessions.push(session.clone()));
+        sessions
+    }
+
+    fn shard(
+        &self, mobile_id: &MobileID,
+    ) -> MutexGuard<'_, HashMap<MobileID, Session>> {
+        let hash = self.hasher.hash_one(mobile_id);
+        let index = (hash % self.shards.len() as u64) as usize;
+        lock(&self.shards[index])
+    }
+}
+
+/// A panic while a shard was locked leaves consistent sessions behind, the
+/// lock is taken anyway.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Registry, Update};
+    use crate::fixtures::DATA;
+    use crate::message_header::SequenceNumber;
+    use crate::options_header::MobileID;
+    use crate::Message;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::thread;
+
+    /// The sample message, sent by the LMU with Mobile ID `id`.
+    fn message(id: u8) -> Message {
+        let mut data = DATA;
+        data[6] = id;
+        Message::parse(&data).unwrap()
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([192, 0, 2, 1], port))
+    }
+
+    #[test]
+    fn test_registry_tracks_address_changes() {
+        let registry = Registry::new();
+        let msg = message(0x35);
+        let mobile_id = MobileID::new(&[0x46, 0x34, 0x66, 0x32, 0x35]);
+
+        assert_eq!(registry.update(addr(1000), &msg), Update::New);
+        assert_eq!(registry.update(addr(1000), &msg), Update::Seen);
+        assert_eq!(
+            registry.update(addr(2000), &msg),
+            Update::AddressChanged(addr(1000))
+        );
+
+        let session = registry.get(&mobile_id).unwrap();
+        assert_eq!(session.addr, addr(2000));
+        assert_eq!(session.addr_changes, 1);
+        assert_eq!(session.last_sequence, SequenceNumber::new(14982));
+        let position = session.last_position.unwrap();
+        approx::assert_relative_eq!(position.latitude, -23.6812936);
+        approx::assert_relative_eq!(position.longitude, -46.7478976);
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn test_registry_pending_acks() {
+        let registry = Registry::new();
+        let mobile_id = MobileID::new(&[0x46, 0x34, 0x66, 0x32, 0x35]);
+        assert!(!registry.add_pending_ack(&mobile_id, SequenceNumber::new(1)));
+
+        registry.update(addr(1000), &message(0x35));
+        assert!(registry.add_pending_ack(&mobile_id, SequenceNumber::new(1)));
+        assert!(registry.add_pending_ack(&mobile_id, SequenceNumber::new(2)));
+        assert!(registry.ack_received(&mobile_id, &SequenceNumber::new(1)));
+        assert!(!registry.ack_received(&mobile_id, &SequenceNumber::new(1)));
+        assert_eq!(
+            registry.get(&mobile_id).unwrap().pending_acks,
+            [SequenceNumber::new(2)]
+        );
+    }
+
+    #[test]
+    fn test_registry_concurrent_updates() {
+        let registry = Arc::new(Registry::with_shards(4));
+        let workers: Vec<_> = (0..4u8)
+            .map(|worker| {
+                let registry = Arc::clone(&registry);
+                thread::spawn(move || {
+                    for id in 0..32u8 {
+                        let msg = message(worker * 32 + id);
+                        registry.update(addr(u16::from(worker)), &msg);
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert_eq!(registry.len(), 128);
+        let mut count = 0;
+        registry.for_each(|_| count += 1);
+        assert_eq!(count, 128);
+        assert_eq!(registry.sessions().len(), 128);
+    }
+
+    #[test]
+    fn test_registry_ignores_anonymous() {
+        let registry = Registry::new();
+        let mut msg = message(0x35);
+        msg.options_header = None;
+        assert_eq!(registry.update(addr(1000), &msg), Update::Anonymous);
+        assert!(registry.is_empty());
+    }
+}
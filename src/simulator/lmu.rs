@@ -0,0 +1,293 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! One virtual LMU.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+use tokio::time::{self, Instant, MissedTickBehavior};
+
+use super::{Config, Counters, ReportSource, STATUS_REQUEST};
+use crate::message_header::{
+    MessageHeader, MessageType, SequenceNumber, ServiceType,
+};
+use crate::messages::ack_nak::{AckNak, AckNakMessage};
+use crate::options_header::{MobileID, MobileIDType, OptionsHeader};
+use crate::server::MAX_DATAGRAM;
+
+/// Reports an LMU holds while waiting for acks, the oldest waiting one is
+/// dropped to make room.
+const MAX_QUEUED: usize = 64;
+
+/// NAK reason for a message type the LMU does not handle.
+const NOT_SUPPORTED: u8 = 2;
+
+/// A report waiting for its ack.
+#[derive(Debug)]
+struct Pending {
+    sequence_number: SequenceNumber,
+    bytes: Vec<u8>,
+    attempts: u32,
+}
+
+pub(super) struct Lmu {
+    socket: UdpSocket,
+    options_header: OptionsHeader,
+    source: Box<dyn ReportSource>,
+    config: Arc<Config>,
+    counters: Arc<Counters>,
+    sequence_number: SequenceNumber,
+
+    /// Reports not acked yet, the first one is in flight when `retry_at`
+    /// is set.
+    outbox: VecDeque<Pending>,
+    retry_at: Option<Instant>,
+    last_sent: Instant,
+}
+
+impl Lmu {
+    pub(super) async fn bind(
+        mobile_id: MobileID, source: Box<dyn ReportSource>,
+        config: Arc<Config>, counters: Arc<Counters>,
+    ) -> io::Result<Self> {
+        let socket = UdpSocket::bind((config.bind, 0)).await?;
+        Ok(Lmu {
+            socket,
+            options_header: OptionsHeader {
+                mobile_id: Some(mobile_id),
+                mobile_id_type: Some(MobileIDType::Esn),
+                authentication_world: None,
+                routing: None,
+                forwarding: None,
+                response_redirection: None,
+                options_extension: None,
+            },
+            source,
+            config,
+            counters,
+            sequence_number: SequenceNumber::new(1),
+            outbox: VecDeque::new(),
+            retry_at: None,
+            last_sent: Instant::now(),
+        })
+    }
+
+    /// Report from `first` on until `stopped` turns true.
+    pub(super) async fn run(
+        mut self, first: Instant, mut stopped: watch::Receiver<bool>,
+    ) -> io::Result<()> {
+        let period = self.config.report_interval.max(Duration::from_millis(1));
+        let mut reports = time::interval_at(first, period);
+        reports.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+
+        while !*stopped.borrow() {
+            let retry_at = self.retry_at;
+            tokio::select! {
+                res = stopped.changed() => {
+                    if res.is_err() {
+                        return Ok(());
+                    }
+                }
+                _ = reports.tick() => self.queue_report(),
+                _ = time::sleep_until(retry_at.unwrap_or_else(Instant::now)),
+                    if retry_at.is_some() => self.retry(),
+                res = self.socket.recv_from(&mut buf) => match res {
+                    Ok((len, src)) if src == self.config.server => {
+                        self.receive(&buf[..len]).await?
+                    }
+                    Ok(_) => {}
+                    // An ICMP port unreachable from a server not up yet.
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionReset
+                        || e.kind() == io::ErrorKind::ConnectionRefused => {}
+                    Err(e) => return Err(e),
+                },
+            }
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    fn queue_report(&mut self) {
+        if self.outbox.len() == MAX_QUEUED {
+            let oldest = usize::from(self.retry_at.is_some());
+            self.outbox.remove(oldest);
+            Counters::add(&self.counters.unacked);
+        }
+        let report = self.source.report(SystemTime::now());
+        let sequence_number = self.sequence_number();
+        let mut bytes = Vec::new();
+        self.encode_header(
+            ServiceType::Acknowledged,
+            MessageType::EventReport,
+            sequence_number,
+            &mut bytes,
+        );
+        report.encode(&mut bytes);
+        self.outbox.push_back(Pending {
+            sequence_number,
+            bytes,
+            attempts: 0,
+        });
+    }
+
+    /// The report in flight was not acked in time, send it again or give
+    /// up on it.
+    fn retry(&mut self) {
+        self.retry_at = None;
+        let exhausted = self
+            .outbox
+            .front()
+            .map_or(true, |head| head.attempts >= self.config.max_attempts);
+        if exhausted && self.outbox.pop_front().is_some() {
+            Counters::add(&self.counters.unacked);
+        }
+    }
+
+    /// Send the first report when it is not in flight.
+    async fn flush(&mut self) -> io::Result<()> {
+        if self.retry_at.is_some() {
+            return Ok(());
+        }
+        let (bytes, first) = match self.outbox.front_mut() {
+            Some(head) => {
+                head.attempts += 1;
+                (head.bytes.clone(), head.attempts == 1)
+            }
+            None => return Ok(()),
+        };
+        if first {
+            Counters::add(&self.counters.reports);
+        } else {
+            Counters::add(&self.counters.retransmissions);
+        }
+        self.send(&bytes).await?;
+        self.retry_at = Some(Instant::now() + self.config.retry_interval);
+        Ok(())
+    }
+
+    async fn receive(&mut self, datagram: &[u8]) -> io::Result<()> {
+        let (i, header) = match OptionsHeader::parse(datagram)
+            .and_then(|(i, _)| MessageHeader::parse(i))
+        {
+            Ok(parsed) => parsed,
+            Err(_) => return Ok(()),
+        };
+
+        match header.message_type {
+            MessageType::AckNak => {
+                if let Ok(msg) = AckNakMessage::parse(datagram) {
+                    self.on_ack_nak(&msg);
+                }
+                Ok(())
+            }
+            MessageType::UnitRequest => {
+                Counters::add(&self.counters.unit_requests);
+                if header.service_type == ServiceType::Acknowledged {
+                    self.reply(&header, AckNak::ack(header.message_type))
+                        .await?;
+                }
+                if i.first() == Some(&STATUS_REQUEST) {
+                    self.locate_report().await?;
+                }
+                Ok(())
+            }
+            _ if header.service_type == ServiceType::Acknowledged => {
+                let nak = AckNak::nak(header.message_type, NOT_SUPPORTED);
+                self.reply(&header, nak).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn on_ack_nak(&mut self, msg: &AckNakMessage) {
+        let matches = self.outbox.front().is_some_and(|head| {
+            head.attempts > 0
+                && head.sequence_number == msg.message_header.sequence_number
+        });
+        if !matches || msg.ack_nak.message_type != MessageType::EventReport {
+            return;
+        }
+        self.outbox.pop_front();
+        self.retry_at = None;
+        if msg.ack_nak.is_ack() {
+            Counters::add(&self.counters.acked);
+        } else {
+            Counters::add(&self.counters.nakd);
+        }
+    }
+
+    async fn reply(
+        &mut self, request: &MessageHeader, ack_nak: AckNak,
+    ) -> io::Result<()> {
+        let mut buf = Vec::new();
+        ack_nak.encode_reply(Some(&self.options_header), request, &mut buf);
+        self.send(&buf).await
+    }
+
+    /// Answer a Status Request with the current position, unacknowledged.
+    async fn locate_report(&mut self) -> io::Result<()> {
+        let report = self.source.report(SystemTime::now());
+        let sequence_number = self.sequence_number();
+        let mut buf = Vec::new();
+        self.encode_header(
+            ServiceType::Unacknowledged,
+            MessageType::LocateReport,
+            sequence_number,
+            &mut buf,
+        );
+        report.encode(&mut buf);
+        Counters::add(&self.counters.locate_reports);
+        self.send(&buf).await
+    }
+
+    fn sequence_number(&mut self) -> SequenceNumber {
+        let sequence_number = self.sequence_number;
+        self.sequence_number = sequence_number.next();
+        sequence_number
+    }
+
+    fn encode_header(
+        &self, service_type: ServiceType, message_type: MessageType,
+        sequence_number: SequenceNumber, buf: &mut Vec<u8>,
+    ) {
+        self.options_header.encode(buf);
+        MessageHeader {
+            service_type,
+            message_type,
+            sequence_number,
+        }
+        .encode(buf);
+    }
+
+    /// Send `datagram` to the server, from a new port when the NAT mapping
+    /// of the current one has expired. A failed send is a lost datagram.
+    async fn send(&mut self, datagram: &[u8]) -> io::Result<()> {
+        let now = Instant::now();
+        if let Some(timeout) = self.config.nat_timeout {
+            if now.duration_since(self.last_sent) > timeout {
+                self.socket = UdpSocket::bind((self.config.bind, 0)).await?;
+                Counters::add(&self.counters.rebinds);
+            }
+        }
+        self.last_sent = now;
+        let _ = self.socket.send_to(datagram, self.config.server).await;
+        Ok(())
+    }
+}
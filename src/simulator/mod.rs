@@ -0,0 +1,519 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Virtual LMUs for load and integration tests.
+//!
+//! A [`Simulator`] runs any number of virtual LMUs against a server, each
+//! with its own Mobile ID and UDP socket. An LMU sends an Acknowledged
+//! Event Report every report interval and retransmits it until it is acked,
+//! queueing the reports due meanwhile as a real LMU logs them. It acks the
+//! Unit Requests of the server and answers a Status Request with a Locate
+//! Report.
+//!
+//! The reports are the same every time with a [`ReportTemplate`], or follow
+//! a GPX or GeoJSON [`Route`] with a [`RouteSource`].
+//!
+//! An LMU keeps its source port the way a NAT mapping does, so the server
+//! reaches it at the address it reports from. With
+//! [`Simulator::nat_timeout`] an LMU silent for longer gets a new port, as
+//! behind a NAT whose mapping expired.
+//!
+//! Every LMU holds a socket, raise the open files limit (`ulimit -n`) to
+//! run thousands of them.
+//!
+//! ```no_run
+//! use calamp_rs::simulator::{ReportTemplate, Simulator};
+//! use std::time::Duration;
+//!
+//! # async fn run() -> std::io::Result<()> {
+//! let simulator = Simulator::new("127.0.0.1:20500".parse().unwrap())
+//!     .devices(1000)
+//!     .report_interval(Duration::from_secs(30))
+//!     .template(ReportTemplate::new(-23.68, -46.74).speed(42.0));
+//! let stats = simulator.run_for(Duration::from_secs(300)).await?;
+//! println!("{} reports acked", stats.acked);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+use crate::messages::event_report::{
+    CommState, EventReport, FixStatus, Inputs, UnitStatus, COORDINATE_LSB,
+};
+use crate::options_header::MobileID;
+use crate::timestamp::Timestamp;
+use crate::units::{Altitude, Hdop, Heading, Rssi, Speed};
+
+mod lmu;
+mod rng;
+mod route;
+
+use lmu::Lmu;
+pub use route::{Route, RouteSource, Waypoint};
+
+/// Action code of the Unit Request answered with a Locate Report.
+pub const STATUS_REQUEST: u8 = 0;
+
+/// Makes the Event Reports of a virtual LMU.
+pub trait ReportSource: Send {
+    /// The report due at `time`.
+    fn report(&mut self, time: SystemTime) -> EventReport;
+}
+
+impl<F> ReportSource for F
+where
+    F: FnMut(SystemTime) -> EventReport + Send,
+{
+    fn report(&mut self, time: SystemTime) -> EventReport {
+        self(time)
+    }
+}
+
+/// The fields of an Event Report, in the units they are usually given in.
+///
+/// Sends the same report every time, timestamped, as a parked vehicle
+/// would.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportTemplate {
+    /// Degrees.
+    pub latitude: f64,
+
+    /// Degrees.
+    pub longitude: f64,
+
+    /// Meters.
+    pub altitude: f64,
+
+    /// Kilometers per hour.
+    pub speed: f64,
+
+    /// Degrees from true North.
+    pub heading: u16,
+
+    pub satellites: u8,
+
+    /// The Fix Status byte.
+    pub fix_status: u8,
+
+    /// HDOP with a 0.1 lsb.
+    pub hdop: u8,
+
+    /// dBm.
+    pub rssi: i16,
+
+    /// The Inputs byte, ignition is bit 0.
+    pub inputs: u8,
+
+    pub event_code: u8,
+    pub accumulators: Vec<u32>,
+}
+
+/// Comm State of an LMU connected to an LTE network.
+const CONNECTED_LTE: u8 = 0b1000_1111;
+
+impl ReportTemplate {
+    pub fn new(latitude: f64, longitude: f64) -> Self {
+        ReportTemplate {
+            latitude,
+            longitude,
+            altitude: 0.0,
+            speed: 0.0,
+            heading: 0,
+            satellites: 9,
+            fix_status: 0,
+            hdop: 9,
+            rssi: -70,
+            inputs: 0,
+            event_code: 0,
+            accumulators: Vec::new(),
+        }
+    }
+
+    pub fn altitude(mut self, meters: f64) -> Self {
+        self.altitude = meters;
+        self
+    }
+
+    pub fn speed(mut self, kmh: f64) -> Self {
+        self.speed = kmh;
+        self
+    }
+
+    pub fn heading(mut self, degrees: u16) -> Self {
+        self.heading = degrees;
+        self
+    }
+
+    pub fn inputs(mut self, inputs: u8) -> Self {
+        self.inputs = inputs;
+        self
+    }
+
+    pub fn ignition(mut self, on: bool) -> Self {
+        self.inputs = self.inputs & !1 | u8::from(on);
+        self
+    }
+
+    pub fn event_code(mut self, event_code: u8) -> Self {
+        self.event_code = event_code;
+        self
+    }
+
+    /// At most 63 accumulators are sent, the rest are ignored.
+    pub fn accumulators(mut self, accumulators: Vec<u32>) -> Self {
+        self.accumulators = accumulators;
+        self
+    }
+
+    /// The report at `time`, also used as time of fix.
+    pub fn to_report(&self, time: SystemTime) -> EventReport {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() as u32);
+        let accum_list: Vec<u32> =
+            self.accumulators.iter().take(0x3f).copied().collect();
+
+        EventReport {
+            update_time: Timestamp::new(secs),
+            time_of_fix: Timestamp::new(secs),
+            latitude: (self.latitude / COORDINATE_LSB).round() as i32,
+            longitude: (self.longitude / COORDINATE_LSB).round() as i32,
+            altitude: Altitude::new((self.altitude * 100.0).round() as i32),
+            speed: Speed::new((self.speed / 0.036).round() as u32),
+            heading: Heading::new(self.heading),
+            satellites: self.satellites,
+            fix_status: byte(FixStatus::parse, self.fix_status),
+            carrier: 0,
+            rssi: Rssi::new(self.rssi),
+            comm_state: byte(CommState::parse, CONNECTED_LTE),
+            hdop: Hdop::new(self.hdop),
+            inputs: byte(Inputs::parse, self.inputs),
+            unit_status: byte(UnitStatus::parse, 0),
+            event_index: 0,
+            event_code: self.event_code,
+            accums: accum_list.len() as u8,
+            append: 0,
+            appended: Vec::new(),
+            accum_list,
+        }
+    }
+}
+
+impl ReportSource for ReportTemplate {
+    fn report(&mut self, time: SystemTime) -> EventReport {
+        self.to_report(time)
+    }
+}
+
+/// A one byte field decoded by its parser.
+fn byte<T>(parse: fn(&[u8]) -> nom::IResult<&[u8], T>, b: u8) -> T {
+    match parse(&[b]) {
+        Ok((_, value)) => value,
+        Err(_) => unreachable!("one byte fields parse any byte"),
+    }
+}
+
+/// Counts of what the LMUs of a [`Simulator`] did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Event Reports sent, not counting retransmissions.
+    pub reports: u64,
+    pub retransmissions: u64,
+    pub acked: u64,
+    pub nakd: u64,
+
+    /// Reports given up after their last attempt, or dropped from a full
+    /// queue.
+    pub unacked: u64,
+
+    pub unit_requests: u64,
+    pub locate_reports: u64,
+
+    /// New source ports taken after a NAT timeout.
+    pub rebinds: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    reports: AtomicU64,
+    retransmissions: AtomicU64,
+    acked: AtomicU64,
+    nakd: AtomicU64,
+    unacked: AtomicU64,
+    unit_requests: AtomicU64,
+    locate_reports: AtomicU64,
+    rebinds: AtomicU64,
+}
+
+impl Counters {
+    fn add(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> Stats {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        Stats {
+            reports: get(&self.reports),
+            retransmissions: get(&self.retransmissions),
+            acked: get(&self.acked),
+            nakd: get(&self.nakd),
+            unacked: get(&self.unacked),
+            unit_requests: get(&self.unit_requests),
+            locate_reports: get(&self.locate_reports),
+            rebinds: get(&self.rebinds),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Config {
+    server: SocketAddr,
+    bind: IpAddr,
+    devices: usize,
+    first_mobile_id: u64,
+    report_interval: Duration,
+    retry_interval: Duration,
+    max_attempts: u32,
+    nat_timeout: Option<Duration>,
+}
+
+type Sources = dyn Fn(usize) -> Box<dyn ReportSource> + Send + Sync;
+
+/// Runs virtual LMUs against a server.
+pub struct Simulator {
+    config: Config,
+    sources: Arc<Sources>,
+    counters: Arc<Counters>,
+}
+
+impl fmt::Debug for Simulator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Simulator")
+            .field("config", &self.config)
+            .field("counters", &self.counters)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Simulator {
+    /// One LMU reporting to `server` every minute, parked at 0°, 0°.
+    pub fn new(server: SocketAddr) -> Self {
+        let bind = match server {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(0.into()),
+        };
+        Simulator {
+            config: Config {
+                server,
+                bind,
+                devices: 1,
+                first_mobile_id: 4_000_000_000,
+                report_interval: Duration::from_secs(60),
+                retry_interval: Duration::from_secs(5),
+                max_attempts: 3,
+                nat_timeout: None,
+            },
+            sources: Arc::new(|_| Box::new(ReportTemplate::new(0.0, 0.0))),
+            counters: Arc::default(),
+        }
+    }
+
+    /// Number of LMUs.
+    pub fn devices(mut self, devices: usize) -> Self {
+        self.config.devices = devices;
+        self
+    }
+
+    /// Mobile ID of the first LMU, the next ones count up from it.
+    pub fn first_mobile_id(mut self, first_mobile_id: u64) -> Self {
+        self.config.first_mobile_id = first_mobile_id;
+        self
+    }
+
+    /// Local address the LMU sockets are bound to.
+    pub fn bind(mut self, ip: IpAddr) -> Self {
+        self.config.bind = ip;
+        self
+    }
+
+    /// Time between two Event Reports of an LMU. The first reports are
+    /// spread over one interval so the LMUs do not all send at once.
+    pub fn report_interval(mut self, interval: Duration) -> Self {
+        self.config.report_interval = interval;
+        self
+    }
+
+    /// Time to wait for an ack before retransmitting.
+    pub fn retry_interval(mut self, interval: Duration) -> Self {
+        self.config.retry_interval = interval;
+        self
+    }
+
+    /// Transmissions of a report before giving up, at least one.
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.config.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Take a new source port after `timeout` without sending.
+    pub fn nat_timeout(mut self, timeout: Duration) -> Self {
+        self.config.nat_timeout = Some(timeout);
+        self
+    }
+
+    /// Every LMU sends `template`.
+    pub fn template(self, template: ReportTemplate) -> Self {
+        self.sources(move |_| Box::new(template.clone()))
+    }
+
+    /// Every LMU drives the route of `source`, the LMU numbered `index`
+    /// with the seed of `source` plus `index`.
+    pub fn route(self, source: RouteSource) -> Self {
+        self.sources(move |index| Box::new(source.for_device(index)))
+    }
+
+    /// The reports of the LMU numbered `index`, from 0, come from
+    /// `sources(index)`.
+    pub fn sources<F>(mut self, sources: F) -> Self
+    where
+        F: Fn(usize) -> Box<dyn ReportSource> + Send + Sync + 'static,
+    {
+        self.sources = Arc::new(sources);
+        self
+    }
+
+    /// Mobile ID of the LMU numbered `index`, an ESN.
+    pub fn mobile_id(&self, index: usize) -> MobileID {
+        mobile_id(self.config.first_mobile_id, index)
+    }
+
+    /// What the LMUs did so far.
+    pub fn stats(&self) -> Stats {
+        self.counters.stats()
+    }
+
+    /// Run the LMUs for `duration`.
+    pub async fn run_for(&self, duration: Duration) -> io::Result<Stats> {
+        self.run_until(tokio::time::sleep(duration)).await
+    }
+
+    /// Run the LMUs until `shutdown` completes, returning the counts of
+    /// this run and the previous ones.
+    ///
+    /// Every socket is bound before the first report is sent, a bind error
+    /// is returned right away.
+    pub async fn run_until<F>(&self, shutdown: F) -> io::Result<Stats>
+    where
+        F: Future<Output = ()>,
+    {
+        let config = Arc::new(self.config.clone());
+        let mut lmus = Vec::with_capacity(config.devices);
+        for index in 0..config.devices {
+            let mobile_id = mobile_id(config.first_mobile_id, index);
+            let source = (self.sources)(index);
+            let lmu = Lmu::bind(
+                mobile_id,
+                source,
+                Arc::clone(&config),
+                Arc::clone(&self.counters),
+            )
+            .await?;
+            lmus.push(lmu);
+        }
+
+        let (stop, stopped) = watch::channel(false);
+        let start = Instant::now();
+        let spread = config.devices.max(1) as f64;
+        let tasks: Vec<_> = lmus
+            .into_iter()
+            .enumerate()
+            .map(|(index, lmu)| {
+                let first = start
+                    + config.report_interval.mul_f64(index as f64 / spread);
+                tokio::spawn(lmu.run(first, stopped.clone()))
+            })
+            .collect();
+
+        shutdown.await;
+        let _ = stop.send(true);
+        let mut res = Ok(());
+        for task in tasks {
+            let done = task.await.unwrap_or_else(|e| Err(io::Error::other(e)));
+            if res.is_ok() {
+                res = done;
+            }
+        }
+        res.map(|_| self.stats())
+    }
+}
+
+fn mobile_id(first: u64, index: usize) -> MobileID {
+    let digits = (first + index as u64).to_string();
+    MobileID::from_bcd_digits(&digits).expect("decimal digits")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReportTemplate, Simulator};
+    use crate::messages::event_report::EventReport;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_report_template() {
+        let template = ReportTemplate::new(-23.6812936, -46.7478976)
+            .altitude(796.08)
+            .speed(36.0)
+            .heading(90)
+            .ignition(true)
+            .accumulators(vec![1, 2]);
+        let time = UNIX_EPOCH + Duration::from_secs(1609644628);
+        let mut buf = Vec::new();
+        template.to_report(time).encode(&mut buf);
+
+        let report = EventReport::parse(&buf).unwrap();
+        assert_eq!(report.update_time.secs(), 1609644628);
+        assert_eq!(report.latitude, -236812936);
+        assert_eq!(report.longitude, -467478976);
+        assert_eq!(report.altitude.raw(), 79608);
+        assert_eq!(report.speed.raw(), 1000);
+        assert_eq!(report.heading.raw(), 90);
+        assert!(report.inputs.ignition);
+        assert!(report.comm_state.connected);
+        assert_eq!(report.accum_list, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_mobile_ids() {
+        let simulator = Simulator::new("127.0.0.1:20500".parse().unwrap())
+            .first_mobile_id(4_000_000_099);
+        assert_eq!(
+            simulator.mobile_id(0).as_esn(),
+            Some(String::from("4000000099"))
+        );
+        assert_eq!(
+            simulator.mobile_id(1).as_esn(),
+            Some(String::from("4000000100"))
+        );
+    }
+}
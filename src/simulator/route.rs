@@ -0,0 +1,802 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Routes driven by virtual LMUs.
+//!
+//! A [`Route`] is read from the track points of a GPX file, or from a
+//! GeoJSON `LineString`, with times when every point has one. A
+//! [`RouteSource`] drives it: parked with the ignition off at the start,
+//! along the route with the ignition on, then parked again at the end.
+//! Timed routes are driven at their recorded pace, the others at a set
+//! speed. Satellites, HDOP and the Fix Status vary from report to report,
+//! from a seed so a run can be repeated.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use serde_json::Value;
+
+use super::rng::Rng;
+use super::{ReportSource, ReportTemplate};
+use crate::messages::event_report::EventReport;
+
+/// Mean Earth radius, in meters.
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// A point of a route.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Waypoint {
+    /// Degrees.
+    pub latitude: f64,
+
+    /// Degrees.
+    pub longitude: f64,
+
+    /// Meters.
+    pub altitude: Option<f64>,
+
+    /// Seconds since the Unix epoch.
+    pub time: Option<f64>,
+}
+
+impl Waypoint {
+    pub fn new(latitude: f64, longitude: f64) -> Self {
+        Waypoint {
+            latitude,
+            longitude,
+            altitude: None,
+            time: None,
+        }
+    }
+
+    /// Great circle distance to `other`, in meters.
+    pub fn distance(&self, other: &Waypoint) -> f64 {
+        let (lat1, lat2) =
+            (self.latitude.to_radians(), other.latitude.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.longitude - self.longitude).to_radians();
+        let a = (dlat / 2.0).sin().powi(2)
+            + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * a.sqrt().min(1.0).asin()
+    }
+
+    /// Initial bearing towards `other`, in degrees from true North.
+    pub fn bearing(&self, other: &Waypoint) -> f64 {
+        let (lat1, lat2) =
+            (self.latitude.to_radians(), other.latitude.to_radians());
+        let dlon = (other.longitude - self.longitude).to_radians();
+        let y = dlon.sin() * lat2.cos();
+        let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlon.cos();
+        y.atan2(x).to_degrees().rem_euclid(360.0)
+    }
+}
+
+/// Points to drive through, in order. Cheap to clone, the points are
+/// shared.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    points: Arc<[Waypoint]>,
+
+    /// Distance from the first point to each point, in meters.
+    distances: Arc<[f64]>,
+
+    /// Every point has a time and times do not go backwards.
+    timed: bool,
+}
+
+impl Route {
+    /// A route through `points`, at least one.
+    pub fn new(points: Vec<Waypoint>) -> io::Result<Self> {
+        if points.is_empty() {
+            return Err(invalid("route without points"));
+        }
+        if let Some(point) = points.iter().find(|p| {
+            !(-90.0..=90.0).contains(&p.latitude)
+                || !(-180.0..=180.0).contains(&p.longitude)
+        }) {
+            return Err(invalid(&format!(
+                "coordinates out of range: {}, {}",
+                point.latitude, point.longitude
+            )));
+        }
+
+        let mut distances = Vec::with_capacity(points.len());
+        let mut total = 0.0;
+        distances.push(total);
+        for pair in points.windows(2) {
+            total += pair[0].distance(&pair[1]);
+            distances.push(total);
+        }
+        let timed = points.iter().all(|p| p.time.is_some())
+            && points.windows(2).all(|pair| pair[0].time <= pair[1].time);
+
+        Ok(Route {
+            points: points.into(),
+            distances: distances.into(),
+            timed,
+        })
+    }
+
+    /// Read a GPX or GeoJSON file, told apart by their first character.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        if text.trim_start().starts_with('<') {
+            Route::from_gpx(&text)
+        } else {
+            Route::from_geojson(&text)
+        }
+    }
+
+    /// The track points of a GPX document, the route points when it has no
+    /// track.
+    pub fn from_gpx(text: &str) -> io::Result<Self> {
+        let mut points = gpx_points(text, "trkpt")?;
+        if points.is_empty() {
+            points = gpx_points(text, "rtept")?;
+        }
+        Route::new(points)
+    }
+
+    /// The first `LineString` or `MultiLineString` of a GeoJSON geometry,
+    /// feature or feature collection. Times are read from the
+    /// `coordTimes` property of the feature, as written by most GPX to
+    /// GeoJSON converters.
+    pub fn from_geojson(text: &str) -> io::Result<Self> {
+        let json: Value = serde_json::from_str(text)
+            .map_err(|e| invalid(&format!("GeoJSON: {}", e)))?;
+        let feature = match json["type"].as_str() {
+            Some("FeatureCollection") => json["features"]
+                .as_array()
+                .and_then(|features| {
+                    features.iter().find(|f| is_line(&f["geometry"]))
+                })
+                .ok_or_else(|| invalid("no LineString feature"))?,
+            _ => &json,
+        };
+        let (geometry, times) = match feature["type"].as_str() {
+            Some("Feature") => {
+                (&feature["geometry"], &feature["properties"]["coordTimes"])
+            }
+            _ => (feature, &Value::Null),
+        };
+        if !is_line(geometry) {
+            return Err(invalid("no LineString geometry"));
+        }
+
+        let lines = match geometry["type"].as_str() {
+            Some("LineString") => vec![&geometry["coordinates"]],
+            _ => geometry["coordinates"]
+                .as_array()
+                .map(|lines| lines.iter().collect())
+                .unwrap_or_default(),
+        };
+        let times = flatten(times);
+        let mut points = Vec::new();
+        for position in
+            lines.iter().filter_map(|line| line.as_array()).flatten()
+        {
+            let coordinate = |i: usize| position.get(i).and_then(Value::as_f64);
+            let (longitude, latitude) = coordinate(0)
+                .zip(coordinate(1))
+                .ok_or_else(|| invalid("bad GeoJSON position"))?;
+            let time = match times.get(points.len()) {
+                Some(time) => Some(
+                    time.as_str()
+                        .and_then(parse_time)
+                        .ok_or_else(|| invalid("bad time in coordTimes"))?,
+                ),
+                None => None,
+            };
+            points.push(Waypoint {
+                latitude,
+                longitude,
+                altitude: coordinate(2),
+                time,
+            });
+        }
+        Route::new(points)
+    }
+
+    pub fn points(&self) -> &[Waypoint] {
+        &self.points
+    }
+
+    /// Length of the route, in meters.
+    pub fn length(&self) -> f64 {
+        self.distances[self.distances.len() - 1]
+    }
+
+    /// Whether the route is driven at the pace of the times of its points.
+    pub fn is_timed(&self) -> bool {
+        self.timed
+    }
+}
+
+fn is_line(geometry: &Value) -> bool {
+    matches!(
+        geometry["type"].as_str(),
+        Some("LineString") | Some("MultiLineString")
+    )
+}
+
+/// The times of a `LineString`, or of the lines of a `MultiLineString`,
+/// in one list.
+fn flatten(times: &Value) -> Vec<&Value> {
+    match times.as_array() {
+        Some(times) if times.iter().all(Value::is_array) => {
+            times.iter().filter_map(Value::as_array).flatten().collect()
+        }
+        Some(times) => times.iter().collect(),
+        None => Vec::new(),
+    }
+}
+
+/// The `tag` elements of a GPX document, such as `trkpt`.
+fn gpx_points(text: &str, tag: &str) -> io::Result<Vec<Waypoint>> {
+    let mut points = Vec::new();
+    let mut rest = text;
+
+    while let Some((start, name)) = find_tag(rest, tag) {
+        let close = format!("</{}>", name);
+        let element = &rest[start + 1 + name.len()..];
+        let end = element
+            .find('>')
+            .ok_or_else(|| invalid(&format!("unterminated <{}>", tag)))?;
+        let (attributes, body, next) = match element[..end].strip_suffix('/') {
+            Some(attributes) => (attributes, "", &element[end + 1..]),
+            None => {
+                let content = &element[end + 1..];
+                let len = content
+                    .find(&close)
+                    .ok_or_else(|| invalid(&format!("no {}", close)))?;
+                (
+                    &element[..end],
+                    &content[..len],
+                    &content[len + close.len()..],
+                )
+            }
+        };
+
+        let coordinate = |name| {
+            attribute(attributes, name)
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| invalid(&format!("bad {} in <{}>", name, tag)))
+        };
+        let altitude = match child(body, "ele") {
+            Some(ele) => Some(ele.parse().map_err(|_| invalid("bad <ele>"))?),
+            None => None,
+        };
+        let time = match child(body, "time") {
+            Some(time) => {
+                Some(parse_time(time).ok_or_else(|| invalid("bad <time>"))?)
+            }
+            None => None,
+        };
+        points.push(Waypoint {
+            latitude: coordinate("lat")?,
+            longitude: coordinate("lon")?,
+            altitude,
+            time,
+        });
+        rest = next;
+    }
+    Ok(points)
+}
+
+/// Value of the attribute `name` among `attributes`, quotes removed.
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    attributes.split_whitespace().find_map(|attribute| {
+        let (key, value) = attribute.split_once('=')?;
+        if key != name {
+            return None;
+        }
+        value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .or_else(|| value.strip_prefix('\'')?.strip_suffix('\''))
+    })
+}
+
+/// The first `tag` element of `text`, with or without a namespace prefix:
+/// the offset of its `<` and its name as written, such as `gpx:trkpt`.
+fn find_tag<'a>(text: &'a str, tag: &str) -> Option<(usize, &'a str)> {
+    let mut from = 0;
+    while let Some(at) = text[from..].find('<') {
+        let start = from + at;
+        let name = &text[start + 1..];
+        let len = name
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            .unwrap_or(name.len());
+        let name = &name[..len];
+        if name.rsplit(':').next() == Some(tag) {
+            return Some((start, name));
+        }
+        from = start + 1;
+    }
+    None
+}
+
+/// Text of the first `name` element of `body`.
+fn child<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    let (start, tag) = find_tag(body, name)?;
+    let content = &body[start + 1 + tag.len()..];
+    let content = &content[content.find('>')? + 1..];
+    let len = content.find(&format!("</{}>", tag))?;
+    Some(content[..len].trim())
+}
+
+/// Seconds since the Unix epoch of an ISO-8601 time such as
+/// `2021-01-03T03:30:28Z`, with optional fraction and UTC offset, written
+/// `+03:00`, `+0300` or `+03`.
+fn parse_time(s: &str) -> Option<f64> {
+    let (date, time) = s.split_once(['T', ' '])?;
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+
+    let (time, offset) = match time.find(['Z', 'z', '+', '-']) {
+        Some(at) => time.split_at(at),
+        None => (time, "Z"),
+    };
+    let offset = match offset {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let offset = &offset[1..];
+            let (hours, minutes) = match offset.split_once(':') {
+                Some(split) => split,
+                None if offset.len() == 4 => offset.split_at(2),
+                None if offset.len() == 2 => (offset, "0"),
+                None => return None,
+            };
+            let hours = hours.parse::<i64>().ok()?.checked_mul(3600)?;
+            let minutes = minutes.parse::<i64>().ok()?.checked_mul(60)?;
+            sign * hours.checked_add(minutes)?
+        }
+    };
+    let mut time = time.splitn(3, ':');
+    let hour: i64 = time.next()?.parse().ok()?;
+    let minute: i64 = time.next()?.parse().ok()?;
+    let second: f64 = time.next()?.parse().ok()?;
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || !(0..24).contains(&hour)
+        || !(0..60).contains(&minute)
+        || !(0.0..61.0).contains(&second)
+    {
+        return None;
+    }
+
+    // Civil date to days, from Howard Hinnant's date algorithms.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era.checked_mul(146_097)?.checked_add(doe - 719_468)?;
+
+    let secs = days
+        .checked_mul(86_400)?
+        .checked_add(hour.checked_mul(3600)?)?
+        .checked_add(minute.checked_mul(60)?)?
+        .checked_sub(offset)?;
+    Some(secs as f64 + second)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reports of a vehicle driving a [`Route`].
+///
+/// The first report starts the trip: the vehicle stays parked at the first
+/// point with the ignition off for the dwell time, drives the route with
+/// the ignition on, then stays parked at the last point with the ignition
+/// off, or starts over when repeating. Speed and heading come from the
+/// route segment being driven, the other fields from the template.
+#[derive(Debug, Clone)]
+pub struct RouteSource {
+    route: Route,
+    template: ReportTemplate,
+
+    /// Meters per second on routes without times.
+    pace: f64,
+    dwell: f64,
+    repeat: bool,
+    seed: u64,
+    rng: Rng,
+
+    /// Time of the first report.
+    start: Option<SystemTime>,
+
+    /// Index of the segment driven at the last report.
+    segment: usize,
+    heading: f64,
+}
+
+impl RouteSource {
+    /// Drive `route` at 50 km/h when it has no times, parked for a minute
+    /// at both ends.
+    pub fn new(route: Route) -> Self {
+        let points = route.points();
+        let heading = points
+            .windows(2)
+            .find(|pair| pair[0].distance(&pair[1]) > 0.0)
+            .map_or(0.0, |pair| pair[0].bearing(&pair[1]));
+        let first = points[0];
+        RouteSource {
+            template: ReportTemplate::new(first.latitude, first.longitude),
+            route,
+            pace: 50.0 / 3.6,
+            dwell: 60.0,
+            repeat: false,
+            seed: 0,
+            rng: Rng::new(0),
+            start: None,
+            segment: 0,
+            heading,
+        }
+    }
+
+    /// Speed on a route without times, in km/h.
+    pub fn speed(mut self, kmh: f64) -> Self {
+        self.pace = kmh.max(0.1) / 3.6;
+        self
+    }
+
+    /// Time parked at each end of the route.
+    pub fn dwell(mut self, dwell: Duration) -> Self {
+        self.dwell = dwell.as_secs_f64();
+        self
+    }
+
+    /// Drive the route again after parking at its end.
+    pub fn repeat(mut self, repeat: bool) -> Self {
+        self.repeat = repeat;
+        self
+    }
+
+    /// Seed of the variations of the GPS readings, 0 by default.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    /// A copy for the LMU numbered `index`, with its own seed.
+    pub(super) fn for_device(&self, index: usize) -> Self {
+        self.clone().seed(self.seed.wrapping_add(index as u64))
+    }
+
+    /// Fields not set from the route, such as RSSI, other inputs or
+    /// accumulators.
+    pub fn template(mut self, template: ReportTemplate) -> Self {
+        self.template = template;
+        self
+    }
+
+    /// Time to drive the route, without the dwell at its ends.
+    pub fn trip_duration(&self) -> Duration {
+        Duration::from_secs_f64(self.offset(self.route.points().len() - 1))
+    }
+
+    /// Seconds from the start of the trip to point `index`.
+    fn offset(&self, index: usize) -> f64 {
+        let points = self.route.points();
+        match (self.route.timed, points[index].time, points[0].time) {
+            (true, Some(time), Some(first)) => time - first,
+            _ => self.route.distances[index] / self.pace,
+        }
+    }
+
+    /// Move to where the vehicle is `elapsed` seconds after the first
+    /// report.
+    fn drive(&mut self, elapsed: f64) {
+        let trip = self.offset(self.route.points().len() - 1);
+        let lap = if self.repeat {
+            elapsed % (2.0 * self.dwell + trip).max(f64::MIN_POSITIVE)
+        } else {
+            elapsed
+        };
+        let driving = lap - self.dwell;
+        let last = self.route.points().len() - 1;
+
+        if driving < 0.0 || driving >= trip {
+            let point = if driving < 0.0 { 0 } else { last };
+            self.segment = 0;
+            self.park(point);
+            return;
+        }
+
+        if driving < self.offset(self.segment) {
+            self.segment = 0;
+        }
+        while self.segment + 1 < last
+            && self.offset(self.segment + 1) <= driving
+        {
+            self.segment += 1;
+        }
+        let (i, points) = (self.segment, self.route.points());
+        let (from, to) = (points[i], points[i + 1]);
+        let (start, end) = (self.offset(i), self.offset(i + 1));
+        let fraction = if end > start {
+            (driving - start) / (end - start)
+        } else {
+            0.0
+        };
+
+        let distance = self.route.distances[i + 1] - self.route.distances[i];
+        if distance > 0.0 {
+            self.heading = from.bearing(&to);
+        }
+        let t = &mut self.template;
+        t.latitude = from.latitude + (to.latitude - from.latitude) * fraction;
+        t.longitude =
+            from.longitude + (to.longitude - from.longitude) * fraction;
+        if let (Some(a), Some(b)) = (from.altitude, to.altitude) {
+            t.altitude = a + (b - a) * fraction;
+        }
+        t.speed = if end > start {
+            distance / (end - start) * 3.6
+        } else {
+            0.0
+        };
+        t.heading = self.heading.round() as u16 % 360;
+        t.inputs |= 1;
+    }
+
+    fn park(&mut self, index: usize) {
+        let point = self.route.points()[index];
+        let t = &mut self.template;
+        t.latitude = point.latitude;
+        t.longitude = point.longitude;
+        if let Some(altitude) = point.altitude {
+            t.altitude = altitude;
+        }
+        t.speed = 0.0;
+        t.heading = self.heading.round() as u16 % 360;
+        t.inputs &= !1;
+    }
+
+    /// Satellites in view wander between 4 and 12, HDOP follows them with
+    /// some jitter, a fix with few satellites is 2D and now and then one
+    /// is differentially corrected.
+    fn vary_gps(&mut self) {
+        let t = &mut self.template;
+        let step = self.rng.below(3) as i16 - 1;
+        t.satellites = (i16::from(t.satellites) + step).clamp(4, 12) as u8;
+        t.hdop = (80 / t.satellites + self.rng.below(4) as u8).max(5);
+        t.fix_status = 0;
+        if t.satellites < 5 {
+            t.fix_status |= 1 << 5;
+        }
+        if self.rng.one_in(8) {
+            t.fix_status |= 1 << 2;
+        }
+    }
+}
+
+impl ReportSource for RouteSource {
+    fn report(&mut self, time: SystemTime) -> EventReport {
+        let start = *self.start.get_or_insert(time);
+        let elapsed = time.duration_since(start).unwrap_or_default();
+        self.drive(elapsed.as_secs_f64());
+        self.vary_gps();
+        self.template.to_report(time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_time, Route, RouteSource, Waypoint};
+    use crate::simulator::ReportSource;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const GPX: &str = r#"<?xml version="1.0"?>
+<gpx version="1.1" creator="test">
+  <trk><name>Marginal</name><trkseg>
+    <trkpt lat="-23.5500" lon="-46.6300"><ele>760</ele>
+      <time>2021-01-03T03:30:00Z</time></trkpt>
+    <trkpt lat="-23.5600" lon="-46.6300"><ele>770</ele>
+      <time>2021-01-03T03:31:00Z</time></trkpt>
+    <trkpt lat='-23.5600' lon='-46.6200'>
+      <time>2021-01-03T03:32:00.500Z</time></trkpt>
+  </trkseg></trk>
+</gpx>"#;
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("2021-01-03T03:30:28Z"), Some(1609644628.0));
+        assert_eq!(parse_time("1970-01-01T00:00:00Z"), Some(0.0));
+        assert_eq!(parse_time("2000-02-29T00:00:00.25Z"), Some(951782400.25));
+        assert_eq!(parse_time("2021-01-03T00:30:28-03:00"), Some(1609644628.0));
+        assert_eq!(parse_time("2021-01-03T06:30:28+0300"), Some(1609644628.0));
+        assert_eq!(parse_time("2021-01-03T06:30:28+03"), Some(1609644628.0));
+        assert_eq!(parse_time("2021-01-03T06:30:28+030"), None);
+        assert_eq!(parse_time("2021-13-03T03:30:28Z"), None);
+        assert_eq!(parse_time("yesterday"), None);
+    }
+
+    #[test]
+    fn test_parse_time_overflow() {
+        let year = "9223372036854775807-01-01T00:00:00Z";
+        assert_eq!(parse_time(year), None);
+        let hour = "2021-01-03T9223372036854775807:00:00Z";
+        assert_eq!(parse_time(hour), None);
+        let offset = "2021-01-03T00:00:00+9223372036854775807:00";
+        assert_eq!(parse_time(offset), None);
+
+        for time in [
+            "2021-01-03T03:30:infZ",
+            "2021-01-03T03:30:NaNZ",
+            "2021-01-03T03:30:1e30Z",
+            "2021-01-03T03:30:-1Z",
+            "2021-01-03T03:30:61Z",
+            "2021-01-03T24:00:00Z",
+            "2021-01-03T03:60:00Z",
+        ] {
+            assert_eq!(parse_time(time), None, "{}", time);
+        }
+        assert_eq!(parse_time("2016-12-31T23:59:60Z"), Some(1483228800.0));
+    }
+
+    #[test]
+    fn test_gpx() {
+        let route = Route::from_gpx(GPX).unwrap();
+        assert_eq!(route.points().len(), 3);
+        assert!(route.is_timed());
+        assert_eq!(route.points()[0].altitude, Some(760.0));
+        assert_eq!(route.points()[2].altitude, None);
+        assert_eq!(route.points()[2].time, Some(1609644720.5));
+        assert!((route.length() - 2132.0).abs() < 1.0, "{}", route.length());
+
+        let rtept = "<gpx><rte><rtept lat=\"1\" lon=\"2\"/></rte></gpx>";
+        let route = Route::from_gpx(rtept).unwrap();
+        assert_eq!(route.points(), [Waypoint::new(1.0, 2.0)]);
+        assert!(Route::from_gpx("<gpx></gpx>").is_err());
+        assert!(Route::from_gpx("<gpx><trkpt lat=\"x\" lon=\"2\"/>").is_err());
+    }
+
+    #[test]
+    fn test_gpx_tag_names() {
+        // <trkptx> is another element, not a track point.
+        let gpx =
+            r#"<gpx><trkptx lat="9" lon="9"/><trkpt lat="1" lon="2"/></gpx>"#;
+        let route = Route::from_gpx(gpx).unwrap();
+        assert_eq!(route.points(), [Waypoint::new(1.0, 2.0)]);
+
+        let gpx = r#"<gpx:gpx><gpx:trk><gpx:trkseg>
+            <gpx:trkpt lat="1" lon="2"><gpx:ele>5</gpx:ele></gpx:trkpt>
+            <gpx:trkpt lat="3" lon="4"/>
+        </gpx:trkseg></gpx:trk></gpx:gpx>"#;
+        let route = Route::from_gpx(gpx).unwrap();
+        assert_eq!(route.points().len(), 2);
+        assert_eq!(route.points()[0].altitude, Some(5.0));
+        assert_eq!(route.points()[1], Waypoint::new(3.0, 4.0));
+    }
+
+    #[test]
+    fn test_geojson() {
+        let feature = r#"{
+            "type": "Feature",
+            "properties": {
+                "coordTimes": ["2021-01-03T03:30:00Z", "2021-01-03T03:31:00Z"]
+            },
+            "geometry": {
+                "type": "LineString",
+                "coordinates": [[-46.63, -23.55, 760], [-46.63, -23.56]]
+            }
+        }"#;
+        let route = Route::from_geojson(feature).unwrap();
+        assert_eq!(route.points()[0].latitude, -23.55);
+        assert_eq!(route.points()[0].altitude, Some(760.0));
+        assert!(route.is_timed());
+
+        let collection = r#"{"type": "FeatureCollection", "features": [
+            {"type": "Feature", "geometry": {"type": "Point",
+                "coordinates": [0, 0]}},
+            {"type": "Feature", "geometry": {"type": "MultiLineString",
+                "coordinates": [[[0, 0], [0, 1]], [[1, 1]]]}}
+        ]}"#;
+        let route = Route::from_geojson(collection).unwrap();
+        assert_eq!(route.points().len(), 3);
+        assert!(!route.is_timed());
+        assert!(Route::from_geojson(r#"{"type": "Point"}"#).is_err());
+    }
+
+    #[test]
+    fn test_drive_timed_route() {
+        let route = Route::from_gpx(GPX).unwrap();
+        let mut source = RouteSource::new(route)
+            .dwell(Duration::from_secs(30))
+            .seed(7);
+        assert_eq!(source.trip_duration(), Duration::from_millis(120_500));
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let at = |secs| start + Duration::from_secs(secs);
+
+        let parked = source.report(at(0));
+        assert!(!parked.inputs.ignition);
+        assert_eq!(parked.speed.raw(), 0);
+        assert_eq!(parked.latitude, -235500000);
+
+        // Half way down the first segment, 1112 m in a minute, South.
+        let driving = source.report(at(60));
+        assert!(driving.inputs.ignition);
+        assert_eq!(driving.latitude, -235550000);
+        assert_eq!(driving.altitude.raw(), 76500);
+        assert_eq!(driving.heading.raw(), 180);
+        assert!((driving.speed.raw() as i64 - 1853).abs() <= 1);
+
+        // Heading East on the second segment.
+        let driving = source.report(at(120));
+        assert_eq!(driving.heading.raw(), 90);
+        assert_eq!(driving.latitude, -235600000);
+
+        let arrived = source.report(at(151));
+        assert!(!arrived.inputs.ignition);
+        assert_eq!(arrived.longitude, -466200000);
+        assert_eq!(arrived.speed.raw(), 0);
+        assert_eq!(arrived.heading.raw(), 90);
+    }
+
+    #[test]
+    fn test_drive_untimed_route_repeat() {
+        let route =
+            Route::new(vec![Waypoint::new(0.0, 0.0), Waypoint::new(0.0, 0.01)])
+                .unwrap();
+        let mut source = RouteSource::new(route)
+            .speed(40.0)
+            .dwell(Duration::from_secs(10))
+            .repeat(true);
+        // 1112 m at 40 km/h.
+        assert_eq!(source.trip_duration().as_secs(), 100);
+        let start = UNIX_EPOCH;
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert!(!source.report(at(0)).inputs.ignition);
+        let driving = source.report(at(60));
+        assert!(driving.inputs.ignition);
+        assert!((driving.longitude - 50000).abs() < 100);
+        assert_eq!(driving.speed.raw(), 1111);
+        assert!(!source.report(at(115)).inputs.ignition);
+        // The second lap, 120 s after the first.
+        let driving = source.report(at(180));
+        assert!(driving.inputs.ignition);
+        assert!((driving.longitude - 50000).abs() < 200);
+    }
+
+    #[test]
+    fn test_seeded_gps_variation() {
+        let route = Route::new(vec![Waypoint::new(1.0, 2.0)]).unwrap();
+        let readings = |seed| {
+            let mut source = RouteSource::new(route.clone()).seed(seed);
+            (0..50)
+                .map(|i| {
+                    let time = UNIX_EPOCH + Duration::from_secs(i * 30);
+                    let report = source.report(time);
+                    let fix_status = u8::from(&report.fix_status);
+                    (report.satellites, report.hdop.raw(), fix_status)
+                })
+                .collect::<Vec<_>>()
+        };
+        let run = readings(1);
+        assert_eq!(run, readings(1));
+        assert_ne!(run, readings(2));
+        assert!(run.iter().all(|(sats, _, _)| (4..=12).contains(sats)));
+        assert!(run.windows(2).any(|pair| pair[0].0 != pair[1].0));
+    }
+}
@@ -0,0 +1,179 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Message times.
+//!
+//! LMDirect times are seconds since the Unix epoch, [`Timestamp`] keeps that
+//! value whatever features are enabled. The `chrono` and `time` features add
+//! conversions to their date types. A zero time is sent by an LMU that has
+//! not obtained a time-sync yet and does not convert.
+
+#[cfg(any(feature = "chrono", feature = "time"))]
+use core::convert::TryFrom;
+use core::fmt;
+
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, TimeZone, Utc};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(any(feature = "chrono", feature = "time"))]
+use crate::error::Error;
+
+/// Seconds since 1970-01-01T00:00:00Z.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Timestamp(u32);
+
+impl Timestamp {
+    pub fn new(secs: u32) -> Self {
+        Timestamp(secs)
+    }
+
+    /// Seconds since the Unix epoch, as sent by the LMU.
+    pub fn secs(&self) -> u32 {
+        self.0
+    }
+
+    /// A zero time means the LMU had no valid time.
+    pub fn is_valid(&self) -> bool {
+        self.0 != 0
+    }
+
+    #[cfg(feature = "chrono")]
+    pub fn to_chrono(&self) -> Option<DateTime<Utc>> {
+        if !self.is_valid() {
+            return None;
+        }
+        Utc.timestamp_opt(i64::from(self.0), 0).single()
+    }
+
+    #[cfg(feature = "time")]
+    pub fn to_offset_date_time(&self) -> Option<time::OffsetDateTime> {
+        if !self.is_valid() {
+            return None;
+        }
+        time::OffsetDateTime::from_unix_timestamp(i64::from(self.0)).ok()
+    }
+
+    /// Calendar date and time in UTC: (year, month, day, hour, min, sec).
+    fn to_civil(self) -> (i64, u32, u32, u32, u32, u32) {
+        let secs = i64::from(self.0);
+        let days = secs.div_euclid(86_400);
+        let rem = secs.rem_euclid(86_400) as u32;
+
+        // Days to civil date, from Howard Hinnant's date algorithms.
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        (year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+    }
+}
+
+impl From<u32> for Timestamp {
+    fn from(secs: u32) -> Self {
+        Timestamp(secs)
+    }
+}
+
+impl From<Timestamp> for u32 {
+    fn from(timestamp: Timestamp) -> u32 {
+        timestamp.0
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl TryFrom<Timestamp> for DateTime<Utc> {
+    type Error = Error;
+
+    fn try_from(timestamp: Timestamp) -> Result<Self, Self::Error> {
+        timestamp
+            .to_chrono()
+            .ok_or(Error::InvalidTimestamp(timestamp.0))
+    }
+}
+
+#[cfg(feature = "time")]
+impl TryFrom<Timestamp> for time::OffsetDateTime {
+    type Error = Error;
+
+    fn try_from(timestamp: Timestamp) -> Result<Self, Self::Error> {
+        timestamp
+            .to_offset_date_time()
+            .ok_or(Error::InvalidTimestamp(timestamp.0))
+    }
+}
+
+/// ISO-8601 in UTC, e.g. `2021-01-03T03:30:28Z`.
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day, hour, min, sec) = self.to_civil();
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year, month, day, hour, min, sec
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Timestamp;
+
+    #[test]
+    fn test_timestamp_display() {
+        assert_eq!(
+            format!("{}", Timestamp::new(1609644628)),
+            "2021-01-03T03:30:28Z"
+        );
+        assert_eq!(format!("{}", Timestamp::new(0)), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            format!("{}", Timestamp::new(u32::MAX)),
+            "2106-02-07T06:28:15Z"
+        );
+        assert_eq!(
+            format!("{}", Timestamp::new(951_782_400)),
+            "2000-02-29T00:00:00Z"
+        );
+        assert!(!Timestamp::new(0).is_valid());
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_timestamp_chrono() {
+        use chrono::{DateTime, Utc};
+        use std::convert::TryFrom;
+
+        let dt = Timestamp::new(1609644628).to_chrono().unwrap();
+        assert_eq!(dt.to_rfc3339(), "2021-01-03T03:30:28+00:00");
+        assert_eq!(Timestamp::new(0).to_chrono(), None);
+        assert!(DateTime::<Utc>::try_from(Timestamp::new(0)).is_err());
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_timestamp_time() {
+        let dt = Timestamp::new(1609644628).to_offset_date_time().unwrap();
+        assert_eq!(dt.unix_timestamp(), 1609644628);
+        assert_eq!(Timestamp::new(0).to_offset_date_time(), None);
+    }
+}
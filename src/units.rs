@@ -0,0 +1,395 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Physical units of the Event Report fields.
+//!
+//! Every type keeps the raw value exactly as sent by the LMU and offers
+//! accessors for the usual units. With the `serde` feature they serialize
+//! as an object holding both the `raw` value and the converted one.
+
+use core::fmt;
+
+#[cfg(feature = "serde")]
+use serde::ser::SerializeStruct;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Speed reported by the GPS receiver, centimeters per second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Speed(u32);
+
+impl Speed {
+    pub fn new(cm_s: u32) -> Self {
+        Speed(cm_s)
+    }
+
+    /// Raw value in centimeters per second.
+    pub fn raw(&self) -> u32 {
+        self.0
+    }
+
+    /// Meters per second.
+    pub fn mps(&self) -> f64 {
+        f64::from(self.0) / 100.0
+    }
+
+    /// Kilometers per hour.
+    pub fn kmh(&self) -> f64 {
+        self.mps() * 3.6
+    }
+
+    /// Miles per hour.
+    pub fn mph(&self) -> f64 {
+        self.mps() / 0.447_04
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.2} km/h", self.kmh())
+    }
+}
+
+/// Altitude above the WGS-84 Datum, centimeters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Altitude(i32);
+
+impl Altitude {
+    pub fn new(cm: i32) -> Self {
+        Altitude(cm)
+    }
+
+    /// Raw value in centimeters.
+    pub fn raw(&self) -> i32 {
+        self.0
+    }
+
+    /// Meters.
+    pub fn meters(&self) -> f64 {
+        f64::from(self.0) / 100.0
+    }
+
+    /// Feet.
+    pub fn feet(&self) -> f64 {
+        self.meters() / 0.3048
+    }
+}
+
+impl fmt::Display for Altitude {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.2} m", self.meters())
+    }
+}
+
+/// Eight point compass rose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompassPoint {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+impl CompassPoint {
+    pub fn abbreviation(&self) -> &'static str {
+        match *self {
+            CompassPoint::North => "N",
+            CompassPoint::NorthEast => "NE",
+            CompassPoint::East => "E",
+            CompassPoint::SouthEast => "SE",
+            CompassPoint::South => "S",
+            CompassPoint::SouthWest => "SW",
+            CompassPoint::West => "W",
+            CompassPoint::NorthWest => "NW",
+        }
+    }
+}
+
+impl fmt::Display for CompassPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.abbreviation())
+    }
+}
+
+/// Heading in degrees from true North.
+///
+/// The raw value is kept even when it is out of range, valid headings are
+/// 0 to 359.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Heading(u16);
+
+impl Heading {
+    pub fn new(raw: u16) -> Self {
+        Heading(raw)
+    }
+
+    /// Heading from degrees, `None` if outside 0–359.
+    pub fn from_degrees(degrees: u16) -> Option<Self> {
+        let heading = Heading(degrees);
+        if heading.is_valid() {
+            Some(heading)
+        } else {
+            None
+        }
+    }
+
+    pub fn raw(&self) -> u16 {
+        self.0
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.0 < 360
+    }
+
+    /// Degrees from true North, `None` if the LMU sent an invalid value.
+    pub fn degrees(&self) -> Option<u16> {
+        if self.is_valid() {
+            Some(self.0)
+        } else {
+            None
+        }
+    }
+
+    /// Nearest point of an eight point compass rose.
+    pub fn compass(&self) -> Option<CompassPoint> {
+        let points = [
+            CompassPoint::North,
+            CompassPoint::NorthEast,
+            CompassPoint::East,
+            CompassPoint::SouthEast,
+            CompassPoint::South,
+            CompassPoint::SouthWest,
+            CompassPoint::West,
+            CompassPoint::NorthWest,
+        ];
+        self.degrees()
+            .map(|d| points[((usize::from(d) * 2 + 45) / 90) % 8])
+    }
+}
+
+impl fmt::Display for Heading {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.compass() {
+            Some(point) => write!(f, "{}° {}", self.0, point),
+            None => write!(f, "invalid ({})", self.0),
+        }
+    }
+}
+
+/// GPS Horizontal Dilution of Precision, unit-less with a 0.1 lsb.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Hdop(u8);
+
+impl Hdop {
+    pub fn new(raw: u8) -> Self {
+        Hdop(raw)
+    }
+
+    pub fn raw(&self) -> u8 {
+        self.0
+    }
+
+    pub fn value(&self) -> f32 {
+        f32::from(self.0) / 10.0
+    }
+}
+
+impl fmt::Display for Hdop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.1}", self.value())
+    }
+}
+
+/// Received signal strength of the wireless modem, dBm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Rssi(i16);
+
+impl Rssi {
+    pub fn new(dbm: i16) -> Self {
+        Rssi(dbm)
+    }
+
+    pub fn raw(&self) -> i16 {
+        self.0
+    }
+
+    pub fn dbm(&self) -> i16 {
+        self.0
+    }
+
+    /// Signal power in milliwatts.
+    #[cfg(feature = "std")]
+    pub fn milliwatts(&self) -> f64 {
+        10f64.powf(f64::from(self.0) / 10.0)
+    }
+}
+
+impl fmt::Display for Rssi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} dBm", self.0)
+    }
+}
+
+/// Deserialization only needs the raw value, the converted one is derived.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct Raw<T> {
+    raw: T,
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Speed {
+    fn serialize<S: Serializer>(
+        &self, serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Speed", 2)?;
+        s.serialize_field("raw", &self.0)?;
+        s.serialize_field("m_s", &self.mps())?;
+        s.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Speed {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        Raw::deserialize(deserializer).map(|r| Speed(r.raw))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Altitude {
+    fn serialize<S: Serializer>(
+        &self, serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Altitude", 2)?;
+        s.serialize_field("raw", &self.0)?;
+        s.serialize_field("m", &self.meters())?;
+        s.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Altitude {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        Raw::deserialize(deserializer).map(|r| Altitude(r.raw))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Heading {
+    fn serialize<S: Serializer>(
+        &self, serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Heading", 2)?;
+        s.serialize_field("raw", &self.0)?;
+        s.serialize_field("deg", &self.degrees())?;
+        s.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Heading {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        Raw::deserialize(deserializer).map(|r| Heading(r.raw))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Hdop {
+    fn serialize<S: Serializer>(
+        &self, serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Hdop", 2)?;
+        s.serialize_field("raw", &self.0)?;
+        s.serialize_field("value", &self.value())?;
+        s.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Hdop {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        Raw::deserialize(deserializer).map(|r| Hdop(r.raw))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Rssi {
+    fn serialize<S: Serializer>(
+        &self, serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Rssi", 2)?;
+        s.serialize_field("raw", &self.0)?;
+        s.serialize_field("dbm", &self.dbm())?;
+        s.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Rssi {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        Raw::deserialize(deserializer).map(|r| Rssi(r.raw))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Altitude, CompassPoint, Hdop, Heading, Rssi, Speed};
+
+    #[test]
+    fn test_unit_conversions() {
+        let speed = Speed::new(2500);
+        approx::assert_relative_eq!(speed.mps(), 25.0);
+        approx::assert_relative_eq!(speed.kmh(), 90.0);
+        approx::assert_relative_eq!(speed.mph(), 55.923_407, epsilon = 1e-6);
+
+        let altitude = Altitude::new(-3048);
+        approx::assert_relative_eq!(altitude.meters(), -30.48);
+        approx::assert_relative_eq!(altitude.feet(), -100.0);
+
+        approx::assert_relative_eq!(Hdop::new(13).value(), 1.3);
+        assert_eq!(Rssi::new(-115).dbm(), -115);
+        #[cfg(feature = "std")]
+        approx::assert_relative_eq!(Rssi::new(-30).milliwatts(), 0.001);
+    }
+
+    #[test]
+    fn test_heading_compass() {
+        assert_eq!(Heading::new(0).compass(), Some(CompassPoint::North));
+        assert_eq!(Heading::new(22).compass(), Some(CompassPoint::North));
+        assert_eq!(Heading::new(23).compass(), Some(CompassPoint::NorthEast));
+        assert_eq!(Heading::new(180).compass(), Some(CompassPoint::South));
+        assert_eq!(Heading::new(337).compass(), Some(CompassPoint::NorthWest));
+        assert_eq!(Heading::new(338).compass(), Some(CompassPoint::North));
+        assert_eq!(Heading::new(359).degrees(), Some(359));
+        assert_eq!(Heading::new(360).degrees(), None);
+        assert_eq!(Heading::new(360).compass(), None);
+        assert_eq!(Heading::from_degrees(400), None);
+        assert_eq!(format!("{}", Heading::new(90)), "90° E");
+    }
+}
@@ -12,47 +12,45 @@
 // OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
 //
 
-#[cfg(feature = "chrono")]
-use chrono::{DateTime, TimeZone, Utc};
-use nom::error::ErrorKind;
 use nom::number::streaming::{be_i16, be_i32, be_u16, be_u32, be_u8};
-use nom::IResult;
+use nom::{IResult, Needed};
+
+use crate::error::Error;
 
-#[allow(dead_code)]
 pub(crate) fn pu32(input: &[u8]) -> IResult<&[u8], u32> {
-    let (i, a): (&[u8], u32) = be_u32::<_, (_, ErrorKind)>(input).unwrap();
-    Ok((i, a))
+    be_u32(input)
 }
 
-#[allow(dead_code)]
 pub(crate) fn pu16(input: &[u8]) -> IResult<&[u8], u16> {
-    let (i, a): (&[u8], u16) = be_u16::<_, (_, ErrorKind)>(input).unwrap();
-    Ok((i, a))
+    be_u16(input)
 }
 
-#[allow(dead_code)]
 pub(crate) fn pu8(input: &[u8]) -> IResult<&[u8], u8> {
-    let (i, a): (&[u8], u8) = be_u8::<_, (_, ErrorKind)>(input).unwrap();
-    Ok((i, a))
+    be_u8(input)
 }
 
-#[allow(dead_code)]
 pub(crate) fn p16(input: &[u8]) -> IResult<&[u8], i16> {
-    let (i, a): (&[u8], i16) = be_i16::<_, (_, ErrorKind)>(input).unwrap();
-    Ok((i, a))
+    be_i16(input)
 }
 
-#[allow(dead_code)]
-pub(crate) fn pf32(input: &[u8]) -> IResult<&[u8], f32> {
-    let (i, a): (&[u8], i32) = be_i32::<_, (_, ErrorKind)>(input).unwrap();
-    let b = (a as f32) * 1e-7;
-    Ok((i, b))
+pub(crate) fn pi32(input: &[u8]) -> IResult<&[u8], i32> {
+    be_i32(input)
 }
 
-#[cfg(feature = "chrono")]
-#[allow(dead_code)]
-pub(crate) fn pdt(input: &[u8]) -> IResult<&[u8], DateTime<Utc>> {
-    let (i, a): (&[u8], u32) = be_u32::<_, (_, ErrorKind)>(input).unwrap();
-    let dt = Utc.timestamp(a, 0);
-    Ok((i, dt))
+/// Convert a nom error into [`Error`], `input` is the buffer the failing
+/// parser was started on so the offset can be reported.
+pub(crate) fn error(
+    input: &[u8], err: nom::Err<nom::error::Error<&[u8]>>,
+) -> Error {
+    match err {
+        nom::Err::Incomplete(Needed::Size(n)) => {
+            Error::Incomplete { needed: n.get() }
+        }
+        nom::Err::Incomplete(Needed::Unknown) => {
+            Error::Incomplete { needed: 1 }
+        }
+        nom::Err::Error(e) | nom::Err::Failure(e) => Error::Malformed {
+            offset: input.len() - e.input.len(),
+        },
+    }
 }
@@ -0,0 +1,508 @@
+//
+// Copyright (c) 2021 Murilo Ijanc' <mbsd@m0x.ru>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+//! Wireshark dissector.
+//!
+//! [`lua_dissector`] writes a Lua plugin decoding LMDirect in Wireshark. It
+//! is generated from the field layouts [`dissect`](crate::dissect) walks,
+//! so both show the same fields with the same names. Copy it to the
+//! personal Lua plugins folder, `~/.local/lib/wireshark/plugins` on Linux.
+//!
+//! ```
+//! let lua = calamp_rs::wireshark::lua_dissector(&[20500]);
+//! assert!(lua.contains("Proto(\"lmdirect\""));
+//! ```
+
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::layout::{self, BitDecode, BitField, Decode, Field};
+use crate::message_header::{MessageType, ServiceType};
+use crate::messages::event_report::NetworkTechnology;
+use crate::options_header::MobileIDType;
+
+/// The Lua source of a dissector for LMDirect on the UDP `ports`.
+pub fn lua_dissector(ports: &[u16]) -> String {
+    let mut out = String::from(HEADER);
+
+    out.push_str("\n-- Value strings\n\n");
+    let service_types = names(|b| ServiceType::from_u8(b).name());
+    value_string(&mut out, "service_types", service_types);
+    let message_types = names(|b| MessageType::from_u8(b).name());
+    value_string(&mut out, "message_types", message_types);
+    let mobile_id_types = names(|b| MobileIDType::from_u8(b).name());
+    value_string(&mut out, "mobile_id_types", mobile_id_types);
+    let technologies = names(|b| NetworkTechnology::parse(b).name());
+    value_string(&mut out, "network_technologies", technologies);
+    let nak_reasons = layout::NAK_REASONS.iter().map(|r| r.to_string());
+    value_string(&mut out, "nak_reasons", nak_reasons.collect());
+    value_string(
+        &mut out,
+        "clear_flag",
+        ["True", "False"].iter().map(|s| s.to_string()).collect(),
+    );
+
+    out.push_str("\n-- Fields\n\nlocal f = lmdirect.fields\n");
+    proto_fields(&mut out, "", &layout::OPTIONS);
+    for (name, value) in layout::OPTION_FIELDS.iter() {
+        proto_field(&mut out, name, &LENGTH);
+        proto_field(&mut out, name, value);
+    }
+    proto_fields(&mut out, "message_header", &layout::MESSAGE_HEADER);
+    proto_fields(&mut out, "event_report", &layout::EVENT_REPORT);
+    proto_field(&mut out, "event_report", &layout::ACCUMULATOR);
+    proto_field(&mut out, "event_report.block", &LENGTH);
+    proto_field(&mut out, "event_report.block", &BLOCK_DATA);
+    proto_fields(&mut out, "ack_nak", &layout::ACK_NAK);
+    proto_field(&mut out, "", &BODY);
+    proto_field(&mut out, "", &TRAILING);
+
+    out.push_str("\n-- Layouts\n\n");
+    lua_layout(&mut out, "options", "", &layout::OPTIONS);
+    lua_layout(
+        &mut out,
+        "message_header",
+        "message_header",
+        &layout::MESSAGE_HEADER,
+    );
+    lua_layout(
+        &mut out,
+        "event_report",
+        "event_report",
+        &layout::EVENT_REPORT,
+    );
+    lua_layout(
+        &mut out,
+        "accumulator",
+        "event_report",
+        &[layout::ACCUMULATOR],
+    );
+    lua_layout(&mut out, "ack_nak", "ack_nak", &layout::ACK_NAK);
+
+    out.push_str(HELPERS);
+    options_header(&mut out);
+    event_report(&mut out);
+    dissector(&mut out);
+
+    out.push_str("\nlocal udp_port = DissectorTable.get(\"udp.port\")\n");
+    for port in ports {
+        let _ = writeln!(out, "udp_port:add({}, lmdirect)", port);
+    }
+    out
+}
+
+const HEADER: &str = r#"-- CalAmp LMDirect dissector for Wireshark.
+--
+-- Generated by `calamp wireshark` from the field layouts of calamp-rs, do
+-- not edit. Copy to the personal Lua plugins folder and use "Decode As..."
+-- for ports other than the ones registered at the end.
+
+local lmdirect = Proto("lmdirect", "CalAmp LMDirect")
+
+local ef_truncated = ProtoExpert.new("lmdirect.truncated",
+    "Message truncated", expert.group.MALFORMED, expert.severity.ERROR)
+local ef_trailing = ProtoExpert.new("lmdirect.trailing_bytes",
+    "Bytes after the message", expert.group.MALFORMED, expert.severity.WARN)
+lmdirect.experts = { ef_truncated, ef_trailing }
+"#;
+
+const HELPERS: &str = r#"
+-- Dissection
+
+-- Whether `bit` of `value` is set.
+local function has(value, bit)
+    return math.floor(value / 2 ^ bit) % 2 == 1
+end
+
+-- Add the fields of `layout` to `tree` from `off` on, returning the offset
+-- after them, or nil when the message ends first.
+local function fixed(layout, buf, off, tree)
+    for _, field in ipairs(layout) do
+        if off + field.len > buf:len() then
+            tree:add_proto_expert_info(ef_truncated)
+            return nil
+        end
+        local range = buf(off, field.len)
+        local item
+        if field.time then
+            item = tree:add(field.field, range, NSTime.new(range:uint(), 0))
+        elseif field.factor then
+            local value = field.signed and range:int() or range:uint()
+            item = tree:add(field.field, range, value,
+                string.format(field.label, value * field.factor))
+        else
+            item = tree:add(field.field, range)
+        end
+        for _, bit in ipairs(field.bits or {}) do
+            item:add(bit, range)
+        end
+        off = off + field.len
+    end
+    return off
+end
+
+-- A length byte then as many bytes of `value`.
+local function sized(tree, buf, off, length, value)
+    off = fixed({ { len = 1, field = length } }, buf, off, tree)
+    if not off then
+        return nil
+    end
+    local len = buf(off - 1, 1):uint()
+    if len == 0 then
+        return off
+    end
+    return fixed({ { len = len, field = value } }, buf, off, tree)
+end
+
+-- The fields added by `dissect` in a subtree named `name`.
+local function group(tree, buf, off, name, dissect)
+    if off >= buf:len() then
+        tree:add_proto_expert_info(ef_truncated)
+        return nil
+    end
+    local subtree = tree:add(lmdirect, buf(off, 1), name)
+    local after = dissect(subtree, buf, off)
+    subtree:set_len((after or buf:len()) - off)
+    return after
+end
+"#;
+
+/// A length byte, of options and appended blocks.
+const LENGTH: Field = Field {
+    name: "length",
+    len: 1,
+    decode: Decode::Unsigned,
+};
+
+const BLOCK_DATA: Field = Field {
+    name: "data",
+    len: 0,
+    decode: Decode::Hex,
+};
+
+/// The body of the message types not dissected.
+const BODY: Field = Field {
+    name: "body",
+    len: 0,
+    decode: Decode::Hex,
+};
+
+const TRAILING: Field = Field {
+    name: "trailing",
+    len: 0,
+    decode: Decode::Hex,
+};
+
+/// The snake case names of the values from 0 up to the first one without
+/// a name.
+fn names<F>(name: F) -> Vec<String>
+where
+    F: Fn(u8) -> Cow<'static, str>,
+{
+    (0..=u8::MAX)
+        .map(name)
+        .take_while(|name| !name.starts_with("unknown_"))
+        .map(Cow::into_owned)
+        .collect()
+}
+
+fn value_string(out: &mut String, name: &str, values: Vec<String>) {
+    let _ = writeln!(out, "local {} = {{", name);
+    for (n, value) in values.iter().enumerate() {
+        let _ = writeln!(out, "    [{}] = \"{}\",", n, value);
+    }
+    out.push_str("}\n");
+}
+
+/// The Lua name of the field `name` of `group`, such as
+/// `event_report_latitude`.
+fn key(group: &str, name: &str) -> String {
+    if group.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}_{}", group.replace('.', "_"), name)
+    }
+}
+
+/// The filter name of the field `name` of `group`, such as
+/// `lmdirect.event_report.latitude`.
+fn abbr(group: &str, name: &str) -> String {
+    if group.is_empty() {
+        format!("lmdirect.{}", name)
+    } else {
+        format!("lmdirect.{}.{}", group, name)
+    }
+}
+
+fn proto_fields(out: &mut String, group: &str, fields: &[Field]) {
+    for field in fields {
+        proto_field(out, group, field);
+    }
+}
+
+fn proto_field(out: &mut String, group: &str, field: &Field) {
+    let bits = 8 * field.len;
+    let (abbr, name) = (abbr(group, field.name), field.name);
+    let ctor = match field.decode {
+        Decode::Unsigned => {
+            format!("uint{}(\"{}\", \"{}\", base.DEC)", bits, abbr, name)
+        }
+        Decode::Hex => format!("bytes(\"{}\", \"{}\")", abbr, name),
+        Decode::Timestamp => {
+            format!("absolute_time(\"{}\", \"{}\", base.UTC)", abbr, name)
+        }
+        Decode::Scaled { signed, .. } => format!(
+            "{}{}(\"{}\", \"{}\", base.DEC)",
+            if signed { "int" } else { "uint" },
+            bits,
+            abbr,
+            name
+        ),
+        Decode::ServiceType => value_field(&abbr, name, "service_types"),
+        Decode::MessageType => value_field(&abbr, name, "message_types"),
+        Decode::MobileIDType => value_field(&abbr, name, "mobile_id_types"),
+        Decode::Ack => value_field(&abbr, name, "nak_reasons"),
+        Decode::Bits(_) => {
+            format!("uint8(\"{}\", \"{}\", base.HEX)", abbr, name)
+        }
+    };
+    let _ = writeln!(out, "f.{} = ProtoField.{}", key(group, name), ctor);
+
+    if let Decode::Bits(bits) = field.decode {
+        let group = if group.is_empty() {
+            field.name.to_string()
+        } else {
+            format!("{}.{}", group, field.name)
+        };
+        for bit in bits {
+            bit_field(out, &group, bit);
+        }
+    }
+}
+
+fn value_field(abbr: &str, name: &str, values: &str) -> String {
+    format!("uint8(\"{}\", \"{}\", base.DEC, {})", abbr, name, values)
+}
+
+fn bit_field(out: &mut String, group: &str, bit: &BitField) {
+    let (abbr, name, mask) = (abbr(group, bit.name), bit.name, bit.mask());
+    let ctor = match bit.decode {
+        BitDecode::Flag => {
+            format!("bool(\"{}\", \"{}\", 8, nil, {:#04x})", abbr, name, mask)
+        }
+        BitDecode::ClearFlag => format!(
+            "uint8(\"{}\", \"{}\", base.DEC, clear_flag, {:#04x})",
+            abbr, name, mask
+        ),
+        BitDecode::Number => format!(
+            "uint8(\"{}\", \"{}\", base.DEC, nil, {:#04x})",
+            abbr, name, mask
+        ),
+        BitDecode::NetworkTechnology => format!(
+            "uint8(\"{}\", \"{}\", base.DEC, network_technologies, {:#04x})",
+            abbr, name, mask
+        ),
+    };
+    let _ = writeln!(out, "f.{} = ProtoField.{}", key(group, name), ctor);
+}
+
+/// The Lua table of `fields`, `<name>_fields`, read by `fixed`.
+fn lua_layout(out: &mut String, name: &str, group: &str, fields: &[Field]) {
+    let _ = writeln!(out, "local {}_fields = {{", name);
+    for field in fields {
+        let _ = write!(
+            out,
+            "    {{ len = {}, field = f.{}",
+            field.len,
+            key(group, field.name)
+        );
+        match field.decode {
+            Decode::Timestamp => out.push_str(", time = true"),
+            Decode::Scaled {
+                signed,
+                factor,
+                decimals,
+                unit,
+            } => {
+                let _ = write!(
+                    out,
+                    ", signed = {}, factor = {:?}, label = \"{}: %.{}f{}\"",
+                    signed, factor, field.name, decimals, unit
+                );
+            }
+            Decode::Bits(bits) if !bits.is_empty() => {
+                let group = if group.is_empty() {
+                    field.name.to_string()
+                } else {
+                    format!("{}.{}", group, field.name)
+                };
+                out.push_str(", bits = {");
+                for (n, bit) in bits.iter().enumerate() {
+                    let sep = if n == 0 { "" } else { "," };
+                    let _ = write!(
+                        out,
+                        "{}\n        f.{}",
+                        sep,
+                        key(&group, bit.name)
+                    );
+                }
+                out.push_str(",\n    }");
+            }
+            _ => {}
+        }
+        out.push_str(" },\n");
+    }
+    out.push_str("}\n");
+}
+
+fn options_header(out: &mut String) {
+    out.push_str(
+        r#"
+local function options_header(tree, buf, off)
+    local options = buf(off, 1):uint()
+    off = fixed(options_fields, buf, off, tree)
+"#,
+    );
+    for (bit, (name, value)) in layout::OPTION_FIELDS.iter().enumerate() {
+        let dissect = format!(
+            "sized(t, buf, off, f.{}, f.{})",
+            key(name, LENGTH.name),
+            key(name, value.name)
+        );
+        let _ = write!(
+            out,
+            r#"    if off and has(options, {bit}) then
+        off = group(tree, buf, off, "{name}", function(t, buf, off)
+            return {dissect}
+        end)
+    end
+"#,
+            bit = bit,
+            name = name,
+            dissect = dissect,
+        );
+    }
+    out.push_str("    return off\nend\n");
+}
+
+fn event_report(out: &mut String) {
+    let accums = layout::EVENT_REPORT_ACCUMS;
+    let append = layout::EVENT_REPORT_APPEND;
+    let bits = &layout::ACCUMS_BITS[0];
+    let accums_byte = format!("buf(start + {}, 1):uint()", accums);
+    let count = match bits.shift {
+        0 => format!("{} % {}", accums_byte, 1u32 << bits.width),
+        shift => format!(
+            "math.floor({} / {}) % {}",
+            accums_byte,
+            1u32 << shift,
+            1u32 << bits.width
+        ),
+    };
+    let _ = write!(
+        out,
+        r#"
+local function event_report(tree, buf, off)
+    local start = off
+    off = fixed(event_report_fields, buf, off, tree)
+    if not off then
+        return nil
+    end
+    local count = {count}
+    local append = buf(start + {append}, 1):uint()
+    if count > 0 then
+        off = group(tree, buf, off, "accumulators", function(t, buf, off)
+            for _ = 1, count do
+                off = fixed(accumulator_fields, buf, off, t)
+                if not off then
+                    return nil
+                end
+            end
+            return off
+        end)
+    end
+    if off and append ~= 0 then
+        off = group(tree, buf, off, "appended", function(t, buf, off)
+            for bit = 0, 7 do
+                if off and has(append, bit) then
+                    off = group(t, buf, off, "block", function(t, buf, off)
+                        return sized(t, buf, off, f.{length}, f.{data})
+                    end)
+                end
+            end
+            return off
+        end)
+    end
+    return off
+end
+"#,
+        count = count,
+        append = append,
+        length = key("event_report.block", LENGTH.name),
+        data = key("event_report.block", BLOCK_DATA.name),
+    );
+}
+
+fn dissector(out: &mut String) {
+    let message_type = layout::offset(&layout::MESSAGE_HEADER, "message_type");
+    let _ = write!(
+        out,
+        r#"
+function lmdirect.dissector(buf, pinfo, tree)
+    pinfo.cols.protocol = "LMDirect"
+    local root = tree:add(lmdirect, buf())
+    local off = 0
+    if buf:len() > 0 and has(buf(0, 1):uint(), 7) then
+        off = group(root, buf, off, "options_header", options_header)
+        if not off then
+            return
+        end
+    end
+
+    local header = off
+    off = group(root, buf, off, "message_header", function(t, buf, off)
+        return fixed(message_header_fields, buf, off, t)
+    end)
+    if not off then
+        return
+    end
+    local message_type = buf(header + {message_type}, 1):uint()
+    pinfo.cols.info = message_types[message_type]
+        or ("Unknown message type " .. message_type)
+
+    if message_type == {event_report} then
+        off = group(root, buf, off, "event_report", event_report)
+    elseif message_type == {ack_nak} then
+        off = group(root, buf, off, "ack_nak", function(t, buf, off)
+            return fixed(ack_nak_fields, buf, off, t)
+        end)
+    elseif off < buf:len() then
+        root:add(f.body, buf(off))
+        off = buf:len()
+    end
+
+    if off and off < buf:len() then
+        root:add(f.trailing, buf(off))
+        root:add_proto_expert_info(ef_trailing)
+    end
+end
+"#,
+        message_type = message_type,
+        event_report = u8::from(MessageType::EventReport),
+        ack_nak = u8::from(MessageType::AckNak),
+    );
+}
@@ -0,0 +1,233 @@
+//! Blocking UDP server over loopback.
+#![cfg(feature = "std")]
+
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use calamp_rs::command::{Command, CommandQueue, CommandState};
+use calamp_rs::message_header::{MessageHeader, MessageType, ServiceType};
+use calamp_rs::messages::ack_nak::{AckNak, AckNakMessage};
+use calamp_rs::options_header::{MobileID, OptionsHeader};
+use calamp_rs::server::blocking::UdpServer;
+use calamp_rs::server::Handler;
+use calamp_rs::{Error, Message};
+
+mod common;
+
+use common::{DATA, WAIT};
+
+#[derive(Debug)]
+enum Event {
+    Message(SocketAddr, Message),
+    Error(SocketAddr, Error),
+    Duplicate(Message),
+    AckNak(AckNakMessage),
+}
+
+struct Forward(Mutex<Sender<Event>>);
+
+impl Handler for Forward {
+    fn on_message(&self, src: SocketAddr, msg: Message) {
+        self.0
+            .lock()
+            .unwrap()
+            .send(Event::Message(src, msg))
+            .unwrap();
+    }
+
+    fn on_error(&self, src: SocketAddr, err: Error) {
+        self.0.lock().unwrap().send(Event::Error(src, err)).unwrap();
+    }
+
+    fn on_duplicate(&self, _src: SocketAddr, msg: Message) {
+        self.0.lock().unwrap().send(Event::Duplicate(msg)).unwrap();
+    }
+
+    fn on_ack_nak(&self, _src: SocketAddr, msg: AckNakMessage) {
+        self.0.lock().unwrap().send(Event::AckNak(msg)).unwrap();
+    }
+}
+
+#[test]
+fn test_blocking_server() {
+    let server = UdpServer::bind("127.0.0.1:0").unwrap().workers(2);
+    let addr = server.local_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+    let (tx, events) = mpsc::channel();
+    let running = thread::spawn(move || server.run(Forward(Mutex::new(tx))));
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.set_read_timeout(Some(WAIT)).unwrap();
+    client.send_to(&DATA[..20], addr).unwrap();
+    client.send_to(&DATA, addr).unwrap();
+
+    let mut buf = [0u8; 1500];
+    let (len, _) = client.recv_from(&mut buf).unwrap();
+    let (i, _) = OptionsHeader::parse(&buf[..len]).unwrap();
+    let (i, header) = MessageHeader::parse(i).unwrap();
+    let (_, ack) = AckNak::parse(i).unwrap();
+    assert_eq!(header.service_type, ServiceType::ResponseToAnAcknowledged);
+    assert_eq!(header.sequence_number.data(), 14982);
+    assert_eq!(ack, AckNak::ack(MessageType::EventReport));
+
+    let mut messages = 0;
+    let mut errors = 0;
+    for _ in 0..2 {
+        match events.recv_timeout(WAIT).unwrap() {
+            Event::Message(src, msg) => {
+                assert_eq!(src, client.local_addr().unwrap());
+                assert_eq!(msg.message_header.sequence_number.data(), 14982);
+                messages += 1;
+            }
+            Event::Error(src, Error::Incomplete { .. }) => {
+                assert_eq!(src, client.local_addr().unwrap());
+                errors += 1;
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    assert_eq!((messages, errors), (1, 1));
+
+    shutdown.shutdown();
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn test_blocking_server_flags_duplicates() {
+    let server = UdpServer::bind("127.0.0.1:0")
+        .unwrap()
+        .workers(1)
+        .dedup(Duration::from_secs(60));
+    let addr = server.local_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+    let (tx, events) = mpsc::channel();
+    let running = thread::spawn(move || server.run(Forward(Mutex::new(tx))));
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.set_read_timeout(Some(WAIT)).unwrap();
+    let mut buf = [0u8; 1500];
+    for _ in 0..2 {
+        client.send_to(&DATA, addr).unwrap();
+        // Both copies are acked.
+        client.recv_from(&mut buf).unwrap();
+    }
+
+    assert!(matches!(
+        events.recv_timeout(WAIT).unwrap(),
+        Event::Message(..)
+    ));
+    match events.recv_timeout(WAIT).unwrap() {
+        Event::Duplicate(msg) => {
+            assert_eq!(msg.message_header.sequence_number.data(), 14982)
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    shutdown.shutdown();
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn test_blocking_server_matches_ack_nak() {
+    let server = UdpServer::bind("127.0.0.1:0").unwrap().workers(1);
+    let addr = server.local_addr().unwrap();
+    let socket = server.try_clone_socket().unwrap();
+    let shutdown = server.shutdown_handle();
+    let (tx, events) = mpsc::channel();
+    let running = thread::spawn(move || server.run(Forward(Mutex::new(tx))));
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.set_read_timeout(Some(WAIT)).unwrap();
+    let mobile_id = MobileID::new(&[0x46, 0x34, 0x66, 0x32, 0x35]);
+    let mut queue = CommandQueue::new();
+    let id = queue.push(
+        mobile_id.clone(),
+        Command::new(MessageType::UnitRequest, &[0x01]),
+    );
+    let completion = queue.completion(id).unwrap();
+
+    // The LMU reports, gets its ACK and then the command.
+    client.send_to(&DATA, addr).unwrap();
+    let mut buf = [0u8; 1500];
+    client.recv_from(&mut buf).unwrap();
+    assert!(matches!(
+        events.recv_timeout(WAIT).unwrap(),
+        Event::Message(..)
+    ));
+    for out in queue.on_contact(&mobile_id, client.local_addr().unwrap()) {
+        socket.send_to(&out.bytes, out.addr).unwrap();
+    }
+
+    let (len, _) = client.recv_from(&mut buf).unwrap();
+    let (i, options_header) = OptionsHeader::parse(&buf[..len]).unwrap();
+    let (_, request) = MessageHeader::parse(i).unwrap();
+    let mut reply = Vec::new();
+    AckNak::ack(request.message_type).encode_reply(
+        options_header.as_ref(),
+        &request,
+        &mut reply,
+    );
+    client.send_to(&reply, addr).unwrap();
+
+    match events.recv_timeout(WAIT).unwrap() {
+        Event::AckNak(msg) => assert_eq!(queue.on_ack_nak(&msg), Some(id)),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(completion.wait(), CommandState::Acked);
+
+    shutdown.shutdown();
+    running.join().unwrap().unwrap();
+}
+
+#[cfg(feature = "pcap")]
+#[test]
+fn test_blocking_server_audit_trail() {
+    use calamp_rs::capture::{Capture, RotatingPcapWriter};
+    use std::time::SystemTime;
+    use std::{env, fs, process};
+
+    struct Audit(Mutex<RotatingPcapWriter>);
+
+    impl Handler for Audit {
+        fn on_message(&self, _src: SocketAddr, _msg: Message) {}
+
+        fn on_datagram(
+            &self, src: SocketAddr, dst: SocketAddr, time: SystemTime,
+            datagram: &[u8],
+        ) {
+            let mut audit = self.0.lock().unwrap();
+            audit.write_udp(time, src, dst, datagram).unwrap();
+            audit.flush().unwrap();
+        }
+    }
+
+    let dir = env::temp_dir().join(format!("calamp-audit-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let server = UdpServer::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+    let audit = Audit(Mutex::new(RotatingPcapWriter::new(&dir, "audit")));
+    let running = thread::spawn(move || server.run(audit));
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.set_read_timeout(Some(WAIT)).unwrap();
+    client.send_to(&[0x00, 0x01], addr).unwrap();
+    client.send_to(&DATA, addr).unwrap();
+    let mut buf = [0u8; 1500];
+    client.recv_from(&mut buf).unwrap();
+    shutdown.shutdown();
+    running.join().unwrap().unwrap();
+
+    let file = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+    let datagrams: Vec<_> =
+        Capture::open(&file).unwrap().map(|d| d.unwrap()).collect();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(datagrams.len(), 2);
+    assert_eq!(datagrams[0].payload, [0x00, 0x01]);
+    assert_eq!(datagrams[1].src, client.local_addr().unwrap());
+    assert_eq!(datagrams[1].dst, addr);
+    assert!(datagrams[1].message.is_ok());
+}
@@ -0,0 +1,327 @@
+//! The `calamp` command line tool.
+#![cfg(feature = "cli")]
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::UdpSocket;
+use std::process::{self, Command, Output, Stdio};
+use std::time::{Duration, UNIX_EPOCH};
+
+use calamp_rs::capture::PcapWriter;
+use calamp_rs::server::ack_reply;
+
+const DATA: &str = "\
+    83054634663235010101023a865ff13a545ff13a57f1e28578e422d64000\
+    0136f80000000b000006200000ff8d021e1e007b211000000031e0000010\
+    1a0000000000222a32000003f1000000000000000001c82d3f01c82d3f00\
+    000000000000000000000000000000000040010000000000000000";
+
+fn calamp(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_calamp"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// The JSON documents printed one after another.
+fn json_values(stdout: &[u8]) -> Vec<serde_json::Value> {
+    serde_json::Deserializer::from_slice(stdout)
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+#[test]
+fn test_decode_hex_lines() {
+    let input = format!("# two reports\n{}\n\n0x{}\n", DATA, DATA);
+    let out = calamp(&["decode", "--format", "json"], input.as_bytes());
+    assert!(out.status.success());
+
+    assert!(out.stdout.starts_with(b"{\n  \"schema_version\": 1,\n"));
+    let msgs = json_values(&out.stdout);
+    assert_eq!(msgs.len(), 2);
+    assert_eq!(msgs[0]["sequence_number"], 14982);
+}
+
+#[test]
+fn test_decode_closed_stdout() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_calamp"))
+        .arg("decode")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Like `| head`: the reader is gone before the first message.
+    drop(child.stdout.take());
+    let input = format!("{}\n", DATA).repeat(1000);
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    assert!(
+        out.stderr.is_empty(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+}
+
+#[test]
+fn test_decode_ndjson() {
+    let golden =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/messages.ndjson");
+    let out = calamp(&["decode", "--format", "ndjson"], DATA.as_bytes());
+    assert!(out.status.success());
+    let golden = fs::read_to_string(golden).unwrap();
+    let first = golden.lines().next().unwrap();
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        first.to_owned() + "\n"
+    );
+}
+
+#[test]
+fn test_decode_raw_and_base64() {
+    let hex = calamp(&["decode", "--format", "yaml"], DATA.as_bytes());
+    assert!(hex.status.success());
+
+    let b64 = "gwVGNGYyNQEBAQI6hl/xOlRf8TpX8eKFeOQi1kAAATb4AAAACwAABiAAAP+NAh4eAHshEAAAADHgAAAQGgAAAAAAIioyAAAD8QAAAAAAAAAAAcgtPwHILT8AAAAAAAAAAAAAAAAAAAAAAABAAQAAAAAAAAAA";
+    let out = calamp(&["decode", "-i", "base64", "-f", "yaml"], b64.as_bytes());
+    assert!(out.status.success());
+    assert_eq!(out.stdout, hex.stdout);
+
+    let bytes = decode_hex(DATA);
+    let raw = [&bytes[..], &bytes[..]].concat();
+    let out = calamp(&["decode", "-i", "raw", "-f", "yaml"], &raw);
+    assert!(out.status.success());
+    assert_eq!(out.stdout, [&hex.stdout[..], &hex.stdout[..]].concat());
+}
+
+#[test]
+fn test_decode_dissect() {
+    let input = format!("{}\n{}00ff\n", DATA, DATA);
+    let out = calamp(&["decode", "--dissect"], input.as_bytes());
+    assert!(out.status.success());
+
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(!stdout.contains('\x1b'));
+    assert!(stdout.contains("sequence_number: 14982"));
+    let last = stdout.trim_end().lines().last().unwrap();
+    assert!(last.starts_with("0075  00 ff "), "{}", last);
+    assert!(
+        last.ends_with("trailing: 2 bytes after the message"),
+        "{}",
+        last
+    );
+}
+
+#[test]
+fn test_decode_reports_errors() {
+    let input = format!("{}\n{}\nzz\n", DATA, &DATA[..40]);
+    let out = calamp(&["decode"], input.as_bytes());
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8(out.stdout)
+        .unwrap()
+        .contains("EventReport"));
+
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(
+        lines[0].starts_with("-:2: incomplete message"),
+        "{}",
+        lines[0]
+    );
+    assert!(
+        lines[1].starts_with("-:3: invalid Hex input"),
+        "{}",
+        lines[1]
+    );
+}
+
+#[test]
+fn test_listen() {
+    let save = env::temp_dir().join(format!("calamp-{}.pcap", process::id()));
+    let mut child = Command::new(env!("CARGO_BIN_EXE_calamp"))
+        .args(["listen", "--bind", "127.0.0.1:0", "--ack", "-n", "2"])
+        .args(["-f", "json", "-m", "4634663235", "-t", "event-report"])
+        .arg("--save")
+        .arg(&save)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let addr = line.trim().strip_prefix("listening on ").unwrap();
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut other = decode_hex(DATA);
+    other[6] = 0x36;
+    let mut buf = [0u8; 1500];
+    for datagram in [other, decode_hex(DATA)] {
+        client.send_to(&datagram, addr).unwrap();
+        // Acked even when filtered out.
+        client.recv_from(&mut buf).unwrap();
+    }
+
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    let received = json_values(&out.stdout);
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0]["src"], client.local_addr().unwrap().to_string());
+    assert_eq!(received[0]["message"]["mobile_id"], "4634663235");
+
+    // Two datagrams and their ACKs.
+    let pcap = fs::read(&save).unwrap();
+    fs::remove_file(&save).unwrap();
+    let udp = 20 + 8;
+    assert_eq!(pcap.len(), 24 + 4 * (16 + udp) + 2 * (117 + 19));
+}
+
+#[test]
+fn test_pcap() {
+    let path =
+        env::temp_dir().join(format!("calamp-{}-in.pcap", process::id()));
+    let mut pcap = PcapWriter::new(fs::File::create(&path).unwrap()).unwrap();
+    let lmu = "192.0.2.1:3500".parse().unwrap();
+    let server = "198.51.100.7:20500".parse().unwrap();
+    let time = UNIX_EPOCH + Duration::from_secs(1_609_644_628);
+    for datagram in [decode_hex(DATA), vec![0x83], decode_hex(DATA)] {
+        pcap.write_udp(time, lmu, server, &datagram).unwrap();
+    }
+    pcap.flush().unwrap();
+    drop(pcap);
+    let file = path.to_str().unwrap();
+
+    let out = calamp(&["pcap", file], &[]);
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(
+        stdout.contains(": 3 datagrams, 2 messages, 1 errors"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("from 2021-01-03T03:30:28Z"), "{}", stdout);
+    assert!(stdout.contains("event_report"), "{}", stdout);
+
+    let out = calamp(&["pcap", "--dump", "-f", "json", file], &[]);
+    fs::remove_file(&path).unwrap();
+    assert!(out.status.success());
+    let captured = json_values(&out.stdout);
+    assert_eq!(captured.len(), 2);
+    assert_eq!(captured[0]["dst"], "198.51.100.7:20500");
+    assert_eq!(captured[0]["time"], 1_609_644_628.0);
+    assert_eq!(String::from_utf8(out.stderr).unwrap().lines().count(), 1);
+}
+
+#[test]
+fn test_replay() {
+    let path =
+        env::temp_dir().join(format!("calamp-{}-replay.pcap", process::id()));
+    let mut pcap = PcapWriter::new(fs::File::create(&path).unwrap()).unwrap();
+    let lmu = "192.0.2.1:3500".parse().unwrap();
+    let server = "198.51.100.7:20500".parse().unwrap();
+    let time = UNIX_EPOCH + Duration::from_secs(1_609_644_628);
+    pcap.write_udp(time, lmu, server, &decode_hex(DATA))
+        .unwrap();
+    pcap.flush().unwrap();
+    drop(pcap);
+
+    let target = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = target.local_addr().unwrap().to_string();
+    let file = path.to_str().unwrap();
+    let child = Command::new(env!("CARGO_BIN_EXE_calamp"))
+        .args(["replay", file, "--to", &addr, "--speed", "max"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut buf = [0u8; 1500];
+    let (len, src) = target.recv_from(&mut buf).unwrap();
+    target
+        .send_to(&ack_reply(&buf[..len]).unwrap(), src)
+        .unwrap();
+
+    let out = child.wait_with_output().unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        "1 datagrams from 1 LMUs\n\
+         1 acks expected: 1 acked, 0 nakd, 0 missing\n"
+    );
+}
+
+#[test]
+fn test_simulate() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    server
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    let addr = server.local_addr().unwrap().to_string();
+    let child = Command::new(env!("CARGO_BIN_EXE_calamp"))
+        .args(["simulate", "--to", &addr, "-n", "3", "--interval", "100ms"])
+        .args(["--lat", "-23.68", "--ignition", "--duration", "500ms"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut buf = [0u8; 1500];
+    let mut ports = Vec::new();
+    while let Ok((len, src)) = server.recv_from(&mut buf) {
+        let ack = ack_reply(&buf[..len]).unwrap();
+        server.send_to(&ack, src).unwrap();
+        if !ports.contains(&src.port()) {
+            ports.push(src.port());
+        }
+    }
+    assert_eq!(ports.len(), 3);
+
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("unacked          0"), "{}", stdout);
+}
+
+fn decode_hex(s: &str) -> Vec<u8> {
+    let digits: Vec<u8> = s.bytes().filter(|b| *b != b' ').collect();
+    digits
+        .chunks(2)
+        .map(|pair| {
+            u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn test_wireshark() {
+    let golden =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/lmdirect.lua");
+    let out = calamp(&["wireshark"], b"");
+    assert!(out.status.success());
+    assert_eq!(out.stdout, fs::read(golden).unwrap());
+
+    let path =
+        env::temp_dir().join(format!("calamp-cli-{}.lua", process::id()));
+    let out = calamp(
+        &["wireshark", "-p", "20510", "-o", path.to_str().unwrap()],
+        b"",
+    );
+    assert!(out.status.success());
+    let lua = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(lua.ends_with("udp_port:add(20510, lmdirect)\n"));
+}
@@ -0,0 +1,67 @@
+//! Fixtures shared by the integration tests.
+
+// Each test crate only uses some of them.
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+#[cfg(feature = "tokio")]
+#[allow(unused_imports)]
+pub use self::server::{spawn_server, TestServer};
+
+/// How long a test waits for anything over loopback.
+pub const WAIT: Duration = Duration::from_secs(5);
+
+/// Acknowledged Event Report from the LMU with ESN 4634663235, sequence
+/// number 14982, with 16 accumulators.
+pub const DATA: [u8; 117] = [
+    0x83, 0x05, 0x46, 0x34, 0x66, 0x32, 0x35, 0x01, 0x01, 0x01, 0x02, 0x3a,
+    0x86, 0x5f, 0xf1, 0x3a, 0x54, 0x5f, 0xf1, 0x3a, 0x57, 0xf1, 0xe2, 0x85,
+    0x78, 0xe4, 0x22, 0xd6, 0x40, 0x00, 0x01, 0x36, 0xf8, 0x00, 0x00, 0x00,
+    0x0b, 0x00, 0x00, 0x06, 0x20, 0x00, 0x00, 0xff, 0x8d, 0x02, 0x1e, 0x1e,
+    0x00, 0x7b, 0x21, 0x10, 0x00, 0x00, 0x00, 0x31, 0xe0, 0x00, 0x00, 0x10,
+    0x1a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x22, 0x2a, 0x32, 0x00, 0x00, 0x03,
+    0xf1, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xc8, 0x2d,
+    0x3f, 0x01, 0xc8, 0x2d, 0x3f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40,
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+#[cfg(feature = "tokio")]
+mod server {
+    use std::io;
+    use std::net::SocketAddr;
+
+    use calamp_rs::server::{Handler, UdpServer};
+    use tokio::sync::oneshot;
+    use tokio::task::JoinHandle;
+    use tokio::time::timeout;
+
+    use super::WAIT;
+
+    /// A [`UdpServer`] on loopback, running until [`TestServer::stop`].
+    pub struct TestServer {
+        pub addr: SocketAddr,
+        stop: oneshot::Sender<()>,
+        task: JoinHandle<io::Result<()>>,
+    }
+
+    /// Bind a server on an ephemeral loopback port and serve `handler`.
+    pub async fn spawn_server<H: Handler>(handler: H) -> TestServer {
+        let server = UdpServer::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let task = tokio::spawn(server.run_until(handler, async {
+            let _ = stopped.await;
+        }));
+        TestServer { addr, stop, task }
+    }
+
+    impl TestServer {
+        /// Stop the server and wait for it to return without an error.
+        pub async fn stop(self) {
+            self.stop.send(()).unwrap();
+            timeout(WAIT, self.task).await.unwrap().unwrap().unwrap();
+        }
+    }
+}
@@ -0,0 +1,423 @@
+-- CalAmp LMDirect dissector for Wireshark.
+--
+-- Generated by `calamp wireshark` from the field layouts of calamp-rs, do
+-- not edit. Copy to the personal Lua plugins folder and use "Decode As..."
+-- for ports other than the ones registered at the end.
+
+local lmdirect = Proto("lmdirect", "CalAmp LMDirect")
+
+local ef_truncated = ProtoExpert.new("lmdirect.truncated",
+    "Message truncated", expert.group.MALFORMED, expert.severity.ERROR)
+local ef_trailing = ProtoExpert.new("lmdirect.trailing_bytes",
+    "Bytes after the message", expert.group.MALFORMED, expert.severity.WARN)
+lmdirect.experts = { ef_truncated, ef_trailing }
+
+-- Value strings
+
+local service_types = {
+    [0] = "unacknowledged",
+    [1] = "acknowledged",
+    [2] = "response_to_an_acknowledged",
+}
+local message_types = {
+    [0] = "null",
+    [1] = "ack_nak",
+    [2] = "event_report",
+    [3] = "id_report",
+    [4] = "user_data",
+    [5] = "application_data",
+    [6] = "configuration_parameter",
+    [7] = "unit_request",
+    [8] = "locate_report",
+    [9] = "user_data_with_accumulators",
+    [10] = "mini_event_report",
+    [11] = "mini_user_data",
+    [12] = "mini_application",
+    [13] = "device_version",
+    [14] = "application_message_with_accumulators",
+}
+local mobile_id_types = {
+    [0] = "off",
+    [1] = "esn",
+    [2] = "equipment",
+    [3] = "subscriber",
+    [4] = "defined",
+    [5] = "phone_number",
+    [6] = "ip_address",
+    [7] = "cdma",
+}
+local network_technologies = {
+    [0] = "cdma_gsm",
+    [1] = "umts",
+    [2] = "lte",
+    [3] = "reserved",
+}
+local nak_reasons = {
+    [0] = "ACK",
+    [1] = "NAK, no reason",
+    [2] = "NAK, not supported message type",
+    [3] = "NAK, not supported operation",
+    [4] = "NAK, unable to pass to serial port",
+    [5] = "NAK, authentication failure",
+    [6] = "NAK, mobile id lookup failure",
+    [7] = "NAK, non zero sequence number same as last received",
+}
+local clear_flag = {
+    [0] = "True",
+    [1] = "False",
+}
+
+-- Fields
+
+local f = lmdirect.fields
+f.options = ProtoField.uint8("lmdirect.options", "options", base.HEX)
+f.options_mobile_id = ProtoField.bool("lmdirect.options.mobile_id", "mobile_id", 8, nil, 0x01)
+f.options_mobile_id_type = ProtoField.bool("lmdirect.options.mobile_id_type", "mobile_id_type", 8, nil, 0x02)
+f.options_authentication_world = ProtoField.bool("lmdirect.options.authentication_world", "authentication_world", 8, nil, 0x04)
+f.options_routing = ProtoField.bool("lmdirect.options.routing", "routing", 8, nil, 0x08)
+f.options_forwarding = ProtoField.bool("lmdirect.options.forwarding", "forwarding", 8, nil, 0x10)
+f.options_response_redirection = ProtoField.bool("lmdirect.options.response_redirection", "response_redirection", 8, nil, 0x20)
+f.options_options_extension = ProtoField.bool("lmdirect.options.options_extension", "options_extension", 8, nil, 0x40)
+f.options_always_set = ProtoField.bool("lmdirect.options.always_set", "always_set", 8, nil, 0x80)
+f.mobile_id_length = ProtoField.uint8("lmdirect.mobile_id.length", "length", base.DEC)
+f.mobile_id_value = ProtoField.bytes("lmdirect.mobile_id.value", "value")
+f.mobile_id_type_length = ProtoField.uint8("lmdirect.mobile_id_type.length", "length", base.DEC)
+f.mobile_id_type_value = ProtoField.uint8("lmdirect.mobile_id_type.value", "value", base.DEC, mobile_id_types)
+f.authentication_world_length = ProtoField.uint8("lmdirect.authentication_world.length", "length", base.DEC)
+f.authentication_world_value = ProtoField.bytes("lmdirect.authentication_world.value", "value")
+f.routing_length = ProtoField.uint8("lmdirect.routing.length", "length", base.DEC)
+f.routing_value = ProtoField.bytes("lmdirect.routing.value", "value")
+f.forwarding_length = ProtoField.uint8("lmdirect.forwarding.length", "length", base.DEC)
+f.forwarding_value = ProtoField.bytes("lmdirect.forwarding.value", "value")
+f.response_redirection_length = ProtoField.uint8("lmdirect.response_redirection.length", "length", base.DEC)
+f.response_redirection_value = ProtoField.bytes("lmdirect.response_redirection.value", "value")
+f.options_extension_length = ProtoField.uint8("lmdirect.options_extension.length", "length", base.DEC)
+f.options_extension_value = ProtoField.bytes("lmdirect.options_extension.value", "value")
+f.message_header_service_type = ProtoField.uint8("lmdirect.message_header.service_type", "service_type", base.DEC, service_types)
+f.message_header_message_type = ProtoField.uint8("lmdirect.message_header.message_type", "message_type", base.DEC, message_types)
+f.message_header_sequence_number = ProtoField.uint16("lmdirect.message_header.sequence_number", "sequence_number", base.DEC)
+f.event_report_update_time = ProtoField.absolute_time("lmdirect.event_report.update_time", "update_time", base.UTC)
+f.event_report_time_of_fix = ProtoField.absolute_time("lmdirect.event_report.time_of_fix", "time_of_fix", base.UTC)
+f.event_report_latitude = ProtoField.int32("lmdirect.event_report.latitude", "latitude", base.DEC)
+f.event_report_longitude = ProtoField.int32("lmdirect.event_report.longitude", "longitude", base.DEC)
+f.event_report_altitude = ProtoField.int32("lmdirect.event_report.altitude", "altitude", base.DEC)
+f.event_report_speed = ProtoField.uint32("lmdirect.event_report.speed", "speed", base.DEC)
+f.event_report_heading = ProtoField.uint16("lmdirect.event_report.heading", "heading", base.DEC)
+f.event_report_satellites = ProtoField.uint8("lmdirect.event_report.satellites", "satellites", base.DEC)
+f.event_report_fix_status = ProtoField.uint8("lmdirect.event_report.fix_status", "fix_status", base.HEX)
+f.event_report_fix_status_unused = ProtoField.bool("lmdirect.event_report.fix_status.unused", "unused", 8, nil, 0x01)
+f.event_report_fix_status_predicted = ProtoField.bool("lmdirect.event_report.fix_status.predicted", "predicted", 8, nil, 0x02)
+f.event_report_fix_status_diff_corrected = ProtoField.bool("lmdirect.event_report.fix_status.diff_corrected", "diff_corrected", 8, nil, 0x04)
+f.event_report_fix_status_last_know = ProtoField.bool("lmdirect.event_report.fix_status.last_know", "last_know", 8, nil, 0x08)
+f.event_report_fix_status_invalid_fix = ProtoField.bool("lmdirect.event_report.fix_status.invalid_fix", "invalid_fix", 8, nil, 0x10)
+f.event_report_fix_status_twod_fix = ProtoField.bool("lmdirect.event_report.fix_status.twod_fix", "twod_fix", 8, nil, 0x20)
+f.event_report_fix_status_historic = ProtoField.bool("lmdirect.event_report.fix_status.historic", "historic", 8, nil, 0x40)
+f.event_report_fix_status_invalid_time = ProtoField.bool("lmdirect.event_report.fix_status.invalid_time", "invalid_time", 8, nil, 0x80)
+f.event_report_carrier = ProtoField.uint16("lmdirect.event_report.carrier", "carrier", base.DEC)
+f.event_report_rssi = ProtoField.int16("lmdirect.event_report.rssi", "rssi", base.DEC)
+f.event_report_comm_state = ProtoField.uint8("lmdirect.event_report.comm_state", "comm_state", base.HEX)
+f.event_report_comm_state_available = ProtoField.bool("lmdirect.event_report.comm_state.available", "available", 8, nil, 0x01)
+f.event_report_comm_state_network_service = ProtoField.bool("lmdirect.event_report.comm_state.network_service", "network_service", 8, nil, 0x02)
+f.event_report_comm_state_data_service = ProtoField.bool("lmdirect.event_report.comm_state.data_service", "data_service", 8, nil, 0x04)
+f.event_report_comm_state_connected = ProtoField.bool("lmdirect.event_report.comm_state.connected", "connected", 8, nil, 0x08)
+f.event_report_comm_state_voice_call_active = ProtoField.bool("lmdirect.event_report.comm_state.voice_call_active", "voice_call_active", 8, nil, 0x10)
+f.event_report_comm_state_roaming = ProtoField.bool("lmdirect.event_report.comm_state.roaming", "roaming", 8, nil, 0x20)
+f.event_report_comm_state_network_technology = ProtoField.uint8("lmdirect.event_report.comm_state.network_technology", "network_technology", base.DEC, network_technologies, 0xc0)
+f.event_report_hdop = ProtoField.uint8("lmdirect.event_report.hdop", "hdop", base.DEC)
+f.event_report_inputs = ProtoField.uint8("lmdirect.event_report.inputs", "inputs", base.HEX)
+f.event_report_inputs_ignition = ProtoField.bool("lmdirect.event_report.inputs.ignition", "ignition", 8, nil, 0x01)
+f.event_report_inputs_input_1 = ProtoField.bool("lmdirect.event_report.inputs.input_1", "input_1", 8, nil, 0x02)
+f.event_report_inputs_input_2 = ProtoField.bool("lmdirect.event_report.inputs.input_2", "input_2", 8, nil, 0x04)
+f.event_report_inputs_input_3 = ProtoField.bool("lmdirect.event_report.inputs.input_3", "input_3", 8, nil, 0x08)
+f.event_report_inputs_input_4 = ProtoField.bool("lmdirect.event_report.inputs.input_4", "input_4", 8, nil, 0x10)
+f.event_report_inputs_input_5 = ProtoField.bool("lmdirect.event_report.inputs.input_5", "input_5", 8, nil, 0x20)
+f.event_report_inputs_input_6 = ProtoField.bool("lmdirect.event_report.inputs.input_6", "input_6", 8, nil, 0x40)
+f.event_report_inputs_input_7 = ProtoField.bool("lmdirect.event_report.inputs.input_7", "input_7", 8, nil, 0x80)
+f.event_report_unit_status = ProtoField.uint8("lmdirect.event_report.unit_status", "unit_status", base.HEX)
+f.event_report_unit_status_ota_update = ProtoField.uint8("lmdirect.event_report.unit_status.ota_update", "ota_update", base.DEC, clear_flag, 0x01)
+f.event_report_unit_status_gps_antenna = ProtoField.uint8("lmdirect.event_report.unit_status.gps_antenna", "gps_antenna", base.DEC, clear_flag, 0x02)
+f.event_report_unit_status_gps_self_test = ProtoField.uint8("lmdirect.event_report.unit_status.gps_self_test", "gps_self_test", base.DEC, clear_flag, 0x04)
+f.event_report_unit_status_gps_tracking = ProtoField.uint8("lmdirect.event_report.unit_status.gps_tracking", "gps_tracking", base.DEC, clear_flag, 0x08)
+f.event_report_unit_status_reserved_1 = ProtoField.uint8("lmdirect.event_report.unit_status.reserved_1", "reserved_1", base.DEC, clear_flag, 0x10)
+f.event_report_unit_status_reserved_2 = ProtoField.uint8("lmdirect.event_report.unit_status.reserved_2", "reserved_2", base.DEC, clear_flag, 0x20)
+f.event_report_unit_status_reserved_3 = ProtoField.uint8("lmdirect.event_report.unit_status.reserved_3", "reserved_3", base.DEC, clear_flag, 0x40)
+f.event_report_unit_status_unused = ProtoField.uint8("lmdirect.event_report.unit_status.unused", "unused", base.DEC, clear_flag, 0x80)
+f.event_report_event_index = ProtoField.uint8("lmdirect.event_report.event_index", "event_index", base.DEC)
+f.event_report_event_code = ProtoField.uint8("lmdirect.event_report.event_code", "event_code", base.DEC)
+f.event_report_accums = ProtoField.uint8("lmdirect.event_report.accums", "accums", base.HEX)
+f.event_report_accums_count = ProtoField.uint8("lmdirect.event_report.accums.count", "count", base.DEC, nil, 0x3f)
+f.event_report_accums_format = ProtoField.uint8("lmdirect.event_report.accums.format", "format", base.DEC, nil, 0xc0)
+f.event_report_append = ProtoField.uint8("lmdirect.event_report.append", "append", base.HEX)
+f.event_report_accumulator = ProtoField.uint32("lmdirect.event_report.accumulator", "accumulator", base.DEC)
+f.event_report_block_length = ProtoField.uint8("lmdirect.event_report.block.length", "length", base.DEC)
+f.event_report_block_data = ProtoField.bytes("lmdirect.event_report.block.data", "data")
+f.ack_nak_message_type = ProtoField.uint8("lmdirect.ack_nak.message_type", "message_type", base.DEC, message_types)
+f.ack_nak_ack = ProtoField.uint8("lmdirect.ack_nak.ack", "ack", base.DEC, nak_reasons)
+f.ack_nak_spare = ProtoField.uint8("lmdirect.ack_nak.spare", "spare", base.DEC)
+f.ack_nak_app_version = ProtoField.bytes("lmdirect.ack_nak.app_version", "app_version")
+f.body = ProtoField.bytes("lmdirect.body", "body")
+f.trailing = ProtoField.bytes("lmdirect.trailing", "trailing")
+
+-- Layouts
+
+local options_fields = {
+    { len = 1, field = f.options, bits = {
+        f.options_mobile_id,
+        f.options_mobile_id_type,
+        f.options_authentication_world,
+        f.options_routing,
+        f.options_forwarding,
+        f.options_response_redirection,
+        f.options_options_extension,
+        f.options_always_set,
+    } },
+}
+local message_header_fields = {
+    { len = 1, field = f.message_header_service_type },
+    { len = 1, field = f.message_header_message_type },
+    { len = 2, field = f.message_header_sequence_number },
+}
+local event_report_fields = {
+    { len = 4, field = f.event_report_update_time, time = true },
+    { len = 4, field = f.event_report_time_of_fix, time = true },
+    { len = 4, field = f.event_report_latitude, signed = true, factor = 1e-7, label = "latitude: %.7f°" },
+    { len = 4, field = f.event_report_longitude, signed = true, factor = 1e-7, label = "longitude: %.7f°" },
+    { len = 4, field = f.event_report_altitude, signed = true, factor = 0.01, label = "altitude: %.2f m" },
+    { len = 4, field = f.event_report_speed, signed = false, factor = 0.036, label = "speed: %.2f km/h" },
+    { len = 2, field = f.event_report_heading, signed = false, factor = 1.0, label = "heading: %.0f°" },
+    { len = 1, field = f.event_report_satellites },
+    { len = 1, field = f.event_report_fix_status, bits = {
+        f.event_report_fix_status_unused,
+        f.event_report_fix_status_predicted,
+        f.event_report_fix_status_diff_corrected,
+        f.event_report_fix_status_last_know,
+        f.event_report_fix_status_invalid_fix,
+        f.event_report_fix_status_twod_fix,
+        f.event_report_fix_status_historic,
+        f.event_report_fix_status_invalid_time,
+    } },
+    { len = 2, field = f.event_report_carrier },
+    { len = 2, field = f.event_report_rssi, signed = true, factor = 1.0, label = "rssi: %.0f dBm" },
+    { len = 1, field = f.event_report_comm_state, bits = {
+        f.event_report_comm_state_available,
+        f.event_report_comm_state_network_service,
+        f.event_report_comm_state_data_service,
+        f.event_report_comm_state_connected,
+        f.event_report_comm_state_voice_call_active,
+        f.event_report_comm_state_roaming,
+        f.event_report_comm_state_network_technology,
+    } },
+    { len = 1, field = f.event_report_hdop, signed = false, factor = 0.1, label = "hdop: %.1f" },
+    { len = 1, field = f.event_report_inputs, bits = {
+        f.event_report_inputs_ignition,
+        f.event_report_inputs_input_1,
+        f.event_report_inputs_input_2,
+        f.event_report_inputs_input_3,
+        f.event_report_inputs_input_4,
+        f.event_report_inputs_input_5,
+        f.event_report_inputs_input_6,
+        f.event_report_inputs_input_7,
+    } },
+    { len = 1, field = f.event_report_unit_status, bits = {
+        f.event_report_unit_status_ota_update,
+        f.event_report_unit_status_gps_antenna,
+        f.event_report_unit_status_gps_self_test,
+        f.event_report_unit_status_gps_tracking,
+        f.event_report_unit_status_reserved_1,
+        f.event_report_unit_status_reserved_2,
+        f.event_report_unit_status_reserved_3,
+        f.event_report_unit_status_unused,
+    } },
+    { len = 1, field = f.event_report_event_index },
+    { len = 1, field = f.event_report_event_code },
+    { len = 1, field = f.event_report_accums, bits = {
+        f.event_report_accums_count,
+        f.event_report_accums_format,
+    } },
+    { len = 1, field = f.event_report_append },
+}
+local accumulator_fields = {
+    { len = 4, field = f.event_report_accumulator },
+}
+local ack_nak_fields = {
+    { len = 1, field = f.ack_nak_message_type },
+    { len = 1, field = f.ack_nak_ack },
+    { len = 1, field = f.ack_nak_spare },
+    { len = 3, field = f.ack_nak_app_version },
+}
+
+-- Dissection
+
+-- Whether `bit` of `value` is set.
+local function has(value, bit)
+    return math.floor(value / 2 ^ bit) % 2 == 1
+end
+
+-- Add the fields of `layout` to `tree` from `off` on, returning the offset
+-- after them, or nil when the message ends first.
+local function fixed(layout, buf, off, tree)
+    for _, field in ipairs(layout) do
+        if off + field.len > buf:len() then
+            tree:add_proto_expert_info(ef_truncated)
+            return nil
+        end
+        local range = buf(off, field.len)
+        local item
+        if field.time then
+            item = tree:add(field.field, range, NSTime.new(range:uint(), 0))
+        elseif field.factor then
+            local value = field.signed and range:int() or range:uint()
+            item = tree:add(field.field, range, value,
+                string.format(field.label, value * field.factor))
+        else
+            item = tree:add(field.field, range)
+        end
+        for _, bit in ipairs(field.bits or {}) do
+            item:add(bit, range)
+        end
+        off = off + field.len
+    end
+    return off
+end
+
+-- A length byte then as many bytes of `value`.
+local function sized(tree, buf, off, length, value)
+    off = fixed({ { len = 1, field = length } }, buf, off, tree)
+    if not off then
+        return nil
+    end
+    local len = buf(off - 1, 1):uint()
+    if len == 0 then
+        return off
+    end
+    return fixed({ { len = len, field = value } }, buf, off, tree)
+end
+
+-- The fields added by `dissect` in a subtree named `name`.
+local function group(tree, buf, off, name, dissect)
+    if off >= buf:len() then
+        tree:add_proto_expert_info(ef_truncated)
+        return nil
+    end
+    local subtree = tree:add(lmdirect, buf(off, 1), name)
+    local after = dissect(subtree, buf, off)
+    subtree:set_len((after or buf:len()) - off)
+    return after
+end
+
+local function options_header(tree, buf, off)
+    local options = buf(off, 1):uint()
+    off = fixed(options_fields, buf, off, tree)
+    if off and has(options, 0) then
+        off = group(tree, buf, off, "mobile_id", function(t, buf, off)
+            return sized(t, buf, off, f.mobile_id_length, f.mobile_id_value)
+        end)
+    end
+    if off and has(options, 1) then
+        off = group(tree, buf, off, "mobile_id_type", function(t, buf, off)
+            return sized(t, buf, off, f.mobile_id_type_length, f.mobile_id_type_value)
+        end)
+    end
+    if off and has(options, 2) then
+        off = group(tree, buf, off, "authentication_world", function(t, buf, off)
+            return sized(t, buf, off, f.authentication_world_length, f.authentication_world_value)
+        end)
+    end
+    if off and has(options, 3) then
+        off = group(tree, buf, off, "routing", function(t, buf, off)
+            return sized(t, buf, off, f.routing_length, f.routing_value)
+        end)
+    end
+    if off and has(options, 4) then
+        off = group(tree, buf, off, "forwarding", function(t, buf, off)
+            return sized(t, buf, off, f.forwarding_length, f.forwarding_value)
+        end)
+    end
+    if off and has(options, 5) then
+        off = group(tree, buf, off, "response_redirection", function(t, buf, off)
+            return sized(t, buf, off, f.response_redirection_length, f.response_redirection_value)
+        end)
+    end
+    if off and has(options, 6) then
+        off = group(tree, buf, off, "options_extension", function(t, buf, off)
+            return sized(t, buf, off, f.options_extension_length, f.options_extension_value)
+        end)
+    end
+    return off
+end
+
+local function event_report(tree, buf, off)
+    local start = off
+    off = fixed(event_report_fields, buf, off, tree)
+    if not off then
+        return nil
+    end
+    local count = buf(start + 38, 1):uint() % 64
+    local append = buf(start + 39, 1):uint()
+    if count > 0 then
+        off = group(tree, buf, off, "accumulators", function(t, buf, off)
+            for _ = 1, count do
+                off = fixed(accumulator_fields, buf, off, t)
+                if not off then
+                    return nil
+                end
+            end
+            return off
+        end)
+    end
+    if off and append ~= 0 then
+        off = group(tree, buf, off, "appended", function(t, buf, off)
+            for bit = 0, 7 do
+                if off and has(append, bit) then
+                    off = group(t, buf, off, "block", function(t, buf, off)
+                        return sized(t, buf, off, f.event_report_block_length, f.event_report_block_data)
+                    end)
+                end
+            end
+            return off
+        end)
+    end
+    return off
+end
+
+function lmdirect.dissector(buf, pinfo, tree)
+    pinfo.cols.protocol = "LMDirect"
+    local root = tree:add(lmdirect, buf())
+    local off = 0
+    if buf:len() > 0 and has(buf(0, 1):uint(), 7) then
+        off = group(root, buf, off, "options_header", options_header)
+        if not off then
+            return
+        end
+    end
+
+    local header = off
+    off = group(root, buf, off, "message_header", function(t, buf, off)
+        return fixed(message_header_fields, buf, off, t)
+    end)
+    if not off then
+        return
+    end
+    local message_type = buf(header + 1, 1):uint()
+    pinfo.cols.info = message_types[message_type]
+        or ("Unknown message type " .. message_type)
+
+    if message_type == 2 then
+        off = group(root, buf, off, "event_report", event_report)
+    elseif message_type == 1 then
+        off = group(root, buf, off, "ack_nak", function(t, buf, off)
+            return fixed(ack_nak_fields, buf, off, t)
+        end)
+    elseif off < buf:len() then
+        root:add(f.body, buf(off))
+        off = buf:len()
+    end
+
+    if off and off < buf:len() then
+        root:add(f.trailing, buf(off))
+        root:add_proto_expert_info(ef_trailing)
+    end
+end
+
+local udp_port = DissectorTable.get("udp.port")
+udp_port:add(20500, lmdirect)
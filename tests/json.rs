@@ -0,0 +1,76 @@
+//! The stable JSON schema against its checked in snapshot.
+//!
+//! The snapshot locks the format down: a change to it must come with a
+//! new `SCHEMA_VERSION` unless it only adds fields. Review the new lines
+//! and run the tests with `CALAMP_BLESS=1` to update the copy.
+#![cfg(feature = "json")]
+
+use std::env;
+use std::fs;
+
+use calamp_rs::json::SCHEMA_VERSION;
+use calamp_rs::Message;
+
+mod common;
+
+use common::DATA;
+
+const GOLDEN: &str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/messages.ndjson");
+
+/// A report as sent, without options header, and without valid times or
+/// heading.
+fn messages() -> Vec<Message> {
+    let mut invalid = DATA;
+    invalid[13..21].fill(0);
+    invalid[37..39].copy_from_slice(&400u16.to_be_bytes());
+    [&DATA[..], &DATA[9..], &invalid[..]]
+        .iter()
+        .map(|data| Message::parse(data).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_json_lines_golden() {
+    let lines: String = messages()
+        .iter()
+        .map(|msg| msg.to_json_line() + "\n")
+        .collect();
+    if env::var_os("CALAMP_BLESS").is_some() {
+        fs::write(GOLDEN, &lines).unwrap();
+    }
+    let golden = fs::read_to_string(GOLDEN).unwrap();
+
+    for (n, (new, old)) in lines.lines().zip(golden.lines()).enumerate() {
+        assert_eq!(new, old, "line {}", n + 1);
+    }
+    assert_eq!(lines, golden);
+}
+
+#[test]
+fn test_json_line_fields() {
+    let messages = messages();
+    let line = messages[0].to_json_line();
+    assert!(!line.contains('\n'));
+
+    let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(json["schema_version"], SCHEMA_VERSION);
+    assert_eq!(json["message_type"], "event_report");
+    assert_eq!(json["service_type"], "acknowledged");
+    assert_eq!(json["mobile_id"], "4634663235");
+    assert_eq!(json["mobile_id_type"], "esn");
+    assert_eq!(json["update_time"], "2021-01-03T03:30:28Z");
+    assert_eq!(json["position"]["latitude"], -23.6812936);
+    assert_eq!(json["position"]["longitude"], -46.7478976);
+    assert_eq!(json["fix_status"]["twod_fix"], true);
+    assert_eq!(json["comm_state"]["network_technology"], "cdma_gsm");
+
+    let json: serde_json::Value =
+        serde_json::from_str(&messages[1].to_json_line()).unwrap();
+    assert!(json["mobile_id"].is_null());
+
+    let json: serde_json::Value =
+        serde_json::from_str(&messages[2].to_json_line()).unwrap();
+    assert!(json["update_time"].is_null());
+    assert!(json["position"]["heading"].is_null());
+}
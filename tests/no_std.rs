@@ -0,0 +1,65 @@
+//! Build the library without the standard library.
+//!
+//! The crate is `#![no_std]` when the `std` feature is disabled, so building
+//! it with `--no-default-features` for the host already rejects any use of
+//! `std`. Set `CALAMP_NO_STD_TARGET` (e.g. `thumbv7em-none-eabihf`) to build
+//! for a real bare metal target, as the `no_std` CI job does. The test fails
+//! when that target is not installed rather than falling back to the host.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+fn assert_target_installed(target: &str) {
+    let output = Command::new("rustc")
+        .arg("--print")
+        .arg("sysroot")
+        .output()
+        .expect("failed to run rustc");
+    let sysroot = String::from_utf8_lossy(&output.stdout);
+    let rustlib = Path::new(sysroot.trim()).join("lib/rustlib").join(target);
+    assert!(
+        rustlib.is_dir(),
+        "CALAMP_NO_STD_TARGET is {} but its standard library is not \
+         installed, run `rustup target add {}`",
+        target,
+        target
+    );
+}
+
+fn build_no_std(features: &str) {
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let target_dir = Path::new(manifest_dir).join("target").join("no_std");
+
+    let mut cmd = Command::new(env!("CARGO"));
+    cmd.current_dir(manifest_dir)
+        .arg("build")
+        .arg("--lib")
+        .arg("--no-default-features")
+        .arg("--features")
+        .arg(features)
+        .arg("--target-dir")
+        .arg(&target_dir);
+    if let Ok(target) = env::var("CALAMP_NO_STD_TARGET") {
+        assert_target_installed(&target);
+        cmd.arg("--target").arg(target);
+    }
+
+    let output = cmd.output().expect("failed to run cargo");
+    assert!(
+        output.status.success(),
+        "no_std build with features [{}] failed:\n{}",
+        features,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_build_no_std() {
+    build_no_std("");
+}
+
+#[test]
+fn test_build_no_std_serde() {
+    build_no_std("serde");
+}
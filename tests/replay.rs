@@ -0,0 +1,117 @@
+//! Replaying recorded datagrams to servers over loopback.
+#![cfg(feature = "replay")]
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, UNIX_EPOCH};
+
+use calamp_rs::capture::Datagram;
+use calamp_rs::replay::Replay;
+use calamp_rs::Message;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time::{timeout, Instant};
+
+mod common;
+
+use common::{spawn_server, DATA, WAIT};
+
+fn datagram(secs: u64, src: &str, dst: &str, payload: &[u8]) -> Datagram {
+    Datagram {
+        time: UNIX_EPOCH + Duration::from_secs(1_609_644_628 + secs),
+        src: src.parse().unwrap(),
+        dst: dst.parse().unwrap(),
+        payload: payload.to_vec(),
+        message: Message::parse(payload),
+    }
+}
+
+/// Two reports of one LMU and one of another, two seconds long, with the
+/// ACK the server sent.
+fn capture() -> Vec<io::Result<Datagram>> {
+    let mut other = DATA;
+    other[6] = 0x36;
+    let ack = [0x83, 0x05, 0x46, 0x34, 0x66, 0x32, 0x35, 0x01, 0x01, 0x02];
+    vec![
+        Ok(datagram(0, "192.0.2.1:3500", "198.51.100.7:20500", &DATA)),
+        Ok(datagram(0, "198.51.100.7:20500", "192.0.2.1:3500", &ack)),
+        Ok(datagram(1, "192.0.2.2:3500", "198.51.100.7:20500", &other)),
+        Ok(datagram(2, "192.0.2.1:3500", "198.51.100.7:20500", &DATA)),
+    ]
+}
+
+#[tokio::test]
+async fn test_replay_is_timed_and_acked() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let server = spawn_server(move |src: SocketAddr, msg: Message| {
+        tx.send((src, Instant::now(), msg)).unwrap()
+    })
+    .await;
+
+    let started = Instant::now();
+    let stats = Replay::new(server.addr)
+        .speed(10.0)
+        .to_port(20500)
+        .run(capture())
+        .await
+        .unwrap();
+    server.stop().await;
+
+    assert_eq!(stats.datagrams, 3);
+    assert_eq!(stats.sources, 2);
+    assert_eq!(stats.expected, 3);
+    assert_eq!(stats.acked, 3);
+    assert_eq!(stats.missing, 0);
+    assert_eq!(stats.unexpected, 0);
+
+    let received: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+    assert_eq!(received.len(), 3);
+    // Same LMU, same source port.
+    assert_eq!(received[0].0, received[2].0);
+    assert_ne!(received[0].0, received[1].0);
+    let span = received[2].1 - started;
+    assert!(span >= Duration::from_millis(200), "{:?}", span);
+    assert!(span < Duration::from_secs(2), "{:?}", span);
+}
+
+#[tokio::test]
+async fn test_replay_rewrites_and_counts_missing_acks() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let replay = Replay::new(server.local_addr().unwrap())
+        .speed(f64::INFINITY)
+        .to_port(20500)
+        .rewrite_mobile_ids(4_100_000_000)
+        .renumber(true)
+        .wait(Duration::from_millis(100));
+
+    let (stats, ids) = tokio::join!(replay.run(capture()), async {
+        let mut ids = Vec::new();
+        let mut buf = [0u8; 1500];
+        for _ in 0..3 {
+            let (len, _) = timeout(WAIT, server.recv_from(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            let msg = Message::parse(&buf[..len]).unwrap();
+            let options_header = msg.options_header.unwrap();
+            ids.push((
+                options_header.mobile_id_string().unwrap(),
+                msg.message_header.sequence_number.data(),
+            ));
+        }
+        ids
+    });
+
+    assert_eq!(
+        ids,
+        [
+            (String::from("4100000000"), 1),
+            (String::from("4100000001"), 1),
+            (String::from("4100000000"), 1),
+        ]
+    );
+    let stats = stats.unwrap();
+    assert_eq!(stats.expected, 3);
+    assert_eq!(stats.acked, 0);
+    assert_eq!(stats.missing, 3);
+}
@@ -0,0 +1,191 @@
+//! Virtual LMUs against servers over loopback.
+#![cfg(feature = "simulator")]
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use calamp_rs::message_header::{
+    MessageHeader, MessageType, SequenceNumber, ServiceType,
+};
+use calamp_rs::messages::ack_nak::{AckNak, AckNakMessage};
+use calamp_rs::messages::event_report::EventReport;
+use calamp_rs::options_header::OptionsHeader;
+use calamp_rs::simulator::{
+    ReportTemplate, Route, RouteSource, Simulator, Waypoint, STATUS_REQUEST,
+};
+use calamp_rs::Message;
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::timeout;
+
+mod common;
+
+use common::{spawn_server, WAIT};
+
+async fn recv(socket: &UdpSocket) -> (Vec<u8>, SocketAddr) {
+    let mut buf = [0u8; 1500];
+    let (len, src) = timeout(WAIT, socket.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    (buf[..len].to_vec(), src)
+}
+
+#[tokio::test]
+async fn test_devices_report_and_are_acked() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let server = spawn_server(move |src: SocketAddr, msg: Message| {
+        tx.send((src, msg)).unwrap()
+    })
+    .await;
+
+    let simulator = Simulator::new(server.addr)
+        .devices(50)
+        .report_interval(Duration::from_millis(200))
+        .template(ReportTemplate::new(-23.68, -46.74).speed(42.0));
+    let stats = simulator.run_for(Duration::from_secs(1)).await.unwrap();
+    server.stop().await;
+
+    let mut mobile_ids = HashSet::new();
+    let mut ports = HashSet::new();
+    while let Ok((src, msg)) = rx.try_recv() {
+        let options_header = msg.options_header.unwrap();
+        mobile_ids.insert(options_header.mobile_id_string().unwrap());
+        ports.insert(src.port());
+        assert_eq!(msg.msg.speed.raw(), 1167);
+    }
+    assert_eq!(mobile_ids.len(), 50);
+    assert!(mobile_ids.contains("4000000049"));
+    assert_eq!(ports.len(), 50);
+
+    assert!(stats.acked >= 50);
+    assert_eq!(stats.retransmissions, 0);
+    assert_eq!(stats.unacked, 0);
+}
+
+#[tokio::test]
+async fn test_unacked_report_is_retransmitted() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let simulator = Simulator::new(server.local_addr().unwrap())
+        .report_interval(Duration::from_secs(60))
+        .retry_interval(Duration::from_millis(100))
+        .max_attempts(3);
+
+    let (stats, sequence_numbers) =
+        tokio::join!(simulator.run_for(Duration::from_secs(1)), async {
+            let mut sequence_numbers = Vec::new();
+            for _ in 0..3 {
+                let (datagram, _) = recv(&server).await;
+                let (i, _) = OptionsHeader::parse(&datagram).unwrap();
+                let (_, header) = MessageHeader::parse(i).unwrap();
+                assert_eq!(header.service_type, ServiceType::Acknowledged);
+                sequence_numbers.push(header.sequence_number);
+            }
+            sequence_numbers
+        });
+    assert_eq!(sequence_numbers, [SequenceNumber::new(1); 3]);
+
+    let stats = stats.unwrap();
+    assert_eq!(stats.reports, 1);
+    assert_eq!(stats.retransmissions, 2);
+    assert_eq!(stats.acked, 0);
+    assert_eq!(stats.unacked, 1);
+}
+
+#[tokio::test]
+async fn test_status_request_is_acked_and_located() {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let simulator = Simulator::new(server.local_addr().unwrap())
+        .report_interval(Duration::from_secs(60))
+        .template(ReportTemplate::new(-23.68, -46.74));
+    let (stop, stopped) = oneshot::channel::<()>();
+
+    let (stats, _) = tokio::join!(
+        simulator.run_until(async {
+            let _ = stopped.await;
+        }),
+        async {
+            let (report, lmu) = recv(&server).await;
+            let mut reply = Vec::new();
+            let (i, options_header) = OptionsHeader::parse(&report).unwrap();
+            let (_, header) = MessageHeader::parse(i).unwrap();
+            AckNak::ack(MessageType::EventReport).encode_reply(
+                options_header.as_ref(),
+                &header,
+                &mut reply,
+            );
+            server.send_to(&reply, lmu).await.unwrap();
+
+            let mut request = Vec::new();
+            options_header.as_ref().unwrap().encode(&mut request);
+            MessageHeader {
+                service_type: ServiceType::Acknowledged,
+                message_type: MessageType::UnitRequest,
+                sequence_number: SequenceNumber::new(7),
+            }
+            .encode(&mut request);
+            request.extend_from_slice(&[STATUS_REQUEST, 0]);
+            server.send_to(&request, lmu).await.unwrap();
+
+            let (ack, _) = recv(&server).await;
+            let ack = AckNakMessage::parse(&ack).unwrap();
+            assert_eq!(ack.message_header.sequence_number.data(), 7);
+            assert_eq!(ack.ack_nak, AckNak::ack(MessageType::UnitRequest));
+            assert_eq!(
+                ack.options_header.unwrap().mobile_id_string().unwrap(),
+                "4000000000"
+            );
+
+            let (locate, _) = recv(&server).await;
+            let (i, _) = OptionsHeader::parse(&locate).unwrap();
+            let (i, header) = MessageHeader::parse(i).unwrap();
+            assert_eq!(header.message_type, MessageType::LocateReport);
+            let report = EventReport::parse(i).unwrap();
+            assert_eq!(report.latitude, -236800000);
+            stop.send(()).unwrap();
+        }
+    );
+
+    let stats = stats.unwrap();
+    assert_eq!(stats.acked, 1);
+    assert_eq!(stats.unit_requests, 1);
+    assert_eq!(stats.locate_reports, 1);
+}
+
+#[tokio::test]
+async fn test_devices_drive_route() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let server =
+        spawn_server(move |_: SocketAddr, msg: Message| tx.send(msg).unwrap())
+            .await;
+
+    // A kilometer East in a minute at 60 km/h, no time parked.
+    let route = Route::new(vec![
+        Waypoint::new(-23.55, -46.63),
+        Waypoint::new(-23.55, -46.6202),
+    ])
+    .unwrap();
+    let source = RouteSource::new(route)
+        .speed(60.0)
+        .dwell(Duration::ZERO)
+        .seed(3);
+    let simulator = Simulator::new(server.addr)
+        .devices(2)
+        .report_interval(Duration::from_millis(100))
+        .route(source);
+    simulator.run_for(Duration::from_millis(500)).await.unwrap();
+    server.stop().await;
+
+    let mut reports = 0;
+    while let Ok(msg) = rx.try_recv() {
+        let report = msg.msg;
+        assert!(report.inputs.ignition);
+        assert_eq!(report.heading.raw(), 90);
+        assert_eq!(report.speed.raw(), 1667);
+        assert_eq!(report.latitude, -235500000);
+        assert!((-466300000..-466200000).contains(&report.longitude));
+        reports += 1;
+    }
+    assert!(reports >= 4, "{} reports", reports);
+}
@@ -0,0 +1,118 @@
+//! Async UDP server over loopback.
+#![cfg(feature = "tokio")]
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use calamp_rs::message_header::{MessageHeader, MessageType, ServiceType};
+use calamp_rs::messages::ack_nak::AckNak;
+use calamp_rs::options_header::OptionsHeader;
+use calamp_rs::server::Handler;
+use calamp_rs::{Error, Message};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+mod common;
+
+use common::{spawn_server, TestServer, DATA, WAIT};
+
+#[derive(Debug)]
+enum Event {
+    Message(SocketAddr, Box<Message>),
+    Error(SocketAddr, Error),
+}
+
+struct Forward(mpsc::UnboundedSender<Event>);
+
+impl Handler for Forward {
+    fn on_message(&self, src: SocketAddr, msg: Message) {
+        self.0.send(Event::Message(src, Box::new(msg))).unwrap();
+    }
+
+    fn on_error(&self, src: SocketAddr, err: Error) {
+        self.0.send(Event::Error(src, err)).unwrap();
+    }
+}
+
+async fn start() -> (TestServer, mpsc::UnboundedReceiver<Event>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    (spawn_server(Forward(tx)).await, rx)
+}
+
+#[tokio::test]
+async fn test_message_is_acked_and_handled() {
+    let (server, mut events) = start().await;
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.send_to(&DATA, server.addr).await.unwrap();
+
+    let mut buf = [0u8; 1500];
+    let (len, _) = timeout(WAIT, client.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    let (i, options_header) = OptionsHeader::parse(&buf[..len]).unwrap();
+    let (i, header) = MessageHeader::parse(i).unwrap();
+    let (_, ack) = AckNak::parse(i).unwrap();
+    assert_eq!(
+        options_header.unwrap().mobile_id_string().unwrap(),
+        "4634663235"
+    );
+    assert_eq!(header.service_type, ServiceType::ResponseToAnAcknowledged);
+    assert_eq!(header.sequence_number.data(), 14982);
+    assert_eq!(ack, AckNak::ack(MessageType::EventReport));
+
+    match timeout(WAIT, events.recv()).await.unwrap().unwrap() {
+        Event::Message(src, msg) => {
+            assert_eq!(src, client.local_addr().unwrap());
+            assert_eq!(msg.message_header.sequence_number.data(), 14982);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_malformed_datagram_goes_to_on_error() {
+    let (server, mut events) = start().await;
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.send_to(&DATA[..20], server.addr).await.unwrap();
+    client.send_to(&DATA, server.addr).await.unwrap();
+
+    match timeout(WAIT, events.recv()).await.unwrap().unwrap() {
+        Event::Error(src, Error::Incomplete { .. }) => {
+            assert_eq!(src, client.local_addr().unwrap())
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    // The server keeps serving after a bad datagram.
+    match timeout(WAIT, events.recv()).await.unwrap().unwrap() {
+        Event::Message(..) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_unacknowledged_is_not_acked() {
+    let (server, mut events) = start().await;
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut data = DATA;
+    data[9] = 0x00;
+    client.send_to(&data, server.addr).await.unwrap();
+
+    assert!(matches!(
+        timeout(WAIT, events.recv()).await.unwrap().unwrap(),
+        Event::Message(..)
+    ));
+    let mut buf = [0u8; 1500];
+    assert!(
+        timeout(Duration::from_millis(200), client.recv_from(&mut buf))
+            .await
+            .is_err()
+    );
+
+    server.stop().await;
+}
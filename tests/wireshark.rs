@@ -0,0 +1,47 @@
+//! The generated Wireshark dissector against its checked in copy.
+//!
+//! A change of the field layouts changes the dissector: review the new Lua
+//! and run the tests with `CALAMP_BLESS=1` to update the copy.
+
+use std::env;
+use std::fs;
+
+use calamp_rs::wireshark::lua_dissector;
+
+const GOLDEN: &str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/lmdirect.lua");
+
+#[test]
+fn test_lua_dissector_golden() {
+    let lua = lua_dissector(&[20500]);
+    if env::var_os("CALAMP_BLESS").is_some() {
+        fs::write(GOLDEN, &lua).unwrap();
+    }
+    let golden = fs::read_to_string(GOLDEN).unwrap();
+
+    // Point at the first difference rather than printing both files.
+    let diff = lua
+        .lines()
+        .zip(golden.lines())
+        .enumerate()
+        .find(|(_, (new, old))| new != old);
+    if let Some((n, (new, old))) = diff {
+        panic!("line {}:\n  new: {}\n  old: {}", n + 1, new, old);
+    }
+    assert_eq!(lua.lines().count(), golden.lines().count());
+    assert_eq!(lua, golden);
+}
+
+#[test]
+fn test_lua_dissector_ports() {
+    let lua = lua_dissector(&[20500, 20510]);
+    let tail: Vec<&str> = lua.lines().rev().take(3).collect();
+    assert_eq!(
+        tail,
+        [
+            "udp_port:add(20510, lmdirect)",
+            "udp_port:add(20500, lmdirect)",
+            "local udp_port = DissectorTable.get(\"udp.port\")",
+        ]
+    );
+}